@@ -0,0 +1,32 @@
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+
+// must match TEXTURE_LAYER_STRIDE in meshing_chunk.rs
+const TEXTURE_LAYER_STRIDE: f32 = 64.0;
+
+@group(1) @binding(100) var voxel_textures: texture_2d_array<f32>;
+@group(1) @binding(101) var voxel_textures_sampler: sampler;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    // the mesher offsets each quad's u coordinate by its layer. Quads are at
+    // most half a stride wide, so shifting by a quarter stride keeps
+    // interpolation error at their edges from changing the layer.
+    let layer = i32(floor(in.uv.x / TEXTURE_LAYER_STRIDE + 0.25));
+    // the offset is a whole number of repeats, so the sampler ignores it
+    pbr_input.material.base_color *= textureSample(voxel_textures, voxel_textures_sampler, in.uv, layer);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,130 @@
+//! Exports the meshes of a world's chunks to a glTF binary or OBJ file,
+//! without opening a window:
+//!
+//! export_chunks <output.glb|output.obj> (--chunks | --box) <x1> <y1> <z1> <x2> <y2> <z2>
+//!     [--seed <seed>] [--save <dir>]
+//!
+//! `--chunks` takes two corners in chunk coordinates, and `--box` two
+//! corners in voxel coordinates, inclusive. Boxes may hold at most
+//! `MAX_EXPORT_CHUNKS` chunks. Chunks are loaded from the world
+//! saved in `--save` where they were saved, and generated from the save's
+//! seed, or `--seed`, otherwise.
+use bevy::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use voxelite::material::VoxelMaterialRegistry;
+use voxelite::material_definition::read_material_definitions;
+use voxelite::mesh_export::{chunks_in_box, ExportFormat, MeshExport, MAX_EXPORT_CHUNKS};
+use voxelite::save::WorldSave;
+use voxelite::storage::chunk::ChunkPosition;
+use voxelite::storage::map::VoxelMapSettings;
+use voxelite::terrain::LayeredTerrain;
+use voxelite::world::{World, WorldPlugin};
+
+const USAGE: &str = "Usage: export_chunks <output.glb|output.obj> (--chunks | --box) \
+<x1> <y1> <z1> <x2> <y2> <z2> [--seed <seed>] [--save <dir>]";
+
+struct Options {
+    output: PathBuf,
+    // None if the box holds too many chunks to export
+    chunks: Option<Vec<ChunkPosition>>,
+    seed: u64,
+    save_dir: Option<PathBuf>,
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let (output, rest) = args.split_first()?;
+    let corner = |values: &[String]| -> Option<IVec3> {
+        let [x, y, z] = values else { return None };
+        Some(IVec3::new(
+            x.parse().ok()?,
+            y.parse().ok()?,
+            z.parse().ok()?,
+        ))
+    };
+    let (mode, rest) = rest.split_first()?;
+    let first = corner(rest.get(0..3)?)?;
+    let second = corner(rest.get(3..6)?)?;
+    let chunks = match mode.as_str() {
+        "--box" => chunks_in_box(first, second),
+        "--chunks" => {
+            let (min, max) = (first.min(second), first.max(second));
+            let size = IVec3::splat(voxelite::chunk::CHUNK_DIM as i32);
+            // corners past the voxel coordinates' range hold too many chunks
+            // anyway
+            chunks_in_box(min.saturating_mul(size), max.saturating_mul(size))
+        }
+        _ => return None,
+    };
+    let mut options = Options {
+        output: PathBuf::from(output),
+        chunks,
+        seed: WorldPlugin::default().seed,
+        save_dir: None,
+    };
+    let mut flags = rest[6..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next()?;
+        match flag.as_str() {
+            "--seed" => options.seed = value.parse().ok()?,
+            "--save" => options.save_dir = Some(PathBuf::from(value)),
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(options) = parse_options(&args) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    if ExportFormat::from_path(&options.output).is_none() {
+        eprintln!("Meshes export to .glb or .obj files");
+        return ExitCode::FAILURE;
+    }
+    let Some(chunks) = options.chunks else {
+        eprintln!(
+            "At most {} chunks can be exported at once",
+            MAX_EXPORT_CHUNKS
+        );
+        return ExitCode::FAILURE;
+    };
+
+    let mut registry = VoxelMaterialRegistry::default();
+    let materials = Path::new("assets/materials");
+    if materials.is_dir() {
+        if let Err(error) = read_material_definitions(materials, &mut registry) {
+            eprintln!("Can't read material definitions: {}", error);
+            return ExitCode::FAILURE;
+        }
+    }
+    let save = match &options.save_dir {
+        Some(dir) => match WorldSave::open(dir, options.seed) {
+            Ok(save) => Some(save),
+            Err(error) => {
+                eprintln!("Can't open world save in {:?}: {}", dir, error);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let seed = save.as_ref().map_or(options.seed, |save| save.seed());
+    let mut world = World::new(VoxelMapSettings::default(), LayeredTerrain::new(seed));
+    if let Some(save) = save {
+        if let Err(error) = save.reserve_materials(&mut registry) {
+            eprintln!("Can't load saved materials: {}", error);
+            return ExitCode::FAILURE;
+        }
+        world = world.with_save(save);
+    }
+
+    let export = MeshExport::from_world(&mut world, &chunks, &registry);
+    if let Err(error) = export.save(&options.output) {
+        eprintln!("Can't write {:?}: {}", options.output, error);
+        return ExitCode::FAILURE;
+    }
+    println!("Exported {} chunks to {:?}", chunks.len(), options.output);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,89 @@
+//! Runs a world without a window or renderer, for hosting it on machines
+//! without a GPU:
+//!
+//! server [--save <dir>] [--seed <seed>] [--spawn <x> <y> <z>]
+//!
+//! Chunks around the spawn point are generated, lit and saved as in the
+//! game, and commands are read from stdin. The world is saved to
+//! `saves/world` unless `--save` gives another directory; `quit` saves it
+//! before exiting.
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+use voxelite::world::{ChunkLoader, WorldPlugin};
+use voxelite::{commands, material};
+
+const USAGE: &str = "Usage: server [--save <dir>] [--seed <seed>] [--spawn <x> <y> <z>]";
+
+/// How often the world is updated.
+const TICKS_PER_SECOND: f64 = 30.;
+
+struct Options {
+    save_dir: PathBuf,
+    seed: u64,
+    spawn: Vec3,
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        save_dir: PathBuf::from("saves/world"),
+        seed: WorldPlugin::default().seed,
+        spawn: Vec3::ZERO,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--save" => options.save_dir = parse_next(&mut args)?,
+            "--seed" => options.seed = parse_next(&mut args)?,
+            "--spawn" => {
+                options.spawn = Vec3::new(
+                    parse_next(&mut args)?,
+                    parse_next(&mut args)?,
+                    parse_next(&mut args)?,
+                )
+            }
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn parse_next<'a, T: FromStr>(args: &mut impl Iterator<Item = &'a String>) -> Option<T> {
+    args.next()?.parse().ok()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(options) = parse_options(&args) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let spawn = options.spawn;
+    App::new()
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1. / TICKS_PER_SECOND,
+            ))),
+            LogPlugin::default(),
+            AssetPlugin::default(),
+            commands::CommandPlugin,
+            material::VoxelMaterialPlugin,
+            WorldPlugin {
+                seed: options.seed,
+                save_dir: Some(options.save_dir),
+                meshing: false,
+                ..default()
+            },
+        ))
+        .add_systems(Startup, move |mut commands: Commands| {
+            commands.spawn((Transform::from_translation(spawn), ChunkLoader));
+            info!("Server started, loading chunks around {}", spawn);
+        })
+        .run();
+    ExitCode::SUCCESS
+}
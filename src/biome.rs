@@ -0,0 +1,207 @@
+use crate::material::{DIRT, GRASS, SAND, SNOW, STONE};
+use crate::noise::Perlin;
+use crate::structure::Decoration;
+use bevy::prelude::*;
+
+/// The characteristics of one kind of terrain. Each biome sits at a point in
+/// climate space (temperature and humidity, both from -1 to 1), and columns
+/// of the world take on the biomes whose climates are closest to their own.
+#[derive(Clone, Debug)]
+pub struct Biome {
+    pub name: &'static str,
+    pub temperature: f32,
+    pub humidity: f32,
+    /// material of the top voxel of each column
+    pub surface: u16,
+    /// material of the voxels below the surface, down to `subsurface_depth`,
+    /// below which is stone
+    pub subsurface: u16,
+    pub subsurface_depth: f32,
+    pub base_height: f32,
+    pub height_amplitude: f32,
+    /// chance of a decoration (such as a tree) being placed on each column
+    pub decoration_density: f32,
+    /// the decorations placed on the biome's surface, each equally likely
+    pub decorations: Vec<Decoration>,
+}
+
+impl Biome {
+    pub fn defaults() -> Vec<Biome> {
+        vec![
+            Biome {
+                name: "Plains",
+                temperature: 0.,
+                humidity: 0.,
+                surface: GRASS,
+                subsurface: DIRT,
+                subsurface_depth: 4.,
+                base_height: 12.,
+                height_amplitude: 10.,
+                decoration_density: 0.004,
+                decorations: vec![Decoration::Tree, Decoration::Tree, Decoration::Boulder],
+            },
+            Biome {
+                name: "Forest",
+                temperature: 0.2,
+                humidity: 0.6,
+                surface: GRASS,
+                subsurface: DIRT,
+                subsurface_depth: 5.,
+                base_height: 18.,
+                height_amplitude: 18.,
+                decoration_density: 0.03,
+                decorations: vec![Decoration::Tree],
+            },
+            Biome {
+                name: "Desert",
+                temperature: 0.8,
+                humidity: -0.6,
+                surface: SAND,
+                subsurface: SAND,
+                subsurface_depth: 6.,
+                base_height: 10.,
+                height_amplitude: 6.,
+                decoration_density: 0.001,
+                decorations: vec![Decoration::Boulder, Decoration::Ruin],
+            },
+            Biome {
+                name: "Mountains",
+                temperature: -0.3,
+                humidity: -0.3,
+                surface: STONE,
+                subsurface: STONE,
+                subsurface_depth: 1.,
+                base_height: 48.,
+                height_amplitude: 56.,
+                decoration_density: 0.002,
+                decorations: vec![Decoration::Boulder],
+            },
+            Biome {
+                name: "Tundra",
+                temperature: -0.8,
+                humidity: 0.3,
+                surface: SNOW,
+                subsurface: DIRT,
+                subsurface_depth: 3.,
+                base_height: 16.,
+                height_amplitude: 12.,
+                decoration_density: 0.002,
+                decorations: vec![Decoration::Tree, Decoration::Boulder],
+            },
+        ]
+    }
+}
+
+/// The biome of one column of the world, along with the height profile and
+/// decoration density blended from every nearby biome.
+pub struct BiomeColumn<'a> {
+    pub biome: &'a Biome,
+    pub base_height: f32,
+    pub height_amplitude: f32,
+    pub decoration_density: f32,
+}
+
+/// Picks biomes for columns of the world from temperature and humidity
+/// noise maps.
+pub struct BiomeMap {
+    temperature_noise: Perlin,
+    humidity_noise: Perlin,
+    pub biomes: Vec<Biome>,
+    /// distance over which the climate changes appreciably, in voxels
+    pub scale: f32,
+    /// distance in climate space over which neighbouring biomes blend; the
+    /// larger it is, the smoother the transitions between biomes
+    pub blend: f32,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u64, biomes: Vec<Biome>) -> Self {
+        assert!(!biomes.is_empty(), "a biome map needs at least one biome");
+        Self {
+            temperature_noise: Perlin::new(seed),
+            humidity_noise: Perlin::new(seed.wrapping_add(1)),
+            biomes,
+            scale: 640.,
+            blend: 0.15,
+        }
+    }
+
+    /// The temperature and humidity of the column, each roughly -1..1.
+    pub fn climate(&self, x: f32, z: f32) -> Vec2 {
+        let point = Vec2::new(x, z) / self.scale;
+        // fractal noise rarely strays far from zero, so it is stretched to
+        // reach the biomes at the edges of climate space
+        let stretch = |value: f32| (value * 2.).clamp(-1., 1.);
+        Vec2::new(
+            stretch(self.temperature_noise.fbm2(point, 3)),
+            stretch(self.humidity_noise.fbm2(point, 3)),
+        )
+    }
+
+    pub fn column(&self, x: f32, z: f32) -> BiomeColumn<'_> {
+        let climate = self.climate(x, z);
+        let distances = self
+            .biomes
+            .iter()
+            .map(|biome| climate.distance(Vec2::new(biome.temperature, biome.humidity)));
+        let nearest = distances.clone().fold(f32::INFINITY, f32::min);
+
+        // weights fall off with distance beyond the nearest biome, so a column
+        // deep inside one biome takes on that biome alone
+        let mut total = 0.;
+        let mut column = BiomeColumn {
+            biome: &self.biomes[0],
+            base_height: 0.,
+            height_amplitude: 0.,
+            decoration_density: 0.,
+        };
+        for (biome, distance) in self.biomes.iter().zip(distances) {
+            if distance == nearest {
+                column.biome = biome;
+            }
+            let weight = (-((distance - nearest) / self.blend).powi(2)).exp();
+            total += weight;
+            column.base_height += biome.base_height * weight;
+            column.height_amplitude += biome.height_amplitude * weight;
+            column.decoration_density += biome.decoration_density * weight;
+        }
+        column.base_height /= total;
+        column.height_amplitude /= total;
+        column.decoration_density /= total;
+        column
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::biome::{Biome, BiomeMap};
+
+    #[test]
+    fn every_biome_appears() {
+        let map = BiomeMap::new(3, Biome::defaults());
+        let mut seen = vec![false; map.biomes.len()];
+        for x in -60..60 {
+            for z in -60..60 {
+                let column = map.column(x as f32 * 64., z as f32 * 64.);
+                let index = map
+                    .biomes
+                    .iter()
+                    .position(|biome| biome.name == column.biome.name)
+                    .unwrap();
+                seen[index] = true;
+            }
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn heights_blend_smoothly() {
+        let map = BiomeMap::new(8, Biome::defaults());
+        let mut previous = map.column(0., 0.).base_height;
+        for x in 1..4000 {
+            let height = map.column(x as f32, 0.).base_height;
+            assert!((height - previous).abs() < 1.);
+            previous = height;
+        }
+    }
+}
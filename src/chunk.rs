@@ -1,28 +1,93 @@
-use crate::voxel::{Voxel, EMPTY, FULL};
+use crate::storage::chunk::{Chunk as RowStorage, ChunkPosition};
+use crate::storage::ChunkStorage;
+use crate::terrain::TerrainGenerator;
+use crate::voxel::{Voxel, EMPTY};
 use bevy::prelude::*;
-use block_mesh::*;
 use ndshape::{ConstPow2Shape3u32, ConstShape};
-use rand::prelude::*;
 
+/// Chunks are stored with row compression unless another
+/// `storage::ChunkStorage` backend is chosen.
 #[derive(Clone)]
-pub struct Chunk {
-    pub voxel_data: [Voxel; ChunkShape::USIZE],
+pub struct Chunk<S: ChunkStorage = RowStorage> {
+    voxel_data: S,
 }
 
 pub const CHUNK_DIM: u32 = 32;
 pub const CHUNK_EXP: u32 = 5;
 pub type ChunkShape = ConstPow2Shape3u32<CHUNK_EXP, CHUNK_EXP, CHUNK_EXP>;
 
-impl Chunk {
-    pub fn generate(_chunk_pos: UVec3, _generator: impl Fn(UVec3) -> Voxel) -> Self {
-        let mut voxel_data = [EMPTY; ChunkShape::USIZE];
-        for index in 0..ChunkShape::SIZE {
-            let coordinates = ChunkShape::delinearize(index);
-            let mut rng = thread_rng();
-            if coordinates[1] < (6 + rng.gen_range(0..3)) {
-                voxel_data[index as usize] = FULL;
-            }
+/// Splits a voxel's world coordinates into the chunk holding it and its
+/// position within that chunk.
+pub fn voxel_chunk(position: IVec3) -> (ChunkPosition, [u32; 3]) {
+    let size = IVec3::splat(CHUNK_DIM as i32);
+    let chunk_pos = position.div_euclid(size).into();
+    (chunk_pos, position.rem_euclid(size).as_uvec3().to_array())
+}
+
+impl<S: ChunkStorage> Chunk<S> {
+    /// Generates the chunk at the given position, evaluating the generator
+    /// over the world position of each column of voxels.
+    pub fn generate(chunk_pos: ChunkPosition, generator: &dyn TerrainGenerator) -> Self {
+        let origin = chunk_pos.as_ivec3() * CHUNK_DIM as i32;
+        let dim = CHUNK_DIM as usize;
+        // columns are laid out one after another, x then z
+        let mut columns = vec![EMPTY; dim * dim * dim];
+        for (index, column) in columns.chunks_mut(dim).enumerate() {
+            let (x, z) = ((index / dim) as i32, (index % dim) as i32);
+            generator.column(origin.x + x, origin.z + z, origin.y, column);
         }
+        Self::from_voxels(|[x, y, z]| columns[(x as usize * dim + z as usize) * dim + y as usize])
+    }
+
+    /// Builds a chunk from the voxel at each position within it.
+    pub fn from_voxels(mut voxel: impl FnMut([u32; 3]) -> Voxel) -> Self {
+        let voxel_data = S::from_fn(|x, y, z| voxel([x as u32, y as u32, z as u32]).into());
         Self { voxel_data }
     }
+
+    pub fn get(&self, [x, y, z]: [u32; 3]) -> Voxel {
+        self.voxel_data
+            .get(x as usize, y as usize, z as usize)
+            .into()
+    }
+
+    pub fn set(&mut self, [x, y, z]: [u32; 3], voxel: Voxel) {
+        self.voxel_data
+            .put(x as usize, y as usize, z as usize, voxel.into());
+    }
+
+    /// The second stage of generation, after `generate`: places every
+    /// structure that reaches into the chunk. Structures are gathered from
+    /// the chunk and all of its neighbours and placed in a fixed order,
+    /// rather than written into whichever neighbours happen to be loaded, so
+    /// the chunk comes out the same no matter which order chunks are
+    /// generated in.
+    pub fn decorate(&mut self, chunk_pos: ChunkPosition, generator: &dyn TerrainGenerator) {
+        let origin = chunk_pos.as_ivec3() * CHUNK_DIM as i32;
+        let size = IVec3::splat(CHUNK_DIM as i32);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let origin_chunk = chunk_pos + IVec3::new(x, y, z);
+                    for placed in generator.structures(origin_chunk) {
+                        for voxel in placed.structure.voxels() {
+                            let local = placed.origin + voxel.offset - origin;
+                            if local.cmplt(IVec3::ZERO).any() || local.cmpge(size).any() {
+                                continue;
+                            }
+                            let local = local.as_uvec3().to_array();
+                            if voxel.replace || self.get(local) == EMPTY {
+                                self.set(local, voxel.voxel);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn contains_material(&self, material: u16) -> bool {
+        (0..ChunkShape::SIZE)
+            .any(|index| self.get(ChunkShape::delinearize(index)).material == material)
+    }
 }
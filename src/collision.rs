@@ -0,0 +1,219 @@
+use crate::world::World;
+use bevy::prelude::*;
+use block_mesh::{Voxel as MeshableVoxel, VoxelVisibility};
+
+// keeps boxes resting exactly on a voxel face from counting as inside the
+// voxel
+const EPSILON: f32 = 1e-4;
+
+/// An axis-aligned box in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn translated(&self, offset: Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Whether the box overlaps the voxel cell, rather than only touching it.
+    pub fn overlaps_cell(&self, cell: IVec3) -> bool {
+        (0..3).all(|axis| self.cells(axis).contains(&cell[axis]))
+    }
+
+    /// The range of cells the box covers along the axis, ignoring cells it
+    /// only touches.
+    fn cells(&self, axis: usize) -> std::ops::RangeInclusive<i32> {
+        (self.min[axis] + EPSILON).floor() as i32..=(self.max[axis] - EPSILON).floor() as i32
+    }
+}
+
+/// What happened when moving a box through the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Movement {
+    pub aabb: Aabb,
+    /// the axes along which the box was stopped by a voxel
+    pub blocked: BVec3,
+    /// whether the box was stopped while moving down, so rests on a voxel
+    pub grounded: bool,
+}
+
+/// Whether the voxel at the given position stops boxes. Every voxel but an
+/// empty one is solid, translucent ones included: glass and leaves should
+/// stop players, and there is no swimming through water yet. Voxels in
+/// chunks that aren't loaded count as solid, so nothing falls into unloaded
+/// terrain.
+pub fn is_solid(world: &World, position: IVec3) -> bool {
+    world
+        .get_voxel(position)
+        .is_none_or(|voxel| voxel.get_visibility() != VoxelVisibility::Empty)
+}
+
+/// Moves the box along one axis, stopping it against the first layer of
+/// solid voxels its leading face would pass into. Returns whether it was
+/// stopped.
+fn sweep_axis(world: &World, aabb: &mut Aabb, axis: usize, distance: f32) -> bool {
+    if distance == 0. {
+        return false;
+    }
+    let [first_other, second_other] = [(axis + 1) % 3, (axis + 2) % 3];
+    let layer_is_solid = |layer: i32| {
+        aabb.cells(first_other).any(|a| {
+            aabb.cells(second_other).any(|b| {
+                let mut position = IVec3::ZERO;
+                position[axis] = layer;
+                position[first_other] = a;
+                position[second_other] = b;
+                is_solid(world, position)
+            })
+        })
+    };
+
+    let mut offset = Vec3::ZERO;
+    if distance > 0. {
+        let face = aabb.max[axis];
+        let first = (face - EPSILON).ceil() as i32;
+        let last = (face + distance - EPSILON).ceil() as i32 - 1;
+        offset[axis] = match (first..=last).find(|layer| layer_is_solid(*layer)) {
+            Some(layer) => layer as f32 - face,
+            None => distance,
+        };
+    } else {
+        let face = aabb.min[axis];
+        let first = (face + EPSILON).floor() as i32 - 1;
+        let last = (face + distance + EPSILON).floor() as i32;
+        offset[axis] = match (last..=first).rev().find(|layer| layer_is_solid(*layer)) {
+            Some(layer) => layer as f32 + 1. - face,
+            None => distance,
+        };
+    }
+    // a box already overlapping a voxel is never pushed backwards
+    if offset[axis].signum() != distance.signum() {
+        offset[axis] = 0.;
+    }
+    *aabb = aabb.translated(offset);
+    offset[axis] != distance
+}
+
+/// Moves the box through the world by `motion`, one axis at a time (up and
+/// down first), stopping it against solid voxels. Boxes that are grounded
+/// and walk into a ledge no taller than `step_height` are lifted onto it.
+pub fn move_aabb(world: &World, aabb: Aabb, motion: Vec3, step_height: f32) -> Movement {
+    let mut moved = aabb;
+    let mut blocked = BVec3::FALSE;
+    blocked.y = sweep_axis(world, &mut moved, 1, motion.y);
+    let grounded = blocked.y && motion.y < 0.;
+    blocked.x = sweep_axis(world, &mut moved, 0, motion.x);
+    blocked.z = sweep_axis(world, &mut moved, 2, motion.z);
+
+    if grounded && step_height > 0. && (blocked.x || blocked.z) {
+        // try the horizontal motion again from the top of the step, then
+        // settle back down onto whatever is there
+        let mut stepped = aabb.translated(Vec3::Y * (moved.min.y - aabb.min.y));
+        let lifted = !sweep_axis(world, &mut stepped, 1, step_height);
+        let stepped_x = sweep_axis(world, &mut stepped, 0, motion.x);
+        let stepped_z = sweep_axis(world, &mut stepped, 2, motion.z);
+        sweep_axis(world, &mut stepped, 1, -step_height);
+        let progress = |moved: Aabb| (moved.min - aabb.min).xz().length_squared();
+        if lifted && progress(stepped) > progress(moved) + EPSILON {
+            return Movement {
+                aabb: stepped,
+                blocked: BVec3::new(stepped_x, blocked.y, stepped_z),
+                grounded,
+            };
+        }
+    }
+    Movement {
+        aabb: moved,
+        blocked,
+        grounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collision::{is_solid, move_aabb, Aabb};
+    use crate::material::STONE;
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::World;
+    use bevy::prelude::*;
+
+    // ground below y = 0, with a single block step at x = 3 and a wall two
+    // blocks tall at z = 3
+    fn test_world() -> World {
+        let terrain = |position: IVec3| {
+            let step = position.x == 3 && position.y == 0;
+            let wall = position.z == 3 && (0..2).contains(&position.y);
+            if position.y < 0 || step || wall {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::new(VoxelMapSettings::default(), terrain);
+        for y in -1..=0 {
+            world.load_chunk(ChunkPosition::new(0, y, 0));
+        }
+        world
+    }
+
+    fn player_at(feet: Vec3) -> Aabb {
+        Aabb::new(
+            feet - Vec3::new(0.3, 0., 0.3),
+            feet + Vec3::new(0.3, 1.8, 0.3),
+        )
+    }
+
+    #[test]
+    fn falling_boxes_land_on_the_ground() {
+        let world = test_world();
+        let movement = move_aabb(
+            &world,
+            player_at(Vec3::new(1.5, 2.25, 1.5)),
+            -Vec3::Y * 10.,
+            0.,
+        );
+        assert_eq!(movement.aabb.min.y, 0.);
+        assert!(movement.grounded);
+        // resting on the ground isn't a collision with it
+        let movement = move_aabb(&world, movement.aabb, Vec3::X * 0.5, 0.);
+        assert!(!movement.blocked.x);
+        assert!((movement.aabb.min.x - 1.7).abs() < 1e-5);
+    }
+
+    #[test]
+    fn walls_stop_boxes_and_steps_lift_them() {
+        let world = test_world();
+        let standing = player_at(Vec3::new(1.5, 0., 1.5));
+        let gravity = -Vec3::Y * 0.1;
+
+        let blocked = move_aabb(&world, standing, Vec3::X * 2. + gravity, 0.);
+        assert!(blocked.blocked.x);
+        assert!((blocked.aabb.max.x - 3.).abs() < 1e-5);
+
+        let stepped = move_aabb(&world, standing, Vec3::X * 2. + gravity, 1.);
+        assert!(!stepped.blocked.x);
+        assert_eq!(stepped.aabb.min.y, 1.);
+        assert!((stepped.aabb.min.x - 3.2).abs() < 1e-5);
+
+        // the wall is taller than a step
+        let walled = move_aabb(&world, standing, Vec3::Z * 2. + gravity, 1.);
+        assert!(walled.blocked.z);
+        assert_eq!(walled.aabb.min.y, 0.);
+    }
+
+    #[test]
+    fn unloaded_chunks_are_solid() {
+        let world = test_world();
+        assert!(is_solid(&world, IVec3::new(40, 5, 0)));
+        assert!(!is_solid(&world, IVec3::new(1, 5, 0)));
+    }
+}
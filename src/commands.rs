@@ -1,8 +1,15 @@
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
 use bevy::{
   prelude::*,
   app::AppExit
 };
 
+use crate::material::VoxelMaterialRegistry;
+use crate::vox::{load_vox, save_vox, VoxelVolume};
+use crate::world::World;
+
 
 /*
  * CommandId
@@ -10,10 +17,12 @@ use bevy::{
  * A list of all the different types of commands that
  * can be issued by an admin.
  */
-#[derive(PartialEq, Eq)]
-enum CommandId {
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandId {
   Quit,
   Status,
+  Import,
+  Export,
   Unknown
 }
 
@@ -27,7 +36,8 @@ enum CommandId {
  * arguments, the parsing system will send those
  * as well.
  */
-struct CommandEvent {
+#[derive(Event, Debug)]
+pub struct CommandEvent {
   pub id: CommandId,
   pub argc: u8,
   pub argv: Vec<String>
@@ -45,48 +55,98 @@ struct CommandEvent {
  * it will send an event which an appropriate
  * responder will handle.
  */
-fn parse_command(
-  command: String,
-  mut event_writer: EventWriter<CommandEvent>
+pub fn parse_command(
+  command: &str,
+  event_writer: &mut EventWriter<CommandEvent>
 ) {
-  let id = command.trim().to_lowercase();
-
-  match id.as_str() {
-    "quit" => {
-      event_writer.send(CommandEvent {
-        id: CommandId::Quit,
-        argc: 0,
-        argv: vec![]
-      });
-    },
-    "status" => {
-      event_writer.send(CommandEvent {
-        id: CommandId::Status,
-        argc: 0,
-        argv: vec![]
-      });
-    },
+  event_writer.send(parse(command));
+}
+
+
+/*
+ * parse
+ *
+ * Splits a command into its id and the arguments
+ * that follow it, separated by whitespace. Unknown
+ * commands keep their whole text as their only
+ * argument.
+ */
+fn parse(command: &str) -> CommandEvent {
+  let mut words = command.split_whitespace();
+  let name = words.next().unwrap_or_default().to_lowercase();
+  let argv: Vec<String> = words.map(str::to_owned).collect();
+
+  let id = match name.as_str() {
+    "quit" => CommandId::Quit,
+    "status" => CommandId::Status,
+    "import" => CommandId::Import,
+    "export" => CommandId::Export,
     _ => {
-      event_writer.send(CommandEvent {
+      return CommandEvent {
         id: CommandId::Unknown,
-        argc: 0,
-        argv: vec![id]
-      });
+        argc: 1,
+        argv: vec![command.trim().to_owned()]
+      };
     }
+  };
+  CommandEvent {
+    id,
+    argc: argv.len() as u8,
+    argv
   }
 }
 
 
+/*
+ * StandardInput
+ *
+ * Lines read from stdin by a background thread, so
+ * that waiting for input never blocks the app.
+ */
+#[derive(Resource)]
+struct StandardInput(Mutex<Receiver<String>>);
+
+fn spawn_standard_input_reader() -> StandardInput {
+  let (sender, receiver) = channel();
+  std::thread::spawn(move || {
+    for line in std::io::stdin().lines() {
+      let Ok(line) = line else { break };
+      if sender.send(line).is_err() {
+        break;
+      }
+    }
+  });
+  StandardInput(Mutex::new(receiver))
+}
+
+
 /*
  * read_standard_input
  *
- * A bevy system designed to simply read from stdin
- * and pass the result to parse_command.
+ * A bevy system which passes every line read from
+ * stdin since it last ran to parse_command.
  */
-fn read_standard_input(event_writer: EventWriter<CommandEvent>) {
-  let mut command = String::new();
-  std::io::stdin().read_line(&mut command).unwrap();
-  parse_command(command, event_writer);
+fn read_standard_input(
+  input: Res<StandardInput>,
+  mut event_writer: EventWriter<CommandEvent>
+) {
+  let receiver = input.0.lock().unwrap();
+  for command in receiver.try_iter() {
+    if !command.trim().is_empty() {
+      parse_command(&command, &mut event_writer);
+    }
+  }
+}
+
+
+/*
+ * parse_position
+ *
+ * Reads three whole numbers as world coordinates.
+ */
+fn parse_position(args: &[String]) -> Option<IVec3> {
+  let [x, y, z] = args else { return None };
+  Some(IVec3::new(x.parse().ok()?, y.parse().ok()?, z.parse().ok()?))
 }
 
 
@@ -100,7 +160,7 @@ fn command_quit(
   mut command_reader: EventReader<CommandEvent>,
   mut exit_writer: EventWriter<AppExit>
 ) {
-  for command in command_reader.iter() {
+  for command in command_reader.read() {
     if command.id == CommandId::Quit {
       info!("Now exiting...");
       exit_writer.send(AppExit);
@@ -119,7 +179,7 @@ fn command_quit(
 fn command_unknown(
   mut command_reader: EventReader<CommandEvent>
 ) {
-  for command in command_reader.iter() {
+  for command in command_reader.read() {
     if command.id == CommandId::Unknown {
       error!("Unknown command: {}", command.argv[0]);
     }
@@ -136,36 +196,74 @@ fn command_unknown(
 fn command_status (
   mut command_reader: EventReader<CommandEvent>
 ) {
-  for command in command_reader.iter() {
+  for command in command_reader.read() {
     if command.id == CommandId::Status {
       info!("Server is healthy.");
     }
   }
 }
 
-struct SpamTriggerState {
-  trigger_timer: Timer
-}
 
-impl Default for SpamTriggerState {
-  fn default() -> Self {
-    SpamTriggerState {
-      trigger_timer: Timer::from_seconds(1.0, true)
+/*
+ * command_import
+ *
+ * Responds to command events which have the Import id,
+ * placing the models of a MagicaVoxel .vox file in the
+ * world with their first corner at the given position:
+ * import <file.vox> <x> <y> <z>
+ */
+fn command_import(
+  mut command_reader: EventReader<CommandEvent>,
+  registry: Res<VoxelMaterialRegistry>,
+  mut world: ResMut<World>
+) {
+  for command in command_reader.read() {
+    if command.id == CommandId::Import {
+      let (Some(path), Some(origin)) = (
+        command.argv.first(),
+        parse_position(command.argv.get(1..).unwrap_or_default())
+      ) else {
+        error!("Usage: import <file.vox> <x> <y> <z>");
+        continue;
+      };
+      match load_vox(path, &registry) {
+        Ok(volume) => {
+          let placed = volume.place_in(&mut world, origin);
+          info!("Placed {} voxels from {}", placed, path);
+        },
+        Err(error) => error!("Can't import {}: {}", path, error)
+      }
     }
   }
 }
 
-fn spammer(
-  time: Res<Time>,
-  mut state: ResMut<SpamTriggerState>,
-  mut command_writer: EventWriter<CommandEvent>
+
+/*
+ * command_export
+ *
+ * Responds to command events which have the Export id,
+ * saving the voxels of the world between two corners,
+ * inclusive, as a MagicaVoxel .vox file:
+ * export <file.vox> <x1> <y1> <z1> <x2> <y2> <z2>
+ */
+fn command_export(
+  mut command_reader: EventReader<CommandEvent>,
+  registry: Res<VoxelMaterialRegistry>,
+  world: Res<World>
 ) {
-  if state.trigger_timer.tick(time.delta()).finished() {
-    command_writer.send(CommandEvent {
-      id: CommandId::Status,
-      argc: 0,
-      argv: vec![]
-    });
+  for command in command_reader.read() {
+    if command.id == CommandId::Export {
+      let corner = |start: usize| parse_position(command.argv.get(start..start + 3)?);
+      let (Some(path), Some(first), Some(second)) = (command.argv.first(), corner(1), corner(4)) else {
+        error!("Usage: export <file.vox> <x1> <y1> <z1> <x2> <y2> <z2>");
+        continue;
+      };
+      let volume = VoxelVolume::from_world(&world, first.min(second), first.max(second));
+      match volume.and_then(|volume| save_vox(path, &volume, &registry).map(|()| volume.size())) {
+        Ok(size) => info!("Exported {} to {}", size, path),
+        Err(error) => error!("Can't export {}: {}", path, error)
+      }
+    }
   }
 }
 
@@ -174,17 +272,35 @@ fn spammer(
  * CommandPlugin
  *
  * Registers all input readers, events, and responders
- * related to commands.
+ * related to commands. Commands are read from stdin,
+ * one per line.
  */
 pub struct CommandPlugin;
 impl Plugin for CommandPlugin {
   fn build(&self, app: &mut App) {
     app.add_event::<CommandEvent>()
-      //.add_system(read_standard_input)
-      .add_system(spammer)
-      .init_resource::<SpamTriggerState>()
-      .add_system(command_quit)
-      .add_system(command_status)
-      .add_system(command_unknown);
+      .insert_resource(spawn_standard_input_reader())
+      .add_systems(Update, (
+        read_standard_input,
+        (command_quit, command_status, command_unknown, command_import, command_export)
+      ).chain());
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::{parse, CommandId};
+
+  #[test]
+  fn commands_are_split_into_arguments() {
+    let command = parse("  Import props/tree.vox 1 -2 3\n");
+    assert_eq!(command.id, CommandId::Import);
+    assert_eq!(command.argc, 4);
+    assert_eq!(command.argv, ["props/tree.vox", "1", "-2", "3"]);
+    assert_eq!(parse("status").id, CommandId::Status);
+    let unknown = parse("dance twice");
+    assert_eq!(unknown.id, CommandId::Unknown);
+    assert_eq!(unknown.argv, ["dance twice"]);
   }
 }
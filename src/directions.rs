@@ -1,5 +1,6 @@
 use bevy::prelude::IVec3;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Directions {
     Zero,
     X,
@@ -23,6 +24,13 @@ impl Directions {
         ]
     }
 
+    /// The direction pointing along the given unit vector, if there is one.
+    pub fn from_vector(vector: IVec3) -> Option<Directions> {
+        Self::all()
+            .into_iter()
+            .find(|direction| direction.to_vector() == vector)
+    }
+
     pub fn to_vector(&self) -> IVec3 {
         match self {
             Self::Zero => IVec3::ZERO,
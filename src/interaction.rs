@@ -0,0 +1,203 @@
+use crate::material::VoxelMaterialRegistry;
+use crate::physics::VoxelBody;
+use crate::player_controller::PlayerController;
+use crate::raycast::LookingAt;
+use crate::voxel::{Voxel, EMPTY};
+use crate::world::World;
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::window::CursorGrabMode;
+
+pub const HOTBAR_SLOTS: usize = 9;
+
+/// The materials a player can place, one of which is selected.
+#[derive(Component, Default)]
+pub struct Hotbar {
+    pub slots: Vec<u16>,
+    pub selected: usize,
+}
+
+impl Hotbar {
+    pub fn selected_material(&self) -> Option<u16> {
+        self.slots.get(self.selected).copied()
+    }
+
+    /// Moves the selection by the given number of slots, wrapping around at
+    /// either end.
+    pub fn scroll(&mut self, slots: i32) {
+        if self.slots.is_empty() {
+            return;
+        }
+        let len = self.slots.len() as i32;
+        self.selected = (self.selected as i32 + slots).rem_euclid(len) as usize;
+    }
+}
+
+/// Marks the UI node showing one slot of the hotbar.
+#[derive(Component)]
+pub struct HotbarSlot(pub usize);
+
+const NUMBER_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+pub fn fill_hotbars(registry: Res<VoxelMaterialRegistry>, mut query: Query<&mut Hotbar>) {
+    // every material but void, in the order they were registered
+    let slots: Vec<u16> = (1..registry.iter().count() as u16)
+        .take(HOTBAR_SLOTS)
+        .collect();
+    for mut hotbar in &mut query {
+        if hotbar.slots != slots {
+            hotbar.selected = hotbar.selected.min(slots.len().saturating_sub(1));
+            hotbar.slots = slots.clone();
+        }
+    }
+}
+
+pub fn select_hotbar_slot(
+    keys: Res<Input<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut query: Query<&mut Hotbar, With<PlayerController>>,
+) {
+    let scrolled: f32 = mouse_wheel.read().map(|event| event.y).sum();
+    for mut hotbar in &mut query {
+        for (slot, key) in NUMBER_KEYS.iter().enumerate() {
+            if keys.just_pressed(*key) && slot < hotbar.slots.len() {
+                hotbar.selected = slot;
+            }
+        }
+        // scrolling up moves towards the first slot
+        if scrolled != 0. {
+            hotbar.scroll(-scrolled.signum() as i32);
+        }
+    }
+}
+
+/// Left click breaks the voxel the player is looking at, and right click
+/// places the selected material against the face they are looking at.
+pub fn break_and_place(
+    buttons: Res<Input<MouseButton>>,
+    window_query: Query<&Window>,
+    mut world: ResMut<World>,
+    query: Query<(&Transform, Option<&VoxelBody>, &LookingAt, &Hotbar), With<PlayerController>>,
+) {
+    let Ok(primary) = window_query.get_single() else {
+        return;
+    };
+    if primary.cursor.grab_mode != CursorGrabMode::Locked {
+        return;
+    }
+    for (transform, body, looking_at, hotbar) in &query {
+        let Some(hit) = looking_at.0 else {
+            continue;
+        };
+        if buttons.just_pressed(MouseButton::Left) {
+            world.set_voxel(hit.position, EMPTY);
+        } else if buttons.just_pressed(MouseButton::Right) {
+            let Some(material) = hotbar.selected_material() else {
+                continue;
+            };
+            let target = hit.adjacent();
+            // never place a voxel inside the player, or inside the voxel the
+            // player is looking out from
+            let inside_player = match body {
+                Some(body) => body.aabb(transform.translation).overlaps_cell(target),
+                None => target == transform.translation.floor().as_ivec3(),
+            };
+            if target == hit.position || inside_player {
+                continue;
+            }
+            world.set_voxel(target, Voxel::new(material));
+        }
+    }
+}
+
+pub fn draw_target_outline(mut gizmos: Gizmos, query: Query<&LookingAt>) {
+    for looking_at in &query {
+        if let Some(hit) = looking_at.0 {
+            // slightly larger than the voxel, so the outline isn't hidden by
+            // its faces
+            let center = hit.position.as_vec3() + Vec3::splat(0.5);
+            let transform = Transform::from_translation(center).with_scale(Vec3::splat(1.01));
+            gizmos.cuboid(transform, Color::BLACK);
+        }
+    }
+}
+
+pub fn setup_hotbar_ui(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(12.),
+                width: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for slot in 0..HOTBAR_SLOTS {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Px(40.),
+                            height: Val::Px(40.),
+                            margin: UiRect::all(Val::Px(2.)),
+                            border: UiRect::all(Val::Px(3.)),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    HotbarSlot(slot),
+                ));
+            }
+        });
+}
+
+pub fn update_hotbar_ui(
+    registry: Res<VoxelMaterialRegistry>,
+    hotbars: Query<&Hotbar, With<PlayerController>>,
+    mut slots: Query<(&HotbarSlot, &mut BackgroundColor, &mut BorderColor)>,
+) {
+    let Ok(hotbar) = hotbars.get_single() else {
+        return;
+    };
+    for (slot, mut background, mut border) in &mut slots {
+        let material = hotbar.slots.get(slot.0).and_then(|id| registry.get(*id));
+        background.0 = material.map_or(Color::NONE, |material| material.get_color());
+        border.0 = if slot.0 == hotbar.selected && material.is_some() {
+            Color::WHITE
+        } else {
+            Color::rgba(0., 0., 0., 0.5)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interaction::Hotbar;
+
+    #[test]
+    fn hotbar_scrolling_wraps_around() {
+        let mut hotbar = Hotbar {
+            slots: vec![1, 2, 3],
+            selected: 0,
+        };
+        hotbar.scroll(-1);
+        assert_eq!(hotbar.selected_material(), Some(3));
+        hotbar.scroll(2);
+        assert_eq!(hotbar.selected_material(), Some(2));
+        let mut empty = Hotbar::default();
+        empty.scroll(1);
+        assert_eq!(empty.selected_material(), None);
+    }
+}
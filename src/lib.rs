@@ -0,0 +1,24 @@
+pub mod biome;
+pub mod chunk;
+pub mod collision;
+pub mod commands;
+pub mod directions;
+pub mod interaction;
+pub mod light;
+pub mod material;
+pub mod material_definition;
+pub mod mesh_export;
+pub mod meshing_chunk;
+pub mod noise;
+pub mod physics;
+pub mod player_controller;
+pub mod raycast;
+pub mod region;
+pub mod save;
+pub mod storage;
+pub mod structure;
+pub mod terrain;
+pub mod vox;
+pub mod voxel;
+pub mod voxel_texture;
+pub mod world;
@@ -0,0 +1,536 @@
+use crate::chunk::{voxel_chunk, ChunkShape, CHUNK_DIM};
+use crate::directions::Directions;
+use crate::material::VoxelMaterialRegistry;
+use crate::storage::chunk::ChunkPosition;
+use crate::voxel::Voxel;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use block_mesh::VoxelVisibility;
+use ndshape::ConstShape;
+use std::collections::VecDeque;
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// How much light reaches a voxel from the sky and from emissive voxels,
+/// each from 0 to `MAX_LIGHT`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Light {
+    pub sky: u8,
+    pub block: u8,
+}
+
+impl Light {
+    /// Open sky, with no block light.
+    pub const SKY: Light = Light {
+        sky: MAX_LIGHT,
+        block: 0,
+    };
+
+    /// The brighter of the sky and block light.
+    pub fn level(&self) -> u8 {
+        self.sky.max(self.block)
+    }
+
+    fn get(&self, kind: LightKind) -> u8 {
+        match kind {
+            LightKind::Sky => self.sky,
+            LightKind::Block => self.block,
+        }
+    }
+
+    fn set(&mut self, kind: LightKind, value: u8) {
+        match kind {
+            LightKind::Sky => self.sky = value,
+            LightKind::Block => self.block = value,
+        }
+    }
+}
+
+/// Sky and block light spread the same way, except that full sky light
+/// travels straight down without dimming.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LightKind {
+    Sky,
+    Block,
+}
+
+/// The light of every voxel of a chunk, with sky and block light packed
+/// into four bits each.
+#[derive(Clone)]
+pub struct ChunkLight {
+    values: Vec<u8>,
+}
+
+impl ChunkLight {
+    pub fn dark() -> Self {
+        Self {
+            values: vec![0; ChunkShape::USIZE],
+        }
+    }
+
+    pub fn get(&self, local: [u32; 3]) -> Light {
+        let value = self.values[ChunkShape::linearize(local) as usize];
+        Light {
+            sky: value >> 4,
+            block: value & 0xf,
+        }
+    }
+
+    pub fn set(&mut self, local: [u32; 3], light: Light) {
+        self.values[ChunkShape::linearize(local) as usize] = light.sky << 4 | light.block;
+    }
+}
+
+/// Whether light passes through the voxel.
+pub fn transmits_light(voxel: Voxel, registry: &VoxelMaterialRegistry) -> bool {
+    registry.get_visibility(voxel) != VoxelVisibility::Opaque
+}
+
+/// The light of every lit chunk in the world. Light floods out from the sky
+/// and from emissive voxels, one voxel at a time, across the borders of
+/// every lit chunk. Voxels are read through a function which returns None
+/// for voxels that aren't loaded.
+#[derive(Default)]
+pub struct LightMap {
+    chunks: HashMap<ChunkPosition, ChunkLight>,
+    // chunks whose meshes show voxels whose light has changed since they
+    // were last taken
+    changed: HashSet<ChunkPosition>,
+}
+
+type Voxels<'a> = &'a dyn Fn(IVec3) -> Option<Voxel>;
+
+impl LightMap {
+    /// The light at the given world coordinates, or None if its chunk isn't
+    /// lit.
+    pub fn get(&self, position: IVec3) -> Option<Light> {
+        let (chunk_pos, local) = voxel_chunk(position);
+        Some(self.chunks.get(&chunk_pos)?.get(local))
+    }
+
+    fn set(&mut self, position: IVec3, light: Light) {
+        let (chunk_pos, local) = voxel_chunk(position);
+        if let Some(chunk) = self.chunks.get_mut(&chunk_pos) {
+            chunk.set(local, light);
+            self.changed.insert(chunk_pos);
+            // voxels on the chunk's faces are also shown by the neighbours
+            // they face
+            for axis in 0..3 {
+                let mut step = IVec3::ZERO;
+                step[axis] = match local[axis] {
+                    0 => -1,
+                    last if last == CHUNK_DIM - 1 => 1,
+                    _ => continue,
+                };
+                self.changed.insert(chunk_pos + step);
+            }
+        }
+    }
+
+    pub fn get_chunk(&self, chunk_pos: ChunkPosition) -> Option<&ChunkLight> {
+        self.chunks.get(&chunk_pos)
+    }
+
+    pub fn contains_chunk(&self, chunk_pos: ChunkPosition) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    /// Drops the light of every chunk for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(ChunkPosition) -> bool) {
+        self.chunks.retain(|pos, _| keep(*pos));
+    }
+
+    /// Every chunk whose mesh shows a voxel whose light changed since this
+    /// was last called, whether or not the chunk is lit itself.
+    pub fn take_changed(&mut self) -> HashSet<ChunkPosition> {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Lights a newly loaded chunk, letting in the light of its lit
+    /// neighbours and spreading its own light out into them.
+    pub fn light_chunk(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        self.chunks.insert(chunk_pos, ChunkLight::dark());
+        let origin = chunk_pos.as_ivec3() * CHUNK_DIM as i32;
+        for kind in [LightKind::Sky, LightKind::Block] {
+            let mut queue = VecDeque::new();
+            for index in 0..ChunkShape::SIZE {
+                let local = IVec3::from_array(ChunkShape::delinearize(index).map(|c| c as i32));
+                self.add_source(kind, origin + local, voxels, registry, &mut queue);
+            }
+            // the faces of the neighbours touching this chunk shine into it
+            for direction in &Directions::all()[1..] {
+                let vector = direction.to_vector();
+                if !self.contains_chunk(chunk_pos + vector) {
+                    continue;
+                }
+                queue.extend(face_positions(chunk_pos, vector));
+            }
+            self.spread(kind, queue, voxels, registry);
+        }
+
+        // the chunk below saw open sky above it until now
+        let below = chunk_pos + IVec3::NEG_Y;
+        if self.contains_chunk(below) {
+            let mut removals = VecDeque::new();
+            for position in face_positions(chunk_pos, IVec3::NEG_Y) {
+                let Some(mut light) = self.get(position) else {
+                    continue;
+                };
+                let above = self.get(position + IVec3::Y).unwrap_or_default();
+                if light.sky == MAX_LIGHT && above.sky != MAX_LIGHT {
+                    light.sky = 0;
+                    self.set(position, light);
+                    removals.push_back((position, MAX_LIGHT));
+                }
+            }
+            self.respread(LightKind::Sky, removals, voxels, registry);
+        }
+    }
+
+    /// Relights the world around a voxel that has just changed: removes the
+    /// light it blocks or no longer gives off, and lets in light it no
+    /// longer blocks.
+    pub fn update_voxel(
+        &mut self,
+        position: IVec3,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        let Some(voxel) = voxels(position) else {
+            return;
+        };
+        for kind in [LightKind::Sky, LightKind::Block] {
+            let Some(mut light) = self.get(position) else {
+                return;
+            };
+            let old = light.get(kind);
+            light.set(kind, 0);
+            self.set(position, light);
+            let mut queue =
+                self.respread_queue(kind, VecDeque::from([(position, old)]), voxels, registry);
+            self.add_source(kind, position, voxels, registry, &mut queue);
+            if transmits_light(voxel, registry) {
+                for direction in &Directions::all()[1..] {
+                    queue.push_back(position + direction.to_vector());
+                }
+            }
+            self.spread(kind, queue, voxels, registry);
+        }
+    }
+
+    /// How much light of the kind the voxel gives off by itself: full sky
+    /// light where nothing lit is above it, and block light from emissive
+    /// materials.
+    fn source(
+        &self,
+        kind: LightKind,
+        position: IVec3,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) -> u8 {
+        let Some(voxel) = voxels(position) else {
+            return 0;
+        };
+        match kind {
+            // chunks above that aren't lit yet are assumed to be open sky, and
+            // are corrected once they are lit
+            LightKind::Sky => {
+                let (above, _) = voxel_chunk(position + IVec3::Y);
+                if transmits_light(voxel, registry) && !self.contains_chunk(above) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            }
+            LightKind::Block => registry
+                .get(voxel.material)
+                .map_or(0, |material| material.get_light_emission()),
+        }
+    }
+
+    /// Brightens the voxel to the light it gives off, queueing it to spread.
+    fn add_source(
+        &mut self,
+        kind: LightKind,
+        position: IVec3,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+        queue: &mut VecDeque<IVec3>,
+    ) {
+        let source = self.source(kind, position, voxels, registry);
+        let Some(mut light) = self.get(position) else {
+            return;
+        };
+        if source > light.get(kind) {
+            light.set(kind, source);
+            self.set(position, light);
+            queue.push_back(position);
+        }
+    }
+
+    /// Spreads the light of every queued voxel out to its neighbours, and on
+    /// from there, until it runs out.
+    fn spread(
+        &mut self,
+        kind: LightKind,
+        mut queue: VecDeque<IVec3>,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        while let Some(position) = queue.pop_front() {
+            let Some(level) = self.get(position).map(|light| light.get(kind)) else {
+                continue;
+            };
+            if level == 0 {
+                continue;
+            }
+            for direction in &Directions::all()[1..] {
+                let vector = direction.to_vector();
+                let neighbour = position + vector;
+                let Some(mut light) = self.get(neighbour) else {
+                    continue;
+                };
+                if !voxels(neighbour).is_some_and(|voxel| transmits_light(voxel, registry)) {
+                    continue;
+                }
+                let spread = if falls_unchanged(kind, vector, level) {
+                    level
+                } else {
+                    level - 1
+                };
+                if light.get(kind) < spread {
+                    light.set(kind, spread);
+                    self.set(neighbour, light);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Darkens every voxel that was lit by the queued voxels, which have
+    /// already been darkened, then spreads light back in from the edges of
+    /// the darkened region.
+    fn respread(
+        &mut self,
+        kind: LightKind,
+        removals: VecDeque<(IVec3, u8)>,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        let queue = self.respread_queue(kind, removals, voxels, registry);
+        self.spread(kind, queue, voxels, registry);
+    }
+
+    /// The darkening half of `respread`, which returns the voxels to spread
+    /// light from afterwards.
+    fn respread_queue(
+        &mut self,
+        kind: LightKind,
+        mut removals: VecDeque<(IVec3, u8)>,
+        voxels: Voxels,
+        registry: &VoxelMaterialRegistry,
+    ) -> VecDeque<IVec3> {
+        let mut queue = VecDeque::new();
+        while let Some((position, level)) = removals.pop_front() {
+            for direction in &Directions::all()[1..] {
+                let vector = direction.to_vector();
+                let neighbour = position + vector;
+                let Some(mut light) = self.get(neighbour) else {
+                    continue;
+                };
+                let neighbour_level = light.get(kind);
+                if neighbour_level == 0 {
+                    continue;
+                }
+                // dimmer neighbours may have been lit by this voxel, so are
+                // darkened too; brighter ones were lit from elsewhere, and
+                // light the darkened region back up
+                if neighbour_level < level || falls_unchanged(kind, vector, level) {
+                    light.set(kind, 0);
+                    self.set(neighbour, light);
+                    removals.push_back((neighbour, neighbour_level));
+                    self.add_source(kind, neighbour, voxels, registry, &mut queue);
+                } else {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        queue
+    }
+}
+
+/// Whether light spreading in the direction keeps its level: full sky light
+/// falls straight down through open voxels without dimming.
+fn falls_unchanged(kind: LightKind, direction: IVec3, level: u8) -> bool {
+    kind == LightKind::Sky && direction == IVec3::NEG_Y && level == MAX_LIGHT
+}
+
+/// The voxels of the neighbouring chunk in the direction which touch the
+/// chunk.
+fn face_positions(chunk_pos: ChunkPosition, direction: IVec3) -> impl Iterator<Item = IVec3> {
+    let origin = chunk_pos.as_ivec3() * CHUNK_DIM as i32;
+    let size = CHUNK_DIM as i32;
+    let axis = direction
+        .abs()
+        .to_array()
+        .iter()
+        .position(|c| *c == 1)
+        .unwrap();
+    let layer = if direction[axis] > 0 { size } else { -1 };
+    (0..size).flat_map(move |a| {
+        (0..size).map(move |b| {
+            let mut local = IVec3::ZERO;
+            local[axis] = layer;
+            local[(axis + 1) % 3] = a;
+            local[(axis + 2) % 3] = b;
+            origin + local
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::light::{Light, LightMap, MAX_LIGHT};
+    use crate::material::{VoxelMaterial, VoxelMaterialRegistry, STONE};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::voxel::{Voxel, EMPTY};
+    use bevy::prelude::*;
+    use bevy::utils::HashMap;
+
+    // two chunks stacked on top of each other, with a stone floor at y = 0
+    // and whatever voxels are placed on it
+    fn voxel(placed: &HashMap<IVec3, Voxel>, position: IVec3) -> Option<Voxel> {
+        let in_chunks = (0..32).contains(&position.x)
+            && (0..64).contains(&position.y)
+            && (0..32).contains(&position.z);
+        if !in_chunks {
+            return None;
+        }
+        Some(match placed.get(&position) {
+            Some(voxel) => *voxel,
+            None if position.y == 0 => Voxel::new(STONE),
+            None => EMPTY,
+        })
+    }
+
+    fn lit(placed: &HashMap<IVec3, Voxel>, registry: &VoxelMaterialRegistry) -> LightMap {
+        let mut light = LightMap::default();
+        for y in 0..2 {
+            light.light_chunk(
+                ChunkPosition::new(0, y, 0),
+                &|position| voxel(placed, position),
+                registry,
+            );
+        }
+        light
+    }
+
+    // a 5 by 5 roof at y = 10, centered on x = z = 10
+    fn roof() -> HashMap<IVec3, Voxel> {
+        let mut placed = HashMap::new();
+        for x in 8..=12 {
+            for z in 8..=12 {
+                placed.insert(IVec3::new(x, 10, z), Voxel::new(STONE));
+            }
+        }
+        placed
+    }
+
+    #[test]
+    fn sky_light_falls_and_spreads_under_roofs() {
+        let registry = VoxelMaterialRegistry::default();
+        let light = lit(&roof(), &registry);
+        // sky light falls through both chunks undimmed
+        assert_eq!(light.get(IVec3::new(3, 1, 3)), Some(Light::SKY));
+        assert_eq!(light.get(IVec3::new(3, 40, 3)), Some(Light::SKY));
+        // under the roof, light only comes in from the sides
+        assert_eq!(light.get(IVec3::new(10, 9, 10)).unwrap().sky, MAX_LIGHT - 3);
+        assert_eq!(light.get(IVec3::new(10, 0, 10)).unwrap().sky, 0);
+        assert_eq!(light.get(IVec3::new(10, 11, 10)), Some(Light::SKY));
+    }
+
+    #[test]
+    fn edits_relight_the_world() {
+        let mut registry = VoxelMaterialRegistry::default();
+        let glowstone = registry
+            .register(
+                "glowstone",
+                VoxelMaterial::new("Glowstone", Color::YELLOW).with_emissive_strength(12.),
+            )
+            .unwrap();
+        let mut placed = roof();
+        let mut light = lit(&placed, &registry);
+
+        // a light under the roof, lighting the floor below it
+        let lamp = IVec3::new(10, 5, 10);
+        placed.insert(lamp, Voxel::new(glowstone));
+        light.update_voxel(lamp, &|position| voxel(&placed, position), &registry);
+        assert_eq!(light.get(lamp).unwrap().block, 12);
+        assert_eq!(light.get(IVec3::new(10, 1, 10)).unwrap().block, 8);
+
+        // opening the roof lets the sky back in
+        let hole = IVec3::new(10, 10, 10);
+        placed.remove(&hole);
+        light.update_voxel(hole, &|position| voxel(&placed, position), &registry);
+        assert_eq!(
+            light.get(IVec3::new(10, 6, 10)),
+            Some(Light { sky: 15, block: 11 })
+        );
+
+        // and taking the light away darkens the floor again
+        placed.remove(&lamp);
+        light.update_voxel(lamp, &|position| voxel(&placed, position), &registry);
+        assert_eq!(light.get(IVec3::new(10, 1, 10)).unwrap().block, 0);
+        assert_eq!(light.get(IVec3::new(10, 1, 10)).unwrap().sky, MAX_LIGHT);
+        // closing the roof takes the sky back out
+        placed.insert(hole, Voxel::new(STONE));
+        light.update_voxel(hole, &|position| voxel(&placed, position), &registry);
+        assert_eq!(light.get(IVec3::new(10, 9, 10)).unwrap().sky, MAX_LIGHT - 3);
+        let changed = light.take_changed();
+        assert!(changed.contains(&ChunkPosition::new(0, 0, 0)));
+        assert!(light.take_changed().is_empty());
+    }
+
+    #[test]
+    fn changes_on_chunk_faces_reach_the_neighbours_showing_them() {
+        let registry = VoxelMaterialRegistry::default();
+        let placed = roof();
+        let mut light = lit(&placed, &registry);
+        light.take_changed();
+        // a voxel on the chunk's x = 0 face, which the chunk at x = -1 shows
+        // in its mesh
+        let mut placed = placed;
+        let shade = IVec3::new(0, 3, 10);
+        placed.insert(shade, Voxel::new(STONE));
+        light.update_voxel(shade, &|position| voxel(&placed, position), &registry);
+        let changed = light.take_changed();
+        assert!(changed.contains(&ChunkPosition::new(0, 0, 0)));
+        assert!(changed.contains(&ChunkPosition::new(-1, 0, 0)));
+        assert!(!changed.contains(&ChunkPosition::new(0, 0, -1)));
+    }
+
+    #[test]
+    fn chunks_lit_later_shade_the_chunks_below() {
+        let registry = VoxelMaterialRegistry::default();
+        // a roof covering the whole lower chunk, in the upper chunk
+        let mut placed = HashMap::new();
+        for x in 0..32 {
+            for z in 0..32 {
+                placed.insert(IVec3::new(x, 40, z), Voxel::new(STONE));
+            }
+        }
+        let voxels = |position| voxel(&placed, position);
+        let mut light = LightMap::default();
+        light.light_chunk(ChunkPosition::new(0, 0, 0), &voxels, &registry);
+        // with nothing lit above it, the lower chunk is open to the sky
+        assert_eq!(light.get(IVec3::new(5, 20, 5)), Some(Light::SKY));
+        light.light_chunk(ChunkPosition::new(0, 1, 0), &voxels, &registry);
+        assert_eq!(light.get(IVec3::new(5, 50, 5)), Some(Light::SKY));
+        assert_eq!(light.get(IVec3::new(5, 20, 5)), Some(Light::default()));
+    }
+}
@@ -5,13 +5,7 @@ use bevy::render::{
     settings::{RenderCreation, WgpuSettings},
     RenderPlugin,
 };
-
-mod chunk;
-mod directions;
-mod meshing_chunk;
-mod player_controller;
-mod voxel;
-mod world;
+use voxelite::{commands, material, physics, player_controller, voxel_texture, world};
 
 fn main() {
     App::new()
@@ -23,8 +17,15 @@ fn main() {
                 }),
             }),
             WireframePlugin,
+            commands::CommandPlugin,
+            material::VoxelMaterialPlugin,
+            voxel_texture::VoxelTexturePlugin,
+            physics::VoxelPhysicsPlugin,
             player_controller::PlayerControllerPlugin,
-            world::WorldPlugin,
+            world::WorldPlugin {
+                save_dir: Some("saves/world".into()),
+                ..default()
+            },
         ))
         .insert_resource(WireframeConfig {
             global: true,
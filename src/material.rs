@@ -1,4 +1,47 @@
-use bevy::prelude::{info, Color};
+use bevy::log::info;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use block_mesh::VoxelVisibility;
+use std::sync::Arc;
+
+use crate::light::MAX_LIGHT;
+use crate::voxel::{Voxel, EMPTY};
+use crate::material_definition::{
+	load_material_definitions, register_material_definitions, MaterialDefinition,
+	MaterialDefinitionLoader,
+};
+
+/*
+ * Runtime ids of the built in materials, which every registry starts with.
+ * The void material is used for empty voxels. Material definition assets
+ * may redefine the built in materials, but never change their ids.
+ */
+pub const VOID: u16 = 0;
+pub const STONE: u16 = 1;
+pub const DIRT: u16 = 2;
+pub const GRASS: u16 = 3;
+pub const SAND: u16 = 4;
+pub const SNOW: u16 = 5;
+pub const LOG: u16 = 6;
+pub const LEAVES: u16 = 7;
+
+// as many materials as the u16 ids stored in voxels can tell apart
+pub const MAX_MATERIALS: usize = u16::MAX as usize + 1;
+
+/*
+ * VoxelFace
+ *
+ * The faces of a voxel that may each have their own texture.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelFace {
+	Top,
+	Side,
+	Bottom,
+}
+impl VoxelFace {
+	pub const ALL: [VoxelFace; 3] = [VoxelFace::Top, VoxelFace::Side, VoxelFace::Bottom];
+}
 
 /*
  * VoxelMaterial
@@ -7,49 +50,362 @@ use bevy::prelude::{info, Color};
  * determines the voxel's characteristics, including name and color.
  * Characteristics are read only, accessible by getter functions.
  */
+#[derive(Clone, Debug, PartialEq)]
 pub struct VoxelMaterial {
-	name: & 'static str,
+	name: String,
 	color: Color,
+	opacity: f32,
+	hardness: f32,
+	emissive_strength: f32,
+	// whether voxels behind the material show through it
+	translucent: bool,
+	// texture paths, indexed by VoxelFace
+	textures: [Option<String>; 3],
 }
 impl VoxelMaterial {
-	pub fn get_name(& self) -> & 'static str { self.name }
+	/*
+	 * Creates a fully opaque, non-emissive, untextured material.
+	 */
+	pub fn new(name: impl Into<String>, color: Color) -> Self {
+		VoxelMaterial {
+			name: name.into(),
+			color,
+			opacity: 1.,
+			hardness: 1.,
+			emissive_strength: 0.,
+			translucent: false,
+			textures: Default::default(),
+		}
+	}
+	/*
+	 * Gives every face of the material the same texture.
+	 */
+	pub fn with_texture(mut self, path: impl Into<String>) -> Self {
+		let path = path.into();
+		self.textures = VoxelFace::ALL.map(|_| Some(path.clone()));
+		self
+	}
+	pub fn with_emissive_strength(mut self, emissive_strength: f32) -> Self {
+		self.emissive_strength = emissive_strength;
+		self
+	}
+	/*
+	 * Translucent materials are drawn with alpha blending, and don't hide
+	 * the faces of the voxels behind them.
+	 */
+	pub fn with_translucent(mut self, translucent: bool) -> Self {
+		self.translucent = translucent;
+		self
+	}
+	pub fn with_face_texture(mut self, face: VoxelFace, path: impl Into<String>) -> Self {
+		self.textures[face as usize] = Some(path.into());
+		self
+	}
+	pub fn from_definition(definition: & MaterialDefinition) -> Self {
+		let [red, green, blue, alpha] = definition.color;
+		// faces without a texture of their own fall back to the material's
+		let faces = & definition.textures;
+		let textures = [& faces.top, & faces.side, & faces.bottom]
+			.map(|texture| texture.clone().or_else(|| definition.texture.clone()));
+		VoxelMaterial {
+			name: definition.name.clone(),
+			color: Color::rgba(red, green, blue, alpha),
+			opacity: definition.opacity,
+			hardness: definition.hardness,
+			emissive_strength: definition.emissive_strength,
+			translucent: definition.translucent,
+			textures,
+		}
+	}
+	pub fn get_name(& self) -> & str { & self.name }
 	pub fn get_color(& self) -> Color { self.color }
+	pub fn get_opacity(& self) -> f32 { self.opacity }
+	pub fn get_hardness(& self) -> f32 { self.hardness }
+	pub fn get_emissive_strength(& self) -> f32 { self.emissive_strength }
+	pub fn is_translucent(& self) -> bool { self.translucent }
+	/*
+	 * How much block light the material gives off, which is its emissive
+	 * strength up to the brightest light there is.
+	 */
+	pub fn get_light_emission(& self) -> u8 {
+		self.emissive_strength.round().clamp(0., MAX_LIGHT as f32) as u8
+	}
+	pub fn get_texture(& self, face: VoxelFace) -> Option<& str> {
+		self.textures[face as usize].as_deref()
+	}
 }
 
 /*
  * VoxelMaterialRegistry
  *
- * The registry links material id's to voxel materials.
+ * The registry links material id's to voxel materials. Every material is
+ * registered under a stable string id (such as "stone"), which maps to the
+ * u16 id stored in voxels. Copies share their materials until one of
+ * them changes, so that meshing tasks and saves can cheaply take a copy
+ * with them.
  */
+#[derive(Resource, Clone)]
 pub struct VoxelMaterialRegistry {
+	inner: Arc<RegistryData>,
+}
+#[derive(Clone, Default)]
+struct RegistryData {
 	materials: Vec<VoxelMaterial>,
+	ids: HashMap<String, u16>,
+	// kept up to date as materials are registered, rather than worked out
+	// for every chunk meshed; see texture_paths and texture_layers
+	texture_paths: Vec<String>,
+	texture_layers: Vec<[u32; 3]>,
+	layers_by_path: HashMap<String, u32>,
+}
+impl RegistryData {
+	/*
+	 * Finds the texture layers of the material with the given id, which is
+	 * the last to be registered or to have its layers found, adding the
+	 * textures that no material used before.
+	 */
+	fn add_texture_layers(& mut self, id: usize) {
+		let textures = VoxelFace::ALL.map(|face| self.materials[id].get_texture(face).map(str::to_owned));
+		let layers = textures.map(|texture| match texture {
+			Some(path) => self.texture_layer(path),
+			None => 0,
+		});
+		self.texture_layers.push(layers);
+	}
+	fn texture_layer(& mut self, path: String) -> u32 {
+		if let Some(layer) = self.layers_by_path.get(& path) {
+			return * layer;
+		}
+		self.texture_paths.push(path.clone());
+		// layer 0 is left blank for untextured faces
+		let layer = self.texture_paths.len() as u32;
+		self.layers_by_path.insert(path, layer);
+		layer
+	}
+	/*
+	 * Finds every material's texture layers again, after a material that
+	 * may have used textures no other material does was redefined.
+	 */
+	fn find_texture_layers(& mut self) {
+		self.texture_paths.clear();
+		self.texture_layers.clear();
+		self.layers_by_path.clear();
+		for id in 0..self.materials.len() {
+			self.add_texture_layers(id);
+		}
+	}
 }
 impl VoxelMaterialRegistry {
 	//#[inline]
 	pub fn get(& self, id: u16) -> Option<& VoxelMaterial> {
-		self.materials.get(id as usize)
+		self.inner.materials.get(id as usize)
+	}
+	pub fn get_id(& self, string_id: & str) -> Option<u16> {
+		self.inner.ids.get(string_id).copied()
+	}
+	pub fn get_string_id(& self, id: u16) -> Option<& str> {
+		self.inner.ids.iter()
+			.find(|(_, other)| **other == id)
+			.map(|(string_id, _)| string_id.as_str())
+	}
+	/*
+	 * Registers a material under the given string id, returning its runtime
+	 * id. Registering a string id again replaces its material, keeping the
+	 * runtime id it was first given. New materials are refused, returning
+	 * None, once MAX_MATERIALS have been registered.
+	 */
+	pub fn register(& mut self, string_id: & str, material: VoxelMaterial) -> Option<u16> {
+		if let Some(id) = self.get_id(string_id) {
+			info!("Redefined material {} (ID: {})", material.get_name(), id);
+			let inner = Arc::make_mut(& mut self.inner);
+			inner.materials[id as usize] = material;
+			inner.find_texture_layers();
+			return Some(id);
+		}
+		if self.inner.materials.len() >= MAX_MATERIALS {
+			return None;
+		}
+		let id = self.inner.materials.len() as u16;
+		info!("Registered material {} (ID: {})", material.get_name(), id);
+		let inner = Arc::make_mut(& mut self.inner);
+		inner.materials.push(material);
+		inner.ids.insert(string_id.to_owned(), id);
+		inner.add_texture_layers(id as usize);
+		Some(id)
 	}
-	pub fn register(& mut self, material: VoxelMaterial) {
-		self.materials.push(material);
-		info!(
-			"Registered material {} (ID: {})",
-			material.get_name(),
-			self.materials.len() - 1
-		);
+	/*
+	 * Registers a placeholder under a string id whose material hasn't been
+	 * defined yet, such as one named by a saved world before its material
+	 * definition loads. Defining the material later replaces the
+	 * placeholder, keeping its runtime id.
+	 */
+	pub fn reserve(& mut self, string_id: & str) -> Option<u16> {
+		self.register(string_id, VoxelMaterial::new(string_id, Color::FUCHSIA))
 	}
 	pub fn iter(& self) -> impl Iterator<Item = & VoxelMaterial> {
-		self.materials.iter()
+		self.inner.materials.iter()
+	}
+	/*
+	 * How the voxel's material lets the voxels behind it be seen. Voxels
+	 * of unknown materials are opaque.
+	 */
+	pub fn get_visibility(& self, voxel: Voxel) -> VoxelVisibility {
+		if voxel == EMPTY {
+			return VoxelVisibility::Empty;
+		}
+		match self.get(voxel.material) {
+			Some(material) if material.is_translucent() => VoxelVisibility::Translucent,
+			_ => VoxelVisibility::Opaque,
+		}
+	}
+	/*
+	 * Every texture used by the registered materials, once each, in the
+	 * order they are first used. These are the layers of the voxel texture
+	 * array, after layer 0, which is left blank for untextured faces.
+	 */
+	pub fn texture_paths(& self) -> & [String] {
+		& self.inner.texture_paths
+	}
+	/*
+	 * The texture array layer of each face of each material, indexed by
+	 * material id, then by VoxelFace.
+	 */
+	pub fn texture_layers(& self) -> & [[u32; 3]] {
+		& self.inner.texture_layers
 	}
 }
 impl Default for VoxelMaterialRegistry {
 	fn default() -> Self {
 		let mut registry = Self {
-			materials: Default::default()
+			inner: Default::default(),
 		};
-		registry.register(VoxelMaterial {
-			name: "Void",
-			color: Color::BLACK
-		});
+		registry.register("void", VoxelMaterial::new("Void", Color::BLACK));
+		registry.register("stone", VoxelMaterial::new("Stone", Color::rgb(0.5, 0.5, 0.52)));
+		registry.register("dirt", VoxelMaterial::new("Dirt", Color::rgb(0.45, 0.3, 0.18)));
+		registry.register("grass", VoxelMaterial::new("Grass", Color::rgb(0.3, 0.6, 0.2)));
+		registry.register("sand", VoxelMaterial::new("Sand", Color::rgb(0.86, 0.8, 0.55)));
+		registry.register("snow", VoxelMaterial::new("Snow", Color::rgb(0.95, 0.96, 0.98)));
+		registry.register("log", VoxelMaterial::new("Log", Color::rgb(0.4, 0.27, 0.14)));
+		let leaves = VoxelMaterial::new("Leaves", Color::rgb(0.2, 0.45, 0.15)).with_translucent(true);
+		registry.register("leaves", leaves);
 		registry
 	}
 }
+
+/*
+ * MaterialsChanged
+ *
+ * Sent whenever materials already in the registry are redefined, with the
+ * runtime ids of each changed material.
+ */
+#[derive(Event)]
+pub struct MaterialsChanged {
+	pub ids: Vec<u16>,
+}
+
+/*
+ * VoxelMaterialPlugin
+ *
+ * Populates the material registry resource at startup, then keeps it up
+ * to date with the material definitions found in assets/materials.
+ */
+pub struct VoxelMaterialPlugin;
+impl Plugin for VoxelMaterialPlugin {
+	fn build(& self, app: & mut App) {
+		app.init_resource::<VoxelMaterialRegistry>()
+			.add_event::<MaterialsChanged>()
+			.init_asset::<MaterialDefinition>()
+			.init_asset_loader::<MaterialDefinitionLoader>()
+			.add_systems(Startup, load_material_definitions)
+			.add_systems(Update, register_material_definitions);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		VoxelFace, VoxelMaterial, VoxelMaterialRegistry, DIRT, GRASS, LEAVES, LOG, MAX_MATERIALS,
+		SAND, SNOW, STONE, VOID,
+	};
+	use crate::voxel::{Voxel, EMPTY};
+	use bevy::prelude::Color;
+	use block_mesh::VoxelVisibility;
+
+	#[test]
+	fn default_materials_match_their_ids() {
+		let registry = VoxelMaterialRegistry::default();
+		assert_eq!(registry.get(VOID).unwrap().get_name(), "Void");
+		assert_eq!(registry.get(STONE).unwrap().get_name(), "Stone");
+		assert_eq!(registry.get(DIRT).unwrap().get_name(), "Dirt");
+		assert_eq!(registry.get(GRASS).unwrap().get_name(), "Grass");
+		assert_eq!(registry.get(SAND).unwrap().get_name(), "Sand");
+		assert_eq!(registry.get(SNOW).unwrap().get_name(), "Snow");
+		assert_eq!(registry.get(LOG).unwrap().get_name(), "Log");
+		assert_eq!(registry.get(LEAVES).unwrap().get_name(), "Leaves");
+		assert_eq!(registry.iter().count(), 8);
+	}
+
+	#[test]
+	fn redefined_materials_keep_their_ids() {
+		let mut registry = VoxelMaterialRegistry::default();
+		let marble = registry.register("marble", VoxelMaterial::new("Marble", Color::WHITE));
+		assert_eq!(registry.get_id("marble"), marble);
+		let stone = registry.register("stone", VoxelMaterial::new("Granite", Color::GRAY));
+		assert_eq!(stone, Some(STONE));
+		assert_eq!(registry.get(STONE).unwrap().get_name(), "Granite");
+		assert_eq!(registry.iter().count(), 9);
+	}
+
+	#[test]
+	fn texture_layers_are_shared_between_faces() {
+		let mut registry = VoxelMaterialRegistry::default();
+		registry.register("stone", VoxelMaterial::new("Stone", Color::GRAY).with_texture("stone.png"));
+		let grass = VoxelMaterial::new("Grass", Color::GREEN)
+			.with_texture("grass_side.png")
+			.with_face_texture(VoxelFace::Top, "grass_top.png")
+			.with_face_texture(VoxelFace::Bottom, "stone.png");
+		registry.register("grass", grass);
+		assert_eq!(registry.texture_paths(), ["stone.png", "grass_top.png", "grass_side.png"]);
+		let layers = registry.texture_layers();
+		assert_eq!(layers.len(), registry.iter().count());
+		assert_eq!(layers[STONE as usize], [1, 1, 1]);
+		assert_eq!(layers[GRASS as usize], [2, 3, 1]);
+		// untextured faces use the blank layer
+		assert_eq!(layers[DIRT as usize], [0, 0, 0]);
+		// textures no material uses any more give up their layers
+		registry.register("grass", VoxelMaterial::new("Grass", Color::GREEN).with_texture("stone.png"));
+		assert_eq!(registry.texture_paths(), ["stone.png"]);
+		assert_eq!(registry.texture_layers()[GRASS as usize], [1, 1, 1]);
+	}
+
+	#[test]
+	fn translucent_materials_are_translucent_voxels() {
+		let mut registry = VoxelMaterialRegistry::default();
+		let glass = VoxelMaterial::new("Glass", Color::WHITE).with_translucent(true);
+		let glass = registry.register("glass", glass).unwrap();
+		assert_eq!(registry.get_visibility(EMPTY), VoxelVisibility::Empty);
+		assert_eq!(registry.get_visibility(Voxel::new(STONE)), VoxelVisibility::Opaque);
+		assert_eq!(registry.get_visibility(Voxel::new(LEAVES)), VoxelVisibility::Translucent);
+		assert_eq!(registry.get_visibility(Voxel::new(glass)), VoxelVisibility::Translucent);
+		// materials that aren't registered yet hide what is behind them
+		assert_eq!(registry.get_visibility(Voxel::new(glass + 1)), VoxelVisibility::Opaque);
+	}
+
+	#[test]
+	fn registries_refuse_materials_without_an_id_left() {
+		let mut registry = VoxelMaterialRegistry::default();
+		let copy = registry.clone();
+		for index in registry.iter().count()..MAX_MATERIALS {
+			let string_id = format!("material_{}", index);
+			assert_eq!(registry.reserve(& string_id), Some(index as u16));
+		}
+		assert_eq!(registry.reserve("one_too_many"), None);
+		assert_eq!(registry.get_id("one_too_many"), None);
+		// existing materials can still be redefined
+		let stone = VoxelMaterial::new("Granite", Color::GRAY);
+		assert_eq!(registry.register("stone", stone), Some(STONE));
+		// copies taken before the registry changed are left as they were
+		assert_eq!(copy.iter().count(), 8);
+		assert_eq!(copy.get(STONE).unwrap().get_name(), "Stone");
+	}
+}
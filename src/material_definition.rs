@@ -0,0 +1,227 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext, LoadedFolder};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::material::{MaterialsChanged, VoxelMaterial, VoxelMaterialRegistry};
+
+/*
+ * MaterialDefinition
+ *
+ * A material as written by designers in a `.material.ron` asset file. The
+ * id is the material's stable string id in the registry; every other field
+ * but the name and color may be left out.
+ */
+#[derive(Asset, TypePath, Debug, Deserialize)]
+pub struct MaterialDefinition {
+	pub id: String,
+	pub name: String,
+	// srgb red, green, blue and alpha
+	pub color: [f32; 4],
+	#[serde(default = "default_opacity")]
+	pub opacity: f32,
+	#[serde(default = "default_hardness")]
+	pub hardness: f32,
+	#[serde(default)]
+	pub emissive_strength: f32,
+	// whether voxels behind the material show through it
+	#[serde(default)]
+	pub translucent: bool,
+	// path of the material's texture, relative to the assets folder
+	#[serde(default)]
+	pub texture: Option<String>,
+	// textures for individual faces, which take the place of `texture`
+	#[serde(default)]
+	pub textures: FaceTextures,
+}
+
+/*
+ * FaceTextures
+ *
+ * Per face texture paths of a material definition, any of which may be
+ * left out.
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct FaceTextures {
+	#[serde(default)]
+	pub top: Option<String>,
+	#[serde(default)]
+	pub side: Option<String>,
+	#[serde(default)]
+	pub bottom: Option<String>,
+}
+
+fn default_opacity() -> f32 {
+	1.
+}
+
+fn default_hardness() -> f32 {
+	1.
+}
+
+#[derive(Debug, Error)]
+pub enum MaterialDefinitionLoaderError {
+	#[error("could not read material definition: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("could not parse material definition: {0}")]
+	Ron(#[from] ron::error::SpannedError),
+	#[error("no runtime id is left for material {0}")]
+	RegistryFull(String),
+}
+
+/*
+ * MaterialDefinitionLoader
+ *
+ * Loads material definitions from RON files.
+ */
+#[derive(Default)]
+pub struct MaterialDefinitionLoader;
+impl AssetLoader for MaterialDefinitionLoader {
+	type Asset = MaterialDefinition;
+	type Settings = ();
+	type Error = MaterialDefinitionLoaderError;
+
+	fn load<'a>(
+		& 'a self,
+		reader: & 'a mut Reader,
+		_settings: & 'a (),
+		_load_context: & 'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<MaterialDefinition, MaterialDefinitionLoaderError>> {
+		Box::pin(async move {
+			let mut bytes = Vec::new();
+			reader.read_to_end(& mut bytes).await?;
+			Ok(ron::de::from_bytes(& bytes)?)
+		})
+	}
+
+	fn extensions(& self) -> & [& str] {
+		& ["material.ron"]
+	}
+}
+
+/*
+ * MaterialDefinitionFolder
+ *
+ * Keeps every material definition in assets/materials loaded, so they can
+ * be hot reloaded.
+ */
+#[derive(Resource)]
+pub struct MaterialDefinitionFolder(pub Handle<LoadedFolder>);
+
+pub fn load_material_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands.insert_resource(MaterialDefinitionFolder(asset_server.load_folder("materials")));
+}
+
+/*
+ * register_material_definitions
+ *
+ * Registers material definitions as they are loaded or modified. Changes
+ * to materials that were already registered are announced with a
+ * MaterialsChanged event, so chunks using them can be re-meshed.
+ */
+pub fn register_material_definitions(
+	mut events: EventReader<AssetEvent<MaterialDefinition>>,
+	definitions: Res<Assets<MaterialDefinition>>,
+	mut registry: ResMut<VoxelMaterialRegistry>,
+	mut changed: EventWriter<MaterialsChanged>,
+) {
+	let mut ids = Vec::new();
+	for event in events.read() {
+		let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else {
+			continue;
+		};
+		let Some(definition) = definitions.get(*id) else {
+			continue;
+		};
+		let material = VoxelMaterial::from_definition(definition);
+		let existing = registry.get_id(& definition.id);
+		if existing.is_some_and(|id| registry.get(id) == Some(& material)) {
+			continue;
+		}
+		let Some(material_id) = registry.register(& definition.id, material) else {
+			error!("Can't register material {}: the registry is full", definition.id);
+			continue;
+		};
+		if existing.is_some() && !ids.contains(& material_id) {
+			ids.push(material_id);
+		}
+	}
+	if !ids.is_empty() {
+		changed.send(MaterialsChanged { ids });
+	}
+}
+
+/*
+ * read_material_definitions
+ *
+ * Registers every material definition in a directory straight from disk,
+ * for tools that run without an asset server. Returns how many were
+ * registered.
+ */
+pub fn read_material_definitions(
+	dir: & Path,
+	registry: & mut VoxelMaterialRegistry,
+) -> Result<usize, MaterialDefinitionLoaderError> {
+	let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+		.map(|entry| entry.map(|entry| entry.path()))
+		.collect::<Result<_, _>>()?;
+	paths.retain(|path| path.to_string_lossy().ends_with(".material.ron"));
+	// registration order decides runtime ids, so it mustn't depend on the
+	// order the directory happens to list its files in
+	paths.sort();
+	for path in & paths {
+		let definition: MaterialDefinition = ron::from_str(& std::fs::read_to_string(path)?)?;
+		registry.register(& definition.id, VoxelMaterial::from_definition(& definition))
+			.ok_or(MaterialDefinitionLoaderError::RegistryFull(definition.id))?;
+	}
+	Ok(paths.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::MaterialDefinition;
+	use crate::material::{
+		VoxelFace, VoxelMaterial, VoxelMaterialRegistry, GRASS, LEAVES, LOG, SNOW, STONE,
+	};
+
+	#[test]
+	fn bundled_definitions_parse() {
+		let mut registry = VoxelMaterialRegistry::default();
+		for source in [
+			include_str!("../assets/materials/stone.material.ron"),
+			include_str!("../assets/materials/dirt.material.ron"),
+			include_str!("../assets/materials/grass.material.ron"),
+			include_str!("../assets/materials/sand.material.ron"),
+			include_str!("../assets/materials/snow.material.ron"),
+			include_str!("../assets/materials/log.material.ron"),
+			include_str!("../assets/materials/leaves.material.ron"),
+			include_str!("../assets/materials/glowstone.material.ron"),
+			include_str!("../assets/materials/water.material.ron"),
+			include_str!("../assets/materials/glass.material.ron"),
+		] {
+			let definition: MaterialDefinition = ron::from_str(source).unwrap();
+			registry.register(& definition.id, VoxelMaterial::from_definition(& definition));
+		}
+		assert_eq!(registry.get_id("stone"), Some(STONE));
+		assert_eq!(registry.get_id("grass"), Some(GRASS));
+		assert_eq!(registry.get_id("snow"), Some(SNOW));
+		assert_eq!(registry.get_id("leaves"), Some(LEAVES));
+		let glowstone = registry.get(registry.get_id("glowstone").unwrap()).unwrap();
+		assert!(glowstone.get_emissive_strength() > 0.);
+		assert_eq!(registry.get(STONE).unwrap().get_opacity(), 1.);
+		assert!(!registry.get(STONE).unwrap().is_translucent());
+		assert!(registry.get(LEAVES).unwrap().is_translucent());
+		let water = registry.get(registry.get_id("water").unwrap()).unwrap();
+		assert!(water.is_translucent());
+		let grass = registry.get(GRASS).unwrap();
+		assert_eq!(grass.get_texture(VoxelFace::Top), Some("textures/grass_top.png"));
+		assert_eq!(grass.get_texture(VoxelFace::Bottom), Some("textures/dirt.png"));
+		// faces left out fall back to the material's texture
+		let log = registry.get(LOG).unwrap();
+		assert_eq!(log.get_texture(VoxelFace::Side), Some("textures/log_side.png"));
+		assert_eq!(log.get_texture(VoxelFace::Top), Some("textures/log_top.png"));
+	}
+}
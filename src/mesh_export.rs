@@ -0,0 +1,453 @@
+use crate::chunk::CHUNK_DIM;
+use crate::directions::Directions;
+use crate::material::VoxelMaterialRegistry;
+use crate::meshing_chunk::ChunkMeshes;
+use crate::storage::chunk::ChunkPosition;
+use crate::world::World;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+const GL_FLOAT: u32 = 5126;
+const GL_UNSIGNED_INT: u32 = 5125;
+const GL_ARRAY_BUFFER: u32 = 34962;
+const GL_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+/// Boxes hold at most this many chunks, so that a mistyped box can't load
+/// and mesh more of the world than fits in memory.
+pub const MAX_EXPORT_CHUNKS: usize = 4096;
+
+/// The file formats chunk meshes can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Wavefront OBJ, with a color after each vertex position
+    Obj,
+    /// binary glTF 2.0
+    Glb,
+}
+
+impl ExportFormat {
+    /// The format named by a path's extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "obj" => Some(Self::Obj),
+            "glb" => Some(Self::Glb),
+            _ => None,
+        }
+    }
+}
+
+/// The chunks within a box of voxels, given by two corners in world
+/// coordinates, or None if the box holds more than `MAX_EXPORT_CHUNKS`
+/// chunks.
+pub fn chunks_in_box(first: IVec3, second: IVec3) -> Option<Vec<ChunkPosition>> {
+    let size = IVec3::splat(CHUNK_DIM as i32);
+    let min = first.min(second).div_euclid(size);
+    let max = first.max(second).div_euclid(size);
+    let count = (0..3).try_fold(1_u64, |total, axis| {
+        total.checked_mul((max[axis] as i64 - min[axis] as i64 + 1) as u64)
+    });
+    if count.is_none_or(|count| count > MAX_EXPORT_CHUNKS as u64) {
+        return None;
+    }
+    let mut positions = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                positions.push(ChunkPosition::new(x, y, z));
+            }
+        }
+    }
+    Some(positions)
+}
+
+/// The vertices of one mesh, as they are exported.
+#[derive(Default)]
+struct MeshData {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    // linear red, green, blue and alpha
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshData {
+    fn from_mesh(mesh: &Mesh) -> Self {
+        let float3 = |attribute| match mesh.attribute(attribute) {
+            Some(VertexAttributeValues::Float32x3(values)) => values.clone(),
+            _ => Vec::new(),
+        };
+        let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(values)) => values.clone(),
+            _ => Vec::new(),
+        };
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            Some(Indices::U16(indices)) => indices.iter().map(|index| *index as u32).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            positions: float3(Mesh::ATTRIBUTE_POSITION),
+            normals: float3(Mesh::ATTRIBUTE_NORMAL),
+            colors,
+            indices,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+struct ExportedChunk {
+    name: String,
+    // the opaque and translucent meshes, in the order of `MATERIAL_NAMES`
+    meshes: [MeshData; 2],
+}
+
+/// The two groups every chunk's faces are split into, which are exported as
+/// materials of these names.
+const MATERIAL_NAMES: [&str; 2] = ["opaque", "translucent"];
+
+/// The meshes of a set of chunks, gathered to be written to a single file.
+/// Meshes are exported with their vertex colors, which already hold each
+/// voxel's material color, ambient occlusion and light, and without texture
+/// coordinates, which only index the game's texture array.
+#[derive(Default)]
+pub struct MeshExport {
+    chunks: Vec<ExportedChunk>,
+}
+
+impl MeshExport {
+    /// Meshes the given chunks of the world, loading and lighting them, and
+    /// their neighbours, first.
+    pub fn from_world(
+        world: &mut World,
+        positions: &[ChunkPosition],
+        registry: &VoxelMaterialRegistry,
+    ) -> Self {
+        for pos in positions {
+            for direction in Directions::all() {
+                world.load_chunk(*pos + direction.to_vector());
+            }
+        }
+        world.update_light(registry);
+        let mut export = Self::default();
+        for pos in positions {
+            if let Some(meshes) = world.mesh_chunk(*pos, registry) {
+                export.add_chunk(*pos, &meshes);
+            }
+        }
+        export
+    }
+
+    /// Adds a chunk's meshes, with vertices in world coordinates.
+    pub fn add_chunk(&mut self, chunk_pos: ChunkPosition, meshes: &ChunkMeshes) {
+        self.chunks.push(ExportedChunk {
+            name: format!("chunk_{}_{}_{}", chunk_pos.x, chunk_pos.y, chunk_pos.z),
+            meshes: [
+                MeshData::from_mesh(&meshes.opaque),
+                MeshData::from_mesh(&meshes.translucent),
+            ],
+        });
+    }
+
+    /// Writes the meshes to the given path, in the format named by its
+    /// extension.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = ExportFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "meshes export to .obj or .glb files",
+            )
+        })?;
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            ExportFormat::Obj => self.write_obj(&mut file)?,
+            ExportFormat::Glb => self.write_glb(&mut file)?,
+        }
+        file.flush()
+    }
+
+    /// Writes the meshes as an OBJ file, with an object per chunk and a
+    /// group per material. Vertex colors follow each position, in sRGB.
+    pub fn write_obj(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "# voxelite chunk meshes")?;
+        // OBJ indices count from 1, across the whole file
+        let mut first_index = 1;
+        for chunk in &self.chunks {
+            if chunk.meshes.iter().all(MeshData::is_empty) {
+                continue;
+            }
+            writeln!(out, "o {}", chunk.name)?;
+            for (mesh, material) in chunk.meshes.iter().zip(MATERIAL_NAMES) {
+                if mesh.is_empty() {
+                    continue;
+                }
+                writeln!(out, "g {}_{}", chunk.name, material)?;
+                writeln!(out, "usemtl {}", material)?;
+                for (position, color) in mesh.positions.iter().zip(&mesh.colors) {
+                    let [red, green, blue, _] =
+                        Color::rgba_linear(color[0], color[1], color[2], color[3]).as_rgba_f32();
+                    let [x, y, z] = position;
+                    writeln!(out, "v {} {} {} {} {} {}", x, y, z, red, green, blue)?;
+                }
+                for [x, y, z] in &mesh.normals {
+                    writeln!(out, "vn {} {} {}", x, y, z)?;
+                }
+                for triangle in mesh.indices.chunks_exact(3) {
+                    let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] + first_index);
+                    writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+                }
+                first_index += mesh.positions.len() as u32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the meshes as a binary glTF file, with a node per chunk whose
+    /// mesh has a primitive per material.
+    pub fn write_glb(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut buffer_views = Vec::new();
+        let mut accessors = Vec::new();
+        // adds the values as a new buffer view and accessor, returning the
+        // accessor's index
+        let mut add_accessor =
+            |values: &[u8], count: usize, kind: &str, component: u32, target: u32, bounds: &str| {
+                buffer_views.push(format!(
+                    r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                    buffer.len(),
+                    values.len(),
+                    target
+                ));
+                buffer.extend_from_slice(values);
+                accessors.push(format!(
+                    r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{}"{}}}"#,
+                    buffer_views.len() - 1,
+                    component,
+                    count,
+                    kind,
+                    bounds
+                ));
+                accessors.len() - 1
+            };
+
+        let mut nodes = Vec::new();
+        let mut meshes = Vec::new();
+        for chunk in &self.chunks {
+            let mut primitives = Vec::new();
+            for (material, mesh) in chunk.meshes.iter().enumerate() {
+                if mesh.is_empty() {
+                    continue;
+                }
+                let (min, max) =
+                    mesh.positions
+                        .iter()
+                        .fold((Vec3::MAX, Vec3::MIN), |(min, max), position| {
+                            let position = Vec3::from_array(*position);
+                            (min.min(position), max.max(position))
+                        });
+                let bounds = format!(
+                    r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                    min.x, min.y, min.z, max.x, max.y, max.z
+                );
+                let count = mesh.positions.len();
+                let positions = float_bytes(mesh.positions.iter().flatten());
+                let position = add_accessor(
+                    &positions,
+                    count,
+                    "VEC3",
+                    GL_FLOAT,
+                    GL_ARRAY_BUFFER,
+                    &bounds,
+                );
+                let normals = float_bytes(mesh.normals.iter().flatten());
+                let normal = add_accessor(&normals, count, "VEC3", GL_FLOAT, GL_ARRAY_BUFFER, "");
+                let colors = float_bytes(mesh.colors.iter().flatten());
+                let color = add_accessor(&colors, count, "VEC4", GL_FLOAT, GL_ARRAY_BUFFER, "");
+                let indices: Vec<u8> = mesh
+                    .indices
+                    .iter()
+                    .flat_map(|index| index.to_le_bytes())
+                    .collect();
+                let index_count = mesh.indices.len();
+                let indices = add_accessor(
+                    &indices,
+                    index_count,
+                    "SCALAR",
+                    GL_UNSIGNED_INT,
+                    GL_ELEMENT_ARRAY_BUFFER,
+                    "",
+                );
+                primitives.push(format!(
+                    r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"COLOR_0":{}}},"indices":{},"material":{}}}"#,
+                    position, normal, color, indices, material
+                ));
+            }
+            // glTF meshes must have at least one primitive
+            if primitives.is_empty() {
+                continue;
+            }
+            nodes.push(format!(
+                r#"{{"name":"{}","mesh":{}}}"#,
+                chunk.name,
+                meshes.len()
+            ));
+            meshes.push(format!(
+                r#"{{"name":"{}","primitives":[{}]}}"#,
+                chunk.name,
+                primitives.join(",")
+            ));
+        }
+
+        let mut json = String::from(r#"{"asset":{"version":"2.0","generator":"voxelite"}"#);
+        // vertex colors are multiplied by the base color, so it is white
+        json.push_str(concat!(
+            r#","materials":["#,
+            r#"{"name":"opaque","pbrMetallicRoughness":{"metallicFactor":0,"roughnessFactor":0.9}},"#,
+            r#"{"name":"translucent","alphaMode":"BLEND","pbrMetallicRoughness":{"metallicFactor":0,"roughnessFactor":0.9}}"#,
+            "]"
+        ));
+        // scenes must hold at least one node, so empty exports have none
+        if !nodes.is_empty() {
+            let node_indices: Vec<String> =
+                (0..nodes.len()).map(|index| index.to_string()).collect();
+            write!(
+                json,
+                r#","scene":0,"scenes":[{{"nodes":[{}]}}]"#,
+                node_indices.join(",")
+            )
+            .unwrap();
+            write!(
+                json,
+                r#","nodes":[{}],"meshes":[{}]"#,
+                nodes.join(","),
+                meshes.join(",")
+            )
+            .unwrap();
+            write!(json, r#","buffers":[{{"byteLength":{}}}]"#, buffer.len()).unwrap();
+            write!(json, r#","bufferViews":[{}]"#, buffer_views.join(",")).unwrap();
+            write!(json, r#","accessors":[{}]"#, accessors.join(",")).unwrap();
+        }
+        json.push('}');
+
+        // chunks are padded to four bytes, JSON with spaces
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        let mut length = 12 + 8 + json.len();
+        if !buffer.is_empty() {
+            length += 8 + buffer.len();
+        }
+        for value in [
+            GLB_MAGIC,
+            2,
+            length as u32,
+            json.len() as u32,
+            GLB_JSON_CHUNK,
+        ] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&json)?;
+        if !buffer.is_empty() {
+            out.write_all(&(buffer.len() as u32).to_le_bytes())?;
+            out.write_all(&GLB_BIN_CHUNK.to_le_bytes())?;
+            out.write_all(&buffer)?;
+        }
+        Ok(())
+    }
+}
+
+fn float_bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    values.flat_map(|value| value.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::{VoxelMaterialRegistry, STONE};
+    use crate::mesh_export::{chunks_in_box, ExportFormat, MeshExport};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::World;
+    use bevy::prelude::*;
+    use std::path::Path;
+
+    fn exported_pillar() -> MeshExport {
+        let registry = VoxelMaterialRegistry::default();
+        let pillar = |position: IVec3| {
+            if position.x == 40 && position.z == 8 && (0..3).contains(&position.y) {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::new(VoxelMapSettings::default(), pillar);
+        let positions = chunks_in_box(IVec3::new(40, 0, 8), IVec3::new(33, 2, 8)).unwrap();
+        assert_eq!(positions, [ChunkPosition::new(1, 0, 0)]);
+        assert!(chunks_in_box(IVec3::MIN, IVec3::MAX).is_none());
+        MeshExport::from_world(&mut world, &positions, &registry)
+    }
+
+    #[test]
+    fn obj_exports_index_every_vertex() {
+        let mut obj = Vec::new();
+        exported_pillar().write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let vertices: Vec<&str> = obj.lines().filter(|line| line.starts_with("v ")).collect();
+        // the pillar's six sides are each a single quad
+        assert_eq!(vertices.len(), 6 * 4);
+        assert_eq!(vertices[0].split_whitespace().count(), 7);
+        let faces = obj.lines().filter(|line| line.starts_with("f ")).count();
+        assert_eq!(faces, 6 * 2);
+        let indices = obj
+            .lines()
+            .filter_map(|line| line.strip_prefix("f "))
+            .flat_map(|line| line.split_whitespace())
+            .map(|corner| corner.split('/').next().unwrap().parse::<usize>().unwrap());
+        assert!(indices.clone().all(|index| (1..=24).contains(&index)));
+        // vertices are in world coordinates
+        assert!(vertices.iter().all(|vertex| {
+            let x: f32 = vertex.split_whitespace().nth(1).unwrap().parse().unwrap();
+            (40. ..=41.).contains(&x)
+        }));
+    }
+
+    #[test]
+    fn glb_exports_are_well_formed() {
+        let mut glb = Vec::new();
+        exported_pillar().write_glb(&mut glb).unwrap();
+        let word =
+            |index: usize| u32::from_le_bytes(glb[index * 4..index * 4 + 4].try_into().unwrap());
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(word(2) as usize, glb.len());
+        let json_len = word(3) as usize;
+        let json = std::str::from_utf8(&glb[20..20 + json_len]).unwrap();
+        assert!(json.contains(r#""COLOR_0""#));
+        assert!(json.contains(r#""min":[40,0,8],"max":[41,3,9]"#));
+        assert!(json.contains(r#""scenes":[{"nodes":[0]}]"#));
+        let bin_len = u32::from_le_bytes(glb[20 + json_len..24 + json_len].try_into().unwrap());
+        assert_eq!(glb.len(), 20 + json_len + 8 + bin_len as usize);
+        assert_eq!(json_len % 4, 0);
+
+        let mut empty = Vec::new();
+        MeshExport::default().write_glb(&mut empty).unwrap();
+        assert_eq!(
+            empty.len() as u32,
+            u32::from_le_bytes(empty[8..12].try_into().unwrap())
+        );
+        // a scene with no nodes isn't valid glTF
+        assert!(!String::from_utf8_lossy(&empty).contains("scene"));
+        assert_eq!(
+            ExportFormat::from_path(Path::new("terrain.GLB")),
+            Some(ExportFormat::Glb)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("terrain.png")), None);
+    }
+}
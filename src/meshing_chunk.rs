@@ -1,101 +1,1041 @@
 use crate::chunk::{Chunk, ChunkShape, CHUNK_DIM};
+use crate::light::{transmits_light, ChunkLight, Light, MAX_LIGHT};
+use crate::material::{VoxelFace, VoxelMaterialRegistry};
+use crate::storage::ChunkStorage;
 use crate::voxel::{Voxel, EMPTY};
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::render::render_resource::PrimitiveTopology;
+use block_mesh::Voxel as MeshableVoxel;
 use block_mesh::*;
-use ndcopy::copy3;
-use ndshape::{ConstShape, ConstShape3u32};
-use std::iter::zip;
+use ndshape::{ConstShape, ConstShape3u32, RuntimeShape, Shape};
 
 const MESH_CHUNK_DIM: u32 = CHUNK_DIM + 2;
 type MeshChunkShape = ConstShape3u32<MESH_CHUNK_DIM, MESH_CHUNK_DIM, MESH_CHUNK_DIM>;
 
+/// Each quad's texture array layer is carried to the voxel shader in the U
+/// texture coordinate, offset by this much per layer. Quads are never wider
+/// than a chunk, so their own U coordinates stay well below it, and the
+/// repeating sampler ignores the offset.
+pub const TEXTURE_LAYER_STRIDE: f32 = 64.;
+
+/// The coarsest level of detail chunks are meshed at, in voxels across each
+/// cell of the downsampled chunk.
+pub const MAX_LOD_SCALE: u32 = 8;
+
+// which face of a voxel each of the `RIGHT_HANDED_Y_UP_CONFIG` faces is
+const FACE_TEXTURES: [VoxelFace; 6] = [
+    VoxelFace::Side,
+    VoxelFace::Bottom,
+    VoxelFace::Side,
+    VoxelFace::Side,
+    VoxelFace::Top,
+    VoxelFace::Side,
+];
+
+/// How brightly each ambient occlusion value lights a vertex, from fully
+/// occluded to open.
+const AO_BRIGHTNESS: [f32; 4] = [0.4, 0.6, 0.8, 1.];
+
+/// How brightly each light level lights a vertex. Each level is about 80% as
+/// bright as the one above it, down to a faint glow in total darkness.
+const LIGHT_BRIGHTNESS: [f32; MAX_LIGHT as usize + 1] = [
+    0.083, 0.092, 0.102, 0.115, 0.132, 0.152, 0.178, 0.209, 0.249, 0.299, 0.361, 0.439, 0.536,
+    0.658, 0.81, 1.,
+];
+
+/// A voxel along with the ambient occlusion and light at the corners of each
+/// of its faces, so that greedy meshing only merges faces that are shaded
+/// alike. Faces hidden by a neighbour are left fully occluded and dark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ShadedVoxel {
+    voxel: Voxel,
+    // looked up from the voxel's material
+    visibility: VoxelVisibility,
+    // for four corners of each of the six faces, the occlusion in the low
+    // two bits and the light level above them
+    corners: [[u8; 4]; 6],
+}
+
+impl ShadedVoxel {
+    /// The ambient occlusion and light level at each corner of a face.
+    fn face_shading(&self, face: usize) -> [(usize, usize); 4] {
+        self.corners[face].map(|corner| ((corner & 3) as usize, (corner >> 2) as usize))
+    }
+
+    /// How brightly each corner of a face is lit.
+    fn face_brightness(&self, face: usize) -> [f32; 4] {
+        self.face_shading(face)
+            .map(|(occlusion, level)| AO_BRIGHTNESS[occlusion] * LIGHT_BRIGHTNESS[level])
+    }
+}
+
+impl MeshableVoxel for ShadedVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.visibility
+    }
+}
+
+/// Merges faces into quads the same way as block-mesh's `VoxelMerger`, but
+/// compares only the shading of the face being meshed, so voxels that are
+/// shaded differently on their other faces still merge.
+struct ShadingMerger;
+
+impl ShadingMerger {
+    /// Which of the `RIGHT_HANDED_Y_UP_CONFIG` faces is being meshed. Samples
+    /// are laid out x first, then y, then z, so the axis of the face's normal
+    /// is given by how its stride ranks against the other two.
+    fn face_index(face_strides: &FaceStrides) -> usize {
+        let n_stride = face_strides.n_stride;
+        let axis = [face_strides.u_stride, face_strides.v_stride]
+            .into_iter()
+            .filter(|stride| *stride < n_stride)
+            .count();
+        // the negative faces come first
+        if face_strides.visibility_offset == n_stride {
+            axis + 3
+        } else {
+            axis
+        }
+    }
+
+    fn face_needs_mesh(
+        voxels: &[ShadedVoxel],
+        visited: &[bool],
+        index: u32,
+        visibility_offset: u32,
+    ) -> bool {
+        let visibility = voxels[index as usize].get_visibility();
+        if visibility == VoxelVisibility::Empty || visited[index as usize] {
+            return false;
+        }
+        let neighbour = voxels[index.wrapping_add(visibility_offset) as usize];
+        match neighbour.get_visibility() {
+            VoxelVisibility::Empty => true,
+            VoxelVisibility::Translucent => visibility == VoxelVisibility::Opaque,
+            VoxelVisibility::Opaque => false,
+        }
+    }
+}
+
+impl MergeStrategy for ShadingMerger {
+    type Voxel = ShadedVoxel;
+
+    unsafe fn find_quad(
+        min_index: u32,
+        max_width: u32,
+        max_height: u32,
+        face_strides: &FaceStrides,
+        voxels: &[ShadedVoxel],
+        visited: &[bool],
+    ) -> (u32, u32) {
+        let visibility_offset = face_strides.visibility_offset;
+        let face = Self::face_index(face_strides);
+        let merge_value = |index: u32| {
+            let sample = voxels[index as usize];
+            (sample.voxel, sample.corners[face])
+        };
+        let quad_value = merge_value(min_index);
+        let row_width = |start: u32, max_width: u32| {
+            let mut width = 0;
+            let mut index = start;
+            while width < max_width
+                && Self::face_needs_mesh(voxels, visited, index, visibility_offset)
+                && merge_value(index) == quad_value
+            {
+                width += 1;
+                index = index.wrapping_add(face_strides.u_stride);
+            }
+            width
+        };
+
+        // the widest row from the first voxel, then as many rows of the same
+        // width as fit above it
+        let width = row_width(min_index, max_width);
+        let mut height = 1;
+        let mut row = min_index.wrapping_add(face_strides.v_stride);
+        while height < max_height && row_width(row, width) == width {
+            height += 1;
+            row = row.wrapping_add(face_strides.v_stride);
+        }
+        (width, height)
+    }
+}
+
 pub struct MeshingChunk {
-    samples: [Voxel; MeshChunkShape::USIZE],
+    // the samples form a cube this many across: a chunk's worth of cells,
+    // and a cell of padding on every side
+    dim: u32,
+    // how many voxels across each cell is
+    scale: u32,
+    samples: Vec<Voxel>,
+    // None where the light isn't known
+    lights: Vec<Option<Light>>,
+    // the sides, in the order of `Directions::all` after the chunk itself,
+    // whose padding is treated as empty
+    skirts: [bool; 6],
 }
 
 impl MeshingChunk {
-    // these correspond to each direction in directions.rs
+    // these correspond to each direction in directions.rs: the size of the
+    // region copied from the chunk in that direction, where the region starts
+    // in that chunk, and where it lands in the samples
     pub const COPY_SHAPES: [([u32; 3], [u32; 3], [u32; 3]); 7] = [
         (ChunkShape::ARRAY, [0, 0, 0], [1, 1, 1]),
-        ([1, 32, 32], [31, 0, 0], [33, 1, 1]),
-        ([1, 32, 32], [0, 0, 0], [0, 1, 1]),
-        ([32, 1, 32], [0, 31, 0], [1, 33, 1]),
-        ([32, 1, 32], [0, 0, 0], [1, 0, 1]),
-        ([32, 32, 1], [0, 0, 31], [1, 1, 33]),
-        ([32, 32, 1], [0, 0, 0], [1, 1, 0]),
+        ([1, 32, 32], [0, 0, 0], [33, 1, 1]),
+        ([1, 32, 32], [31, 0, 0], [0, 1, 1]),
+        ([32, 1, 32], [0, 0, 0], [1, 33, 1]),
+        ([32, 1, 32], [0, 31, 0], [1, 0, 1]),
+        ([32, 32, 1], [0, 0, 0], [1, 1, 33]),
+        ([32, 32, 1], [0, 0, 31], [1, 1, 0]),
     ];
 
-    pub fn new(chunks: [Option<&Chunk>; 7]) -> Self {
+    /// Copies a chunk and the facing sides of its neighbours, in the order
+    /// of `Directions::all`, into the samples. Every sample is lit as if
+    /// under open sky until light is given with `with_light`.
+    pub fn new<S: ChunkStorage>(chunks: [Option<&Chunk<S>>; 7]) -> Self {
         let mut mesh_chunk = Self {
-            samples: [EMPTY; MeshChunkShape::USIZE],
-        };
-        let iter = zip(chunks, Self::COPY_SHAPES);
-        for (chunk, (copy_shape, chunk_offset, meshing_offset)) in iter {
-            if let Some(valid_chunk) = chunk {
-                copy3(
-                    copy_shape,
-                    &valid_chunk.voxel_data,
-                    &ChunkShape {},
-                    chunk_offset,
-                    &mut mesh_chunk.samples,
-                    &MeshChunkShape {},
-                    meshing_offset,
-                );
+            dim: MESH_CHUNK_DIM,
+            scale: 1,
+            samples: vec![EMPTY; MeshChunkShape::USIZE],
+            lights: vec![Some(Light::SKY); MeshChunkShape::USIZE],
+            skirts: [false; 6],
+        };
+        Self::for_each_copy(|direction, sample, local| {
+            if let Some(chunk) = chunks[direction] {
+                mesh_chunk.samples[sample] = chunk.get(local);
             }
-        }
+        });
         mesh_chunk
     }
 
-    pub fn mesh(&self) -> Mesh {
+    /// Copies the light of a chunk and its neighbours, in the same order as
+    /// `new`. Samples from neighbours that aren't lit are left unknown.
+    pub fn with_light(mut self, lights: [Option<&ChunkLight>; 7]) -> Self {
+        self.lights.fill(None);
+        Self::for_each_copy(|direction, sample, local| {
+            if let Some(light) = lights[direction] {
+                self.lights[sample] = Some(light.get(local));
+            }
+        });
+        self
+    }
+
+    /// Calls `copy` with the direction, sample index and position within that
+    /// direction's chunk of every sample copied from a chunk.
+    fn for_each_copy(mut copy: impl FnMut(usize, usize, [u32; 3])) {
+        for (direction, (copy_shape, chunk_offset, meshing_offset)) in
+            Self::COPY_SHAPES.into_iter().enumerate()
+        {
+            for x in 0..copy_shape[0] {
+                for y in 0..copy_shape[1] {
+                    for z in 0..copy_shape[2] {
+                        let sample = <MeshChunkShape as ConstShape<3>>::linearize([
+                            meshing_offset[0] + x,
+                            meshing_offset[1] + y,
+                            meshing_offset[2] + z,
+                        ]);
+                        let local = [
+                            chunk_offset[0] + x,
+                            chunk_offset[1] + y,
+                            chunk_offset[2] + z,
+                        ];
+                        copy(direction, sample as usize, local);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Meshes the faces of the chunk's voxels on the given sides even where
+    /// the neighbouring chunk hides them. These faces close the gaps between
+    /// chunks meshed at different levels of detail.
+    pub fn with_skirts(mut self, sides: [bool; 6]) -> Self {
+        self.skirts = sides;
+        self
+    }
+
+    /// Downsamples the chunk into cells `scale` voxels across, for meshing
+    /// at a lower level of detail. Each cell is filled with the most common
+    /// material of its voxels if at least half of them are filled, and takes
+    /// the brightest of their light. The padding only holds a single layer
+    /// of voxels, so padding cells are sampled from that. Chunks that are
+    /// already downsampled are left as they are.
+    pub fn downsample(self, scale: u32) -> Self {
+        if scale <= 1 || self.scale != 1 {
+            return self;
+        }
+        let fine_shape = self.shape();
+        let dim = CHUNK_DIM / scale + 2;
+        let shape = RuntimeShape::<u32, 3>::new([dim; 3]);
+        // the samples a cell covers along one axis
+        let range = |cell: u32| {
+            if cell == 0 {
+                0..1
+            } else if cell == dim - 1 {
+                MESH_CHUNK_DIM - 1..MESH_CHUNK_DIM
+            } else {
+                1 + (cell - 1) * scale..1 + cell * scale
+            }
+        };
+        let mut samples = Vec::with_capacity(shape.usize());
+        let mut lights = Vec::with_capacity(shape.usize());
+        for index in 0..shape.size() {
+            let [x, y, z] = shape.delinearize(index);
+            let mut counts: Vec<(Voxel, u32)> = Vec::new();
+            let mut total = 0;
+            let mut light: Option<Light> = None;
+            for fine_x in range(x) {
+                for fine_y in range(y) {
+                    for fine_z in range(z) {
+                        let fine = fine_shape.linearize([fine_x, fine_y, fine_z]) as usize;
+                        total += 1;
+                        let voxel = self.samples[fine];
+                        if voxel != EMPTY {
+                            match counts.iter_mut().find(|(other, _)| *other == voxel) {
+                                Some((_, count)) => *count += 1,
+                                None => counts.push((voxel, 1)),
+                            }
+                        }
+                        if let Some(fine_light) = self.lights[fine] {
+                            let brightest = light.unwrap_or_default();
+                            light = Some(Light {
+                                sky: brightest.sky.max(fine_light.sky),
+                                block: brightest.block.max(fine_light.block),
+                            });
+                        }
+                    }
+                }
+            }
+            let filled: u32 = counts.iter().map(|(_, count)| count).sum();
+            let most_common = counts
+                .iter()
+                .rev()
+                .max_by_key(|(_, count)| *count)
+                .map_or(EMPTY, |(voxel, _)| *voxel);
+            samples.push(if filled * 2 >= total {
+                most_common
+            } else {
+                EMPTY
+            });
+            lights.push(light);
+        }
+        Self {
+            dim,
+            scale,
+            samples,
+            lights,
+            skirts: self.skirts,
+        }
+    }
+
+    fn shape(&self) -> RuntimeShape<u32, 3> {
+        RuntimeShape::<u32, 3>::new([self.dim; 3])
+    }
+
+    fn sample_index(&self, position: IVec3) -> Option<usize> {
+        if position.cmplt(IVec3::ZERO).any() || position.cmpge(IVec3::splat(self.dim as i32)).any()
+        {
+            return None;
+        }
+        Some(self.shape().linearize(position.as_uvec3().to_array()) as usize)
+    }
+
+    /// Which side's padding the sample at `position` is in, in the order of
+    /// `skirts`.
+    fn padding_side(&self, position: IVec3) -> Option<usize> {
+        let last = self.dim as i32 - 1;
+        (0..3).find_map(|axis| {
+            if position[axis] == last {
+                Some(axis * 2)
+            } else if position[axis] == 0 {
+                Some(axis * 2 + 1)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn visibility(&self, position: IVec3, registry: &VoxelMaterialRegistry) -> VoxelVisibility {
+        self.sample_index(position)
+            .map_or(VoxelVisibility::Empty, |sample| {
+                registry.get_visibility(self.samples[sample])
+            })
+    }
+
+    /// Whether the sample at `position` shades the corners of the faces
+    /// around it. Translucent voxels let light past, so only opaque ones do.
+    fn is_solid(&self, position: IVec3, registry: &VoxelMaterialRegistry) -> bool {
+        self.visibility(position, registry) == VoxelVisibility::Opaque
+    }
+
+    /// The light of the sample at `position`, if it is known and light can
+    /// pass through it.
+    fn light(&self, position: IVec3, registry: &VoxelMaterialRegistry) -> Option<Light> {
+        let sample = self.sample_index(position)?;
+        if !transmits_light(self.samples[sample], registry) {
+            return None;
+        }
+        self.lights[sample]
+    }
+
+    /// The shading at each corner of one face of the voxel at `position`, in
+    /// the order of `OrientedBlockFace::quad_corners`. The ambient occlusion
+    /// goes from 0 where the corner is boxed in by its neighbours up to 3
+    /// where it is open, and the light level is the average of the voxels
+    /// touching the corner in front of the face.
+    fn face_shading(
+        &self,
+        position: IVec3,
+        face: &OrientedBlockFace,
+        registry: &VoxelMaterialRegistry,
+    ) -> [u8; 4] {
+        let unit = face.quad_corners(&UnorientedQuad {
+            minimum: [1; 3],
+            width: 1,
+            height: 1,
+        });
+        // block-mesh uses its own version of glam
+        let u = IVec3::from_array((unit[1] - unit[0]).as_ivec3().to_array());
+        let v = IVec3::from_array((unit[2] - unit[0]).as_ivec3().to_array());
+        let front = position + IVec3::from_array(face.signed_normal().to_array());
+        [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(du, dv)| {
+            let side_u = self.is_solid(front + u * du, registry);
+            let side_v = self.is_solid(front + v * dv, registry);
+            let corner = self.is_solid(front + u * du + v * dv, registry);
+            let occlusion = if side_u && side_v {
+                0
+            } else {
+                3 - side_u as u8 - side_v as u8 - corner as u8
+            };
+            // light can't reach the corner voxel past two solid sides
+            let touching = [
+                Some(front),
+                Some(front + u * du),
+                Some(front + v * dv),
+                (!(side_u && side_v)).then_some(front + u * du + v * dv),
+            ];
+            let (mut total, mut count) = (0, 0);
+            for position in touching.into_iter().flatten() {
+                if let Some(light) = self.light(position, registry) {
+                    total += light.level() as usize;
+                    count += 1;
+                }
+            }
+            // rounded to the nearest level, or full light with none to average
+            let level = (total + count / 2)
+                .checked_div(count)
+                .map_or(MAX_LIGHT, |level| level as u8);
+            occlusion | level << 2
+        })
+    }
+
+    fn shaded_samples(&self, registry: &VoxelMaterialRegistry) -> Vec<ShadedVoxel> {
         let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        let shape = self.shape();
+        (0..shape.size())
+            .map(|index| {
+                let voxel = self.samples[index as usize];
+                let position = IVec3::from(shape.delinearize(index).map(|c| c as i32));
+                let skirted = self
+                    .padding_side(position)
+                    .is_some_and(|side| self.skirts[side]);
+                let visibility = if skirted {
+                    VoxelVisibility::Empty
+                } else {
+                    registry.get_visibility(voxel)
+                };
+                let mut corners = [[0; 4]; 6];
+                // only faces of the chunk's own voxels are meshed
+                let inside = position.cmpge(IVec3::ONE).all()
+                    && position.cmple(IVec3::splat(self.dim as i32 - 2)).all();
+                if inside && visibility != VoxelVisibility::Empty {
+                    for (index, face) in faces.iter().enumerate() {
+                        let normal = IVec3::from_array(face.signed_normal().to_array());
+                        let neighbour = position + normal;
+                        let skirt = self
+                            .padding_side(neighbour)
+                            .is_some_and(|side| self.skirts[side]);
+                        if self.is_solid(neighbour, registry) && !skirt {
+                            continue;
+                        }
+                        corners[index] = self.face_shading(position, face, registry);
+                    }
+                }
+                ShadedVoxel {
+                    voxel,
+                    visibility,
+                    corners,
+                }
+            })
+            .collect()
+    }
+
+    /// Meshes the chunk's opaque and translucent voxels separately, so that
+    /// translucent ones can be drawn after everything behind them.
+    pub fn mesh(&self, registry: &VoxelMaterialRegistry) -> ChunkMeshes {
+        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        let texture_layers = registry.texture_layers();
+        let samples = self.shaded_samples(registry);
 
-        let mut buffer = GreedyQuadsBuffer::new(self.samples.len());
+        let mut buffer = GreedyQuadsBuffer::new(samples.len());
 
-        greedy_quads(
-            &self.samples,
-            &MeshChunkShape {},
+        let shape = self.shape();
+        greedy_quads_with_merge_strategy::<_, _, ShadingMerger>(
+            &samples,
+            &shape,
             [0; 3],
-            [33; 3],
+            [self.dim - 1; 3],
             &faces,
             &mut buffer,
         );
+        // cells are scaled up to their size in voxels, and moved so that the
+        // first cell after the padding still starts a voxel in
+        let scale = self.scale as f32;
+        let cell_offset = 1. - scale;
 
-        let num_indices = buffer.quads.num_quads() * 6;
-        let num_vertices = buffer.quads.num_quads() * 4;
-
-        let mut indices = Vec::with_capacity(num_indices);
-        let mut positions = Vec::with_capacity(num_vertices);
-        let mut normals = Vec::with_capacity(num_vertices);
+        let mut opaque = MeshBuilder::default();
+        let mut translucent = MeshBuilder::default();
 
-        for (group, face) in buffer.quads.groups.into_iter().zip(faces.into_iter()) {
+        let groups = buffer
+            .quads
+            .groups
+            .into_iter()
+            .zip(faces)
+            .zip(FACE_TEXTURES)
+            .enumerate();
+        for (face_index, ((group, face), voxel_face)) in groups {
             for quad in group.into_iter() {
-                indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-                positions.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
-                normals.extend_from_slice(&face.quad_mesh_normals());
+                // quads are only merged across voxels of the same material and
+                // shading, so any voxel of the quad gives its color and the
+                // brightness at its corners
+                let sample = samples[shape.linearize(quad.minimum) as usize];
+                let voxel = sample.voxel;
+                let brightness = sample.face_brightness(face_index);
+                let builder = if sample.visibility == VoxelVisibility::Translucent {
+                    &mut translucent
+                } else {
+                    &mut opaque
+                };
+                let start = builder.positions.len() as u32;
+                builder
+                    .indices
+                    .extend_from_slice(&quad_indices(&face, start, brightness));
+                let positions = face.quad_mesh_positions(&quad, scale);
+                builder
+                    .positions
+                    .extend(positions.map(|position| position.map(|c| c + cell_offset)));
+                builder.normals.extend_from_slice(&face.quad_mesh_normals());
+                let color = registry
+                    .get(voxel.material)
+                    .map_or(Color::FUCHSIA, |material| material.get_color())
+                    .as_linear_rgba_f32();
+                builder.colors.extend(brightness.map(|brightness| {
+                    [
+                        color[0] * brightness,
+                        color[1] * brightness,
+                        color[2] * brightness,
+                        color[3],
+                    ]
+                }));
+                // texture coordinates count voxels across the quad, so the
+                // texture repeats once per voxel instead of stretching
+                let layer = texture_layers
+                    .get(voxel.material as usize)
+                    .map_or(0, |layers| layers[voxel_face as usize]);
+                let offset = layer as f32 * TEXTURE_LAYER_STRIDE;
+                let tex_coords = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, &quad);
+                builder
+                    .uvs
+                    .extend(tex_coords.map(|[u, v]| [u * scale + offset, v * scale]));
             }
         }
 
+        ChunkMeshes {
+            opaque: opaque.build(),
+            translucent: translucent.build(),
+        }
+    }
+}
+
+/// The meshes of a chunk's opaque and translucent voxels.
+pub struct ChunkMeshes {
+    pub opaque: Mesh,
+    pub translucent: Mesh,
+}
+
+/// The vertices of a mesh as it is built, quad by quad.
+#[derive(Default)]
+struct MeshBuilder {
+    indices: Vec<u32>,
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    uvs: Vec<[f32; 2]>,
+}
+
+impl MeshBuilder {
+    fn build(self) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
 
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_POSITION,
-            VertexAttributeValues::Float32x3(positions),
+            VertexAttributeValues::Float32x3(self.positions),
         );
 
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_NORMAL,
-            VertexAttributeValues::Float32x3(normals),
+            VertexAttributeValues::Float32x3(self.normals),
+        );
+
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            VertexAttributeValues::Float32x4(self.colors),
         );
 
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_UV_0,
-            VertexAttributeValues::Float32x2(vec![[0.0; 2]; num_vertices]),
+            VertexAttributeValues::Float32x2(self.uvs),
         );
 
-        mesh.set_indices(Some(Indices::U32(indices.clone())));
+        mesh.set_indices(Some(Indices::U32(self.indices)));
 
         mesh
     }
 }
+
+/// The indices of a quad's two triangles. The quad is split along the
+/// diagonal whose corners are brighter, so that shading fades evenly across
+/// the quad rather than along the split.
+fn quad_indices(face: &OrientedBlockFace, start: u32, brightness: [f32; 4]) -> [u32; 6] {
+    let indices = face.quad_mesh_indices(start);
+    if brightness[0] + brightness[3] <= brightness[1] + brightness[2] {
+        return indices;
+    }
+    // the same winding, split from the first corner to the last instead
+    if indices[1] == start + 1 {
+        [start, start + 1, start + 3, start, start + 3, start + 2]
+    } else {
+        [start, start + 3, start + 1, start, start + 2, start + 3]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::Chunk;
+    use crate::light::{ChunkLight, Light};
+    use crate::material::{VoxelFace, VoxelMaterial, VoxelMaterialRegistry, DIRT, GRASS, STONE};
+    use crate::meshing_chunk::{quad_indices, MeshChunkShape, MeshingChunk, TEXTURE_LAYER_STRIDE};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::voxel::{Voxel, EMPTY};
+    use bevy::prelude::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use block_mesh::{UnorientedQuad, RIGHT_HANDED_Y_UP_CONFIG};
+    use ndshape::ConstShape;
+
+    #[test]
+    fn padding_comes_from_the_facing_side_of_neighbours() {
+        // a different material on each side of each chunk, so the padding
+        // shows which side it was copied from
+        let sides = |position: IVec3| {
+            let local = position.rem_euclid(IVec3::splat(32));
+            let material = if local.x == 0 {
+                STONE
+            } else if local.x == 31 {
+                DIRT
+            } else {
+                GRASS
+            };
+            Voxel::new(material)
+        };
+        let center: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &sides);
+        let meshing = MeshingChunk::new([Some(&center); 7]);
+        let sample =
+            |position: [u32; 3]| meshing.samples[MeshChunkShape::linearize(position) as usize];
+        // the neighbour in +x touches the chunk with its low side, and the
+        // neighbour in -x with its high side
+        assert_eq!(sample([33, 5, 5]).material, STONE);
+        assert_eq!(sample([0, 5, 5]).material, DIRT);
+        assert_eq!(sample([5, 0, 5]).material, GRASS);
+    }
+
+    #[test]
+    fn mesh_colors_come_from_materials() {
+        let registry = VoxelMaterialRegistry::default();
+        let layers = |position: IVec3| match position.y {
+            ..=2 => Voxel::new(STONE),
+            3..=4 => Voxel::new(DIRT),
+            5 => Voxel::new(GRASS),
+            _ => EMPTY,
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &layers);
+        let mesh = MeshingChunk::new([Some(&chunk), None, None, None, None, None, None])
+            .mesh(&registry)
+            .opaque;
+        let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        else {
+            panic!("mesh has no vertex colors");
+        };
+        let expected: Vec<[f32; 4]> = [STONE, DIRT, GRASS]
+            .map(|id| registry.get(id).unwrap().get_color().as_linear_rgba_f32())
+            .to_vec();
+        assert!(!colors.is_empty());
+        assert!(expected.iter().all(|color| colors.contains(color)));
+        assert!(colors.iter().all(|color| expected.contains(color)));
+    }
+
+    #[test]
+    fn texture_coordinates_tile_across_merged_quads() {
+        let mut registry = VoxelMaterialRegistry::default();
+        let grass = VoxelMaterial::new("Grass", Color::GREEN)
+            .with_face_texture(VoxelFace::Side, "grass_side.png")
+            .with_face_texture(VoxelFace::Top, "grass_top.png");
+        registry.register("grass", grass);
+        let [top_layer, side_layer, _] = registry.texture_layers()[GRASS as usize];
+        // a 4 by 4 slab, one voxel thick
+        let slab = |position: IVec3| {
+            if position.y == 0 && position.x < 4 && position.z < 4 {
+                Voxel::new(GRASS)
+            } else {
+                EMPTY
+            }
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &slab);
+        let mesh = MeshingChunk::new([Some(&chunk), None, None, None, None, None, None])
+            .mesh(&registry)
+            .opaque;
+        let (
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x2(uvs)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        )
+        else {
+            panic!("mesh has no normals or texture coordinates");
+        };
+        assert_eq!(normals.len(), uvs.len());
+        for (quad_normals, quad_uvs) in normals.chunks(4).zip(uvs.chunks(4)) {
+            let layer = (quad_uvs[0][0] / TEXTURE_LAYER_STRIDE).floor() as u32;
+            let span = |axis: usize| {
+                let values = quad_uvs.iter().map(|uv| uv[axis]);
+                values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min)
+            };
+            match quad_normals[0] {
+                [0., 1., 0.] => {
+                    assert_eq!(layer, top_layer);
+                    assert_eq!((span(0), span(1)), (4., 4.));
+                }
+                [0., -1., 0.] => assert_eq!(layer, 0),
+                _ => {
+                    assert_eq!(layer, side_layer);
+                    assert_eq!((span(0), span(1)), (4., 1.));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn occlusion_darkens_corners_and_splits_quads() {
+        let registry = VoxelMaterialRegistry::default();
+        // an 8 by 8 floor with a single voxel standing on it
+        let floor = |position: IVec3| {
+            let on_floor = position.x < 8 && position.z < 8;
+            if on_floor && (position.y == 0 || position == IVec3::new(4, 1, 4)) {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &floor);
+        let mesh = MeshingChunk::new([Some(&chunk), None, None, None, None, None, None])
+            .mesh(&registry)
+            .opaque;
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x4(colors)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR),
+        )
+        else {
+            panic!("mesh is missing attributes");
+        };
+        let stone = registry
+            .get(STONE)
+            .unwrap()
+            .get_color()
+            .as_linear_rgba_f32();
+
+        // mesh positions are offset by the padding around the chunk, so the
+        // top of the floor is at 2
+        let floor_top: Vec<usize> = (0..positions.len())
+            .step_by(4)
+            .filter(|quad| normals[*quad] == [0., 1., 0.] && positions[*quad][1] == 2.)
+            .collect();
+        let area: f32 = floor_top
+            .iter()
+            .map(|quad| {
+                let [a, b, c] = [0, 1, 2].map(|corner| Vec3::from(positions[quad + corner]));
+                (b - a).length() * (c - a).length()
+            })
+            .sum();
+        assert_eq!(area, 63.);
+        // corners around the voxel are shaded, so can't merge with the rest
+        assert!(floor_top.len() > 2);
+        for (position, color) in positions.iter().zip(colors) {
+            let position = Vec3::from(*position);
+            if position.y != 2. {
+                continue;
+            }
+            // only vertices touching the standing voxel are shaded by it
+            let near_voxel = (position.xz() - Vec2::splat(5.5)).abs().max_element() <= 0.5;
+            if near_voxel {
+                assert!(color[0] < stone[0]);
+            } else {
+                assert_eq!(*color, stone);
+            }
+            assert_eq!(color[3], stone[3]);
+        }
+    }
+
+    #[test]
+    fn light_darkens_unlit_vertices() {
+        let registry = VoxelMaterialRegistry::default();
+        let floor = |position: IVec3| {
+            if position.y == 0 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &floor);
+        // only the voxels above the first four rows of the floor are lit
+        let mut light = ChunkLight::dark();
+        for x in 0..4 {
+            for z in 0..32 {
+                light.set([x, 1, z], Light::SKY);
+            }
+        }
+        let mesh = MeshingChunk::new([Some(&chunk), None, None, None, None, None, None])
+            .with_light([Some(&light), None, None, None, None, None, None])
+            .mesh(&registry)
+            .opaque;
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x4(colors)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR),
+        )
+        else {
+            panic!("mesh is missing attributes");
+        };
+        let stone = registry
+            .get(STONE)
+            .unwrap()
+            .get_color()
+            .as_linear_rgba_f32();
+        let top: Vec<(&[f32; 3], &[f32; 4])> = positions
+            .iter()
+            .zip(colors)
+            .zip(normals)
+            .filter(|(_, normal)| **normal == [0., 1., 0.])
+            .map(|(vertex, _)| vertex)
+            .collect();
+        // mesh positions are offset by the padding, so vertices up to 4 only
+        // touch lit voxels, and vertices from 6 only touch dark ones
+        assert!(top.iter().any(|(position, _)| position[0] >= 6.));
+        for (position, color) in top {
+            if position[0] <= 4. {
+                assert_eq!(*color, stone);
+            } else if position[0] >= 6. {
+                assert!(color[0] < stone[0] * 0.1);
+                assert_eq!(color[3], stone[3]);
+            }
+        }
+    }
+
+    #[test]
+    fn translucent_voxels_are_meshed_separately() {
+        let mut registry = VoxelMaterialRegistry::default();
+        let glass =
+            VoxelMaterial::new("Glass", Color::rgba(1., 1., 1., 0.3)).with_translucent(true);
+        let glass = registry.register("glass", glass).unwrap();
+        // a stone voxel with two glass voxels beside it in +x
+        let row = move |position: IVec3| match position.to_array() {
+            [4, 0, 4] => Voxel::new(STONE),
+            [5..=6, 0, 4] => Voxel::new(glass),
+            _ => EMPTY,
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &row);
+        let meshes =
+            MeshingChunk::new([Some(&chunk), None, None, None, None, None, None]).mesh(&registry);
+        // the x coordinates of the quads facing along x, offset by the padding
+        let x_faces = |mesh: &Mesh| {
+            let (
+                Some(VertexAttributeValues::Float32x3(positions)),
+                Some(VertexAttributeValues::Float32x3(normals)),
+            ) = (
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+                mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            )
+            else {
+                panic!("mesh is missing attributes");
+            };
+            let mut faces: Vec<(f32, f32)> = (0..positions.len())
+                .step_by(4)
+                .filter(|quad| normals[*quad][0] != 0.)
+                .map(|quad| (positions[quad][0], normals[quad][0]))
+                .collect();
+            faces.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            faces
+        };
+        // stone is seen through the glass, but glass isn't drawn against
+        // the stone or between glass voxels
+        assert_eq!(x_faces(&meshes.opaque), [(5., -1.), (6., 1.)]);
+        assert_eq!(x_faces(&meshes.translucent), [(8., 1.)]);
+    }
+
+    /// The positions, normals and texture coordinates of every quad of the
+    /// mesh, a vertex at a time.
+    fn quads(mesh: &Mesh) -> Vec<[(Vec3, Vec3, Vec2); 4]> {
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+            Some(VertexAttributeValues::Float32x2(uvs)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+            mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        )
+        else {
+            panic!("mesh is missing attributes");
+        };
+        (0..positions.len())
+            .step_by(4)
+            .map(|quad| {
+                [0, 1, 2, 3].map(|corner| {
+                    let vertex = quad + corner;
+                    (
+                        Vec3::from(positions[vertex]),
+                        Vec3::from(normals[vertex]),
+                        Vec2::from(uvs[vertex]),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn downsampled_chunks_cover_the_same_space() {
+        let registry = VoxelMaterialRegistry::default();
+        // ground up to y = 9, which rounds down to 8 in cells of 4
+        let ground = |position: IVec3| {
+            if position.y < 9 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &ground);
+        let coarse = MeshingChunk::new([Some(&chunk), None, None, None, None, None, None])
+            .downsample(4)
+            .mesh(&registry)
+            .opaque;
+        let coarse_quads = quads(&coarse);
+        // the mesh still spans the chunk, a voxel in from the padding
+        let positions = coarse_quads
+            .iter()
+            .flatten()
+            .map(|(position, _, _)| *position);
+        let min = positions.clone().fold(Vec3::MAX, Vec3::min);
+        let max = positions.fold(Vec3::MIN, Vec3::max);
+        assert_eq!((min, max), (Vec3::ONE, Vec3::new(33., 9., 33.)));
+        for quad in coarse_quads {
+            let size = (quad[3].0 - quad[0].0).abs();
+            let span = (quad[3].2 - quad[0].2).abs();
+            // textures still repeat once per voxel
+            let mut sides: Vec<f32> = size.to_array().into_iter().filter(|c| *c != 0.).collect();
+            let mut spans = span.to_array().to_vec();
+            sides.sort_by(f32::total_cmp);
+            spans.sort_by(f32::total_cmp);
+            assert_eq!(sides, spans);
+            assert!(sides.iter().all(|side| side % 4. == 0.));
+        }
+    }
+
+    #[test]
+    fn downsampling_bumpy_ground_saves_quads() {
+        let registry = VoxelMaterialRegistry::default();
+        let bumps = |position: IVec3| {
+            if position.y < 8 + (position.x + position.z) % 3 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &bumps);
+        let meshing = || MeshingChunk::new([Some(&chunk), None, None, None, None, None, None]);
+        let full = quads(&meshing().mesh(&registry).opaque).len();
+        let mut last = full;
+        for scale in [2, 4, 8] {
+            let coarse = quads(&meshing().downsample(scale).mesh(&registry).opaque).len();
+            assert!(coarse <= last);
+            last = coarse;
+        }
+        // the bumps are smaller than the coarsest cells, so smooth out
+        assert!(last * 100 < full);
+    }
+
+    #[test]
+    fn skirts_mesh_faces_hidden_by_neighbours() {
+        let registry = VoxelMaterialRegistry::default();
+        let solid = |_: IVec3| Voxel::new(STONE);
+        let chunk: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &solid);
+        let facing_x = |skirts: [bool; 6]| {
+            let mesh = MeshingChunk::new([Some(&chunk); 7])
+                .with_skirts(skirts)
+                .mesh(&registry)
+                .opaque;
+            quads(&mesh)
+                .iter()
+                .filter(|quad| quad[0].1.x != 0.)
+                .map(|quad| (quad[0].0.x, quad[0].1.x))
+                .collect::<Vec<(f32, f32)>>()
+        };
+        // a chunk surrounded by solid chunks has no faces at all
+        assert!(facing_x([false; 6]).is_empty());
+        let skirted = facing_x([true, false, false, false, false, false]);
+        assert!(!skirted.is_empty());
+        assert!(skirted.iter().all(|face| *face == (33., 1.)));
+    }
+
+    #[test]
+    fn quads_split_along_the_brighter_diagonal() {
+        let quad = UnorientedQuad {
+            minimum: [1; 3],
+            width: 1,
+            height: 1,
+        };
+        for face in RIGHT_HANDED_Y_UP_CONFIG.faces {
+            let corners = face.quad_mesh_positions(&quad, 1.).map(Vec3::from);
+            let triangle_normal = |triangle: &[u32]| {
+                let [a, b, c] = [0, 1, 2].map(|i| corners[triangle[i] as usize]);
+                (b - a).cross(c - a).normalize()
+            };
+            let default = quad_indices(&face, 0, [0.4, 1., 1., 1.]);
+            assert_eq!(default, face.quad_mesh_indices(0));
+            let flipped = quad_indices(&face, 0, [1., 0.6, 0.8, 1.]);
+            for triangle in flipped.chunks(3) {
+                assert!(triangle.contains(&0) && triangle.contains(&3));
+                // flipping keeps the triangles facing the same way
+                assert_eq!(triangle_normal(triangle), triangle_normal(&default[..3]));
+            }
+        }
+    }
+}
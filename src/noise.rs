@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+
+/// Seeded Perlin gradient noise. The same seed always gives the same noise,
+/// on every platform and with every version of our dependencies, since saved
+/// worlds only store how they differ from their regenerated terrain.
+#[derive(Clone)]
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        // a Fisher-Yates shuffle drawing from splitmix64, rather than from an
+        // rng whose output may change between versions of rand. The seed is
+        // hashed first so that nearby seeds, such as those of the layers of
+        // one terrain, don't draw overlapping runs of numbers.
+        let mut table: [u8; 256] = std::array::from_fn(|index| index as u8);
+        for index in (1..table.len()).rev() {
+            let draw = mix(mix(seed).wrapping_add(index as u64));
+            table.swap(index, (draw % (index as u64 + 1)) as usize);
+        }
+        Self {
+            permutation: std::array::from_fn(|index| table[index % 256]),
+        }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> u8 {
+        let p = &self.permutation;
+        p[p[p[(x & 255) as usize] as usize + (y & 255) as usize] as usize + (z & 255) as usize]
+    }
+
+    /// Noise in roughly -1..1, varying smoothly over a scale of one unit.
+    pub fn get2(&self, point: Vec2) -> f32 {
+        self.get3(point.extend(0.))
+    }
+
+    /// Noise in roughly -1..1, varying smoothly over a scale of one unit.
+    pub fn get3(&self, point: Vec3) -> f32 {
+        let cell = point.floor();
+        let [x, y, z] = cell.as_ivec3().to_array();
+        let local = point - cell;
+        let fade = local * local * local * (local * (local * 6. - 15.) + 10.);
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let offset = local - Vec3::new(dx as f32, dy as f32, dz as f32);
+            gradient(self.hash(x + dx, y + dy, z + dz), offset)
+        };
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+        lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z)
+    }
+
+    /// Fractal noise summing octaves of halving amplitude and doubling
+    /// frequency, normalized to roughly -1..1.
+    pub fn fbm2(&self, point: Vec2, octaves: u32) -> f32 {
+        self.fbm3(point.extend(0.), octaves)
+    }
+
+    /// Fractal noise summing octaves of halving amplitude and doubling
+    /// frequency, normalized to roughly -1..1.
+    pub fn fbm3(&self, point: Vec3, octaves: u32) -> f32 {
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut max = 0.;
+        for octave in 0..octaves {
+            // offset each octave so that their lattices don't line up with
+            // each other, or with the axes
+            let offset = Vec3::new(12.9898, 78.233, 37.719) * (octave + 1) as f32;
+            total += self.get3(point * (1 << octave) as f32 + offset) * amplitude;
+            max += amplitude;
+            amplitude *= 0.5;
+        }
+        total / max
+    }
+}
+
+/// The splitmix64 finalizer, which scrambles every bit of the input into
+/// every bit of the output.
+pub fn mix(value: u64) -> u64 {
+    let mut value = value.wrapping_add(0x9e3779b97f4a7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+/// A stream of random numbers drawn from splitmix64. Like `Perlin`, its
+/// output is fixed by its definition rather than by whichever version of rand
+/// is in use, so it can decide anything generated into the world.
+#[derive(Clone, Debug)]
+pub struct SplitMix {
+    state: u64,
+}
+
+impl SplitMix {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let value = mix(self.state);
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        value
+    }
+
+    /// A number in `0..count`, which must not be empty.
+    pub fn below(&mut self, count: u32) -> u32 {
+        // the high bits scaled into range, which is unbiased enough for
+        // counts this small
+        (((self.next_u64() >> 32) * count as u64) >> 32) as u32
+    }
+
+    /// A number in `0..1`.
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1 << 24) as f32
+    }
+
+    /// True with the given probability.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.unit() < probability
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        items.get(self.below(items.len() as u32) as usize)
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// one of the twelve edge directions of a cube, picked by the hash
+fn gradient(hash: u8, offset: Vec3) -> f32 {
+    let Vec3 { x, y, z } = offset;
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::noise::{mix, Perlin, SplitMix};
+    use bevy::prelude::*;
+
+    #[test]
+    fn noise_is_deterministic_and_bounded() {
+        let first = Perlin::new(42);
+        let second = Perlin::new(42);
+        let other = Perlin::new(43);
+        let mut differs = false;
+        for index in 0..1000 {
+            let point = Vec3::new(index as f32 * 0.37, index as f32 * -0.11, 5.5);
+            let value = first.fbm3(point, 4);
+            assert_eq!(value, second.fbm3(point, 4));
+            assert!((-1.5..=1.5).contains(&value));
+            differs |= value != other.fbm3(point, 4);
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn noise_is_zero_on_lattice_points() {
+        let noise = Perlin::new(7);
+        assert_eq!(noise.get3(Vec3::new(3., -4., 12.)), 0.);
+    }
+
+    #[test]
+    fn permutations_are_pinned_to_their_definition() {
+        // splitmix64's reference output for a state of zero
+        assert_eq!(mix(0), 0xe220a8397b1dcdaf);
+        let noise = Perlin::new(1);
+        assert_eq!(
+            noise.permutation[..8],
+            [232, 215, 34, 132, 180, 83, 237, 66]
+        );
+        assert_eq!(noise.permutation[..256], noise.permutation[256..]);
+    }
+
+    #[test]
+    fn splitmix_streams_are_pinned_to_their_definition() {
+        // splitmix64's reference output for a seed of zero
+        let mut stream = SplitMix::new(0);
+        assert_eq!(stream.next_u64(), 0xe220a8397b1dcdaf);
+        assert_eq!(stream.next_u64(), 0x6e789e6aa1b965f4);
+        assert_eq!(stream.next_u64(), 0x06c45d188009454f);
+        let mut stream = SplitMix::new(5);
+        for _ in 0..1000 {
+            assert!(stream.below(3) < 3);
+            assert!((0. ..1.).contains(&stream.unit()));
+        }
+        assert_eq!(stream.choose::<u8>(&[]), None);
+    }
+}
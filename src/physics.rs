@@ -0,0 +1,258 @@
+use crate::collision::{move_aabb, Aabb};
+use crate::world::World;
+use bevy::prelude::*;
+
+pub struct VoxelPhysicsPlugin;
+
+impl Plugin for VoxelPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelPhysicsSettings>()
+            .add_systems(FixedUpdate, step_voxel_bodies)
+            .add_systems(Update, interpolate_voxel_bodies);
+    }
+}
+
+#[derive(Resource)]
+pub struct VoxelPhysicsSettings {
+    /// downwards acceleration of every body, scaled by its `gravity_scale`
+    pub gravity: f32,
+}
+
+impl Default for VoxelPhysicsSettings {
+    fn default() -> Self {
+        Self { gravity: 25.0 }
+    }
+}
+
+/// Gives an entity velocity, gravity and friction, and keeps its collision
+/// box out of solid voxels.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct VoxelBody {
+    /// half the size of the collision box on each axis
+    pub half_extents: Vec3,
+    /// where the center of the collision box is, relative to the entity's
+    /// translation
+    pub offset: Vec3,
+    pub velocity: Vec3,
+    /// 0 for bodies that float, such as projectiles
+    pub gravity_scale: f32,
+    /// how quickly horizontal velocity dies away while on the ground, as the
+    /// fraction lost per second
+    pub friction: f32,
+    /// the body climbs ledges up to this tall while on the ground
+    pub step_height: f32,
+    /// whether the body came to rest on a voxel in its last step
+    pub grounded: bool,
+    /// the axes along which the body was stopped by a voxel in its last step
+    pub contacts: BVec3,
+}
+
+impl VoxelBody {
+    pub fn new(half_extents: Vec3) -> Self {
+        Self {
+            half_extents,
+            offset: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            gravity_scale: 1.,
+            friction: 0.9,
+            step_height: 0.,
+            grounded: false,
+            contacts: BVec3::FALSE,
+        }
+    }
+
+    /// Whether the body ran into a wall in its last step.
+    pub fn touching_wall(&self) -> bool {
+        self.contacts.x || self.contacts.z
+    }
+
+    pub fn aabb(&self, translation: Vec3) -> Aabb {
+        let center = translation + self.offset;
+        Aabb::new(center - self.half_extents, center + self.half_extents)
+    }
+
+    /// Advances the body by `delta_seconds`: applies gravity and friction,
+    /// then moves it through the world, stopping it against solid voxels.
+    /// Returns the entity's new translation.
+    pub fn step(
+        &mut self,
+        world: &World,
+        translation: Vec3,
+        gravity: f32,
+        delta_seconds: f32,
+    ) -> Vec3 {
+        self.velocity.y -= gravity * self.gravity_scale * delta_seconds;
+        if self.grounded {
+            let remaining = (1. - self.friction).clamp(0., 1.).powf(delta_seconds);
+            self.velocity.x *= remaining;
+            self.velocity.z *= remaining;
+        }
+
+        let aabb = self.aabb(translation);
+        let movement = move_aabb(world, aabb, self.velocity * delta_seconds, self.step_height);
+        // velocity into whatever stopped the body is lost
+        for axis in 0..3 {
+            if movement.blocked.test(axis) {
+                self.velocity[axis] = 0.;
+            }
+        }
+        self.grounded = movement.grounded;
+        self.contacts = movement.blocked;
+        translation + (movement.aabb.min - aabb.min)
+    }
+}
+
+/// Draws a body part of the way between its last two fixed steps, so that its
+/// motion looks smooth at any framerate. Bodies with this component are
+/// stepped from `current` rather than from their translation.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct InterpolatedBody {
+    /// the translation before the last fixed step
+    pub previous: Vec3,
+    /// the translation after the last fixed step
+    pub current: Vec3,
+}
+
+impl InterpolatedBody {
+    pub fn new(translation: Vec3) -> Self {
+        Self {
+            previous: translation,
+            current: translation,
+        }
+    }
+}
+
+/// Bodies are stepped at a fixed rate, so that how they fall and collide
+/// doesn't depend on the framerate.
+fn step_voxel_bodies(
+    time: Res<Time>,
+    settings: Res<VoxelPhysicsSettings>,
+    world: Res<World>,
+    mut query: Query<(
+        &mut Transform,
+        &mut VoxelBody,
+        Option<&mut InterpolatedBody>,
+    )>,
+) {
+    for (mut transform, mut body, interpolated) in &mut query {
+        let start = interpolated
+            .as_ref()
+            .map_or(transform.translation, |interpolated| interpolated.current);
+        let end = body.step(&world, start, settings.gravity, time.delta_seconds());
+        match interpolated {
+            Some(mut interpolated) => {
+                interpolated.previous = start;
+                interpolated.current = end;
+            }
+            None => transform.translation = end,
+        }
+    }
+}
+
+fn interpolate_voxel_bodies(
+    time: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &InterpolatedBody)>,
+) {
+    let fraction = time.overstep_percentage();
+    for (mut transform, interpolated) in &mut query {
+        transform.translation = interpolated.previous.lerp(interpolated.current, fraction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::STONE;
+    use crate::physics::{InterpolatedBody, VoxelBody, VoxelPhysicsPlugin};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::World;
+    use bevy::prelude::*;
+
+    // ground below y = 0, and a wall at x = 8
+    fn test_world() -> World {
+        let terrain = |position: IVec3| {
+            if position.y < 0 || position.x == 8 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::new(VoxelMapSettings::default(), terrain);
+        for y in -1..=0 {
+            world.load_chunk(ChunkPosition::new(0, y, 0));
+        }
+        world
+    }
+
+    // steps the body at 64Hz, stopping early if it touches a wall
+    fn simulate(body: &mut VoxelBody, world: &World, mut translation: Vec3, steps: usize) -> Vec3 {
+        for _ in 0..steps {
+            translation = body.step(world, translation, 25., 1. / 64.);
+            if body.touching_wall() {
+                break;
+            }
+        }
+        translation
+    }
+
+    #[test]
+    fn bodies_fall_and_come_to_rest() {
+        let world = test_world();
+        let mut body = VoxelBody::new(Vec3::splat(0.25));
+        body.velocity = Vec3::new(3., 0., 0.);
+        let translation = simulate(&mut body, &world, Vec3::new(2.5, 4., 2.5), 384);
+        assert!(body.grounded);
+        assert!((translation.y - 0.25).abs() < 1e-5);
+        // friction stops the body sliding before it reaches the wall
+        assert!(body.velocity.length() < 1e-3);
+        assert!(!body.touching_wall());
+        assert!(translation.x > 2.5 && translation.x < 7.75);
+    }
+
+    #[test]
+    fn floating_bodies_stop_against_walls() {
+        let world = test_world();
+        let mut body = VoxelBody::new(Vec3::splat(0.1));
+        body.gravity_scale = 0.;
+        body.velocity = Vec3::new(20., 0., 0.);
+        let translation = simulate(&mut body, &world, Vec3::new(2.5, 4., 2.5), 64);
+        assert!(body.touching_wall());
+        assert_eq!(body.velocity, Vec3::ZERO);
+        assert!((translation.x - 7.9).abs() < 1e-5);
+        assert!((translation.y - 4.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn interpolated_bodies_are_drawn_between_fixed_steps() {
+        let mut app = App::new();
+        app.add_plugins(VoxelPhysicsPlugin)
+            .init_resource::<Time>()
+            .init_resource::<Time<Fixed>>()
+            .insert_resource(test_world());
+        let start = Vec3::new(2.5, 4., 2.5);
+        let body = app
+            .world
+            .spawn((
+                Transform::from_translation(start),
+                VoxelBody::new(Vec3::splat(0.25)),
+                InterpolatedBody::new(start),
+            ))
+            .id();
+        let mut fixed = Time::<Fixed>::from_seconds(1. / 64.);
+        fixed.advance_by(std::time::Duration::from_secs_f64(1. / 64.));
+        *app.world.resource_mut::<Time>() = fixed.as_generic();
+        for _ in 0..2 {
+            app.world.run_schedule(FixedUpdate);
+        }
+        // the transform is left alone by the fixed step, and drawn from the
+        // step before last when no time has passed since the last step
+        assert_eq!(app.world.get::<Transform>(body).unwrap().translation, start);
+        app.update();
+        let interpolated = app.world.get::<InterpolatedBody>(body).unwrap().clone();
+        assert!(interpolated.current.y < interpolated.previous.y);
+        assert!(interpolated.previous.y < start.y);
+        let translation = app.world.get::<Transform>(body).unwrap().translation;
+        assert_eq!(translation, interpolated.previous);
+    }
+}
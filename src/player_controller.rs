@@ -1,3 +1,10 @@
+use crate::interaction::{
+    break_and_place, draw_target_outline, fill_hotbars, select_hotbar_slot, setup_hotbar_ui,
+    update_hotbar_ui, Hotbar,
+};
+use crate::physics::{InterpolatedBody, VoxelBody};
+use crate::raycast::{update_looking_at, LookingAt};
+use crate::world::ChunkLoader;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy::window::CursorGrabMode;
@@ -10,9 +17,28 @@ impl Plugin for PlayerControllerPlugin {
         app.init_resource::<PlayerSettings>()
             .add_systems(Startup, setup)
             .add_systems(Startup, initial_grab_cursor)
+            .add_systems(Startup, setup_hotbar_ui)
             .add_systems(Update, handle_keyboard_input)
             .add_systems(Update, handle_mouse_input)
-            .add_systems(Update, cursor_grab);
+            .add_systems(Update, cursor_grab)
+            .add_systems(Update, toggle_movement_mode.before(handle_keyboard_input))
+            .add_systems(
+                Update,
+                update_looking_at
+                    .after(handle_keyboard_input)
+                    .after(handle_mouse_input),
+            )
+            .add_systems(
+                Update,
+                (
+                    fill_hotbars,
+                    select_hotbar_slot,
+                    break_and_place.after(update_looking_at),
+                    draw_target_outline.after(update_looking_at),
+                    update_hotbar_ui,
+                )
+                    .chain(),
+            );
     }
 }
 
@@ -20,21 +46,85 @@ impl Plugin for PlayerControllerPlugin {
 pub struct PlayerController {
     pub yaw: f32,
     pub pitch: f32,
+    pub mode: MovementMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MovementMode {
+    /// moves freely through terrain
+    #[default]
+    Fly,
+    /// falls under gravity and collides with solid voxels, moved by the
+    /// `VoxelBody` the player is given while walking
+    Walk,
+}
+
+/// Half the width of a walking player's collision box.
+const PLAYER_HALF_WIDTH: f32 = 0.3;
+const PLAYER_HEIGHT: f32 = 1.8;
+/// How far above the bottom of the collision box the camera sits.
+const PLAYER_EYE_HEIGHT: f32 = 1.62;
+
+/// The body of a walking player, whose translation is their eye position.
+pub fn player_body(step_height: f32) -> VoxelBody {
+    let mut body = VoxelBody::new(Vec3::new(
+        PLAYER_HALF_WIDTH,
+        PLAYER_HEIGHT / 2.,
+        PLAYER_HALF_WIDTH,
+    ));
+    body.offset = Vec3::Y * (PLAYER_HEIGHT / 2. - PLAYER_EYE_HEIGHT);
+    body.step_height = step_height;
+    // walking speed is set directly from input, so there is nothing for
+    // friction to do
+    body.friction = 0.;
+    body
 }
 
 #[derive(Resource)]
 pub struct PlayerSettings {
+    /// chunks within this many chunks of a player are generated and meshed
     pub view_distance: usize,
+    /// chunk meshes further than this from every player are despawned. Kept
+    /// larger than `view_distance` so chunks on the border don't flicker:
+    /// values up to `view_distance` are treated as `view_distance + 1`.
+    pub unload_distance: usize,
+    /// chunk voxel data further than this from every player is dropped.
+    /// Kept larger than the unload distance, so that meshes never lose the
+    /// neighbours they were built from: smaller values are raised to one
+    /// more than it.
+    pub evict_distance: usize,
+    /// chunks further than this from every player are meshed at half
+    /// resolution, and at half that again each time the distance doubles,
+    /// down to an eighth
+    pub lod_distance: usize,
     pub movement_speed: f32,
     pub mouse_sensitivity: f32,
+    /// horizontal speed while walking
+    pub walking_speed: f32,
+    /// vertical speed given by a jump
+    pub jump_speed: f32,
+    /// walking players climb ledges up to this tall without jumping
+    pub step_height: f32,
+    /// switches between flying and walking
+    pub toggle_mode_key: KeyCode,
+    /// how far away, in voxels, players can break and place voxels
+    pub reach: f32,
 }
 
 impl Default for PlayerSettings {
     fn default() -> Self {
         Self {
-            view_distance: 1,
+            view_distance: 4,
+            unload_distance: 5,
+            evict_distance: 7,
+            lod_distance: 2,
             movement_speed: 20.0,
             mouse_sensitivity: 0.1,
+            walking_speed: 5.0,
+            jump_speed: 8.0,
+            step_height: 1.0,
+            toggle_mode_key: KeyCode::F,
+            reach: 8.0,
         }
     }
 }
@@ -45,11 +135,10 @@ fn setup(mut commands: Commands) {
             transform: Transform::from_translation(Vec3::ZERO),
             ..Default::default()
         })
-        .insert(PlayerController {
-            yaw: 0.0,
-            pitch: 0.0,
-            ..Default::default()
-        });
+        .insert(PlayerController::default())
+        .insert(LookingAt::default())
+        .insert(Hotbar::default())
+        .insert(ChunkLoader);
 }
 
 fn toggle_grab_cursor(window: &mut Window) {
@@ -77,17 +166,67 @@ fn cursor_grab(keys: Res<Input<KeyCode>>, mut window_query: Query<&mut Window>)
     }
 }
 
+/// Walking players are given a body for the physics step to move, and flying
+/// players lose it again.
+fn toggle_movement_mode(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<PlayerSettings>,
+    mut query: Query<(Entity, &Transform, &mut PlayerController)>,
+) {
+    if !keys.just_pressed(settings.toggle_mode_key) {
+        return;
+    }
+    for (entity, transform, mut player_controller) in query.iter_mut() {
+        player_controller.mode = match player_controller.mode {
+            MovementMode::Fly => {
+                commands.entity(entity).insert((
+                    player_body(settings.step_height),
+                    InterpolatedBody::new(transform.translation),
+                ));
+                MovementMode::Walk
+            }
+            MovementMode::Walk => {
+                commands
+                    .entity(entity)
+                    .remove::<(VoxelBody, InterpolatedBody)>();
+                MovementMode::Fly
+            }
+        };
+    }
+}
+
 fn handle_keyboard_input(
     keys: Res<Input<KeyCode>>,
     time: Res<Time>,
     window_query: Query<&Window>,
     settings: Res<PlayerSettings>,
-    mut query: Query<&mut Transform, With<PlayerController>>,
+    mut query: Query<(&mut Transform, &PlayerController, Option<&mut VoxelBody>)>,
 ) {
     let Ok(primary) = window_query.get_single() else {
         return;
     };
-    for mut transform in query.iter_mut() {
+    for (mut transform, player_controller, body) in query.iter_mut() {
+        if player_controller.mode == MovementMode::Walk {
+            // walking players only steer their bodies, which the physics step
+            // moves; the body arrives once the mode change is applied
+            let Some(mut body) = body else {
+                continue;
+            };
+            let locked = primary.cursor.grab_mode == CursorGrabMode::Locked;
+            let direction = if locked {
+                walking_direction(&keys, &transform)
+            } else {
+                Vec3::ZERO
+            };
+            let horizontal = direction * settings.walking_speed;
+            body.velocity.x = horizontal.x;
+            body.velocity.z = horizontal.z;
+            if locked && keys.pressed(KeyCode::Space) && body.grounded {
+                body.velocity.y = settings.jump_speed;
+            }
+            continue;
+        }
         let mut velocity = Vec3::ZERO;
         let local_z = transform.local_z();
         let forward = -Vec3::new(local_z.x, 0., local_z.z);
@@ -113,6 +252,25 @@ fn handle_keyboard_input(
     }
 }
 
+/// The horizontal direction the pressed movement keys point in, normalized.
+fn walking_direction(keys: &Input<KeyCode>, transform: &Transform) -> Vec3 {
+    let local_z = transform.local_z();
+    let forward = -Vec3::new(local_z.x, 0., local_z.z).normalize_or_zero();
+    let right = Vec3::new(local_z.z, 0., -local_z.x).normalize_or_zero();
+
+    let mut direction = Vec3::ZERO;
+    for key in keys.get_pressed() {
+        match key {
+            KeyCode::W => direction += forward,
+            KeyCode::S => direction -= forward,
+            KeyCode::A => direction -= right,
+            KeyCode::D => direction += right,
+            _ => (),
+        }
+    }
+    direction.normalize_or_zero()
+}
+
 fn handle_mouse_input(
     settings: Res<PlayerSettings>,
     mut mouse_motion: EventReader<MouseMotion>,
@@ -0,0 +1,176 @@
+use crate::directions::Directions;
+use crate::player_controller::{PlayerController, PlayerSettings};
+use crate::voxel::{Voxel, EMPTY};
+use crate::world::World;
+use bevy::prelude::*;
+
+/// The first solid voxel a ray meets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub position: IVec3,
+    pub voxel: Voxel,
+    /// the face of the voxel the ray entered through, or `Directions::Zero`
+    /// if the ray started inside the voxel
+    pub normal: Directions,
+    /// how far along the ray the voxel was entered
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    /// The cell touching the face the ray entered through, where a voxel
+    /// placed against the hit voxel goes.
+    pub fn adjacent(&self) -> IVec3 {
+        self.position + self.normal.to_vector()
+    }
+}
+
+/// Walks the ray through the voxel grid one cell at a time (a DDA), returning
+/// the first solid voxel within `max_distance` of the origin. Translucent
+/// voxels are solid to rays as they are to bodies (see `collision::is_solid`),
+/// so that glass and water can be targeted. The ray stops at chunks that
+/// aren't loaded, as nothing is known about what lies beyond.
+pub fn raycast(
+    world: &World,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let mut cell = origin.floor().as_ivec3();
+    let step = IVec3::new(
+        direction.x.signum() as i32,
+        direction.y.signum() as i32,
+        direction.z.signum() as i32,
+    );
+    // how far along the ray it takes to cross one cell on each axis, and how
+    // far until the next cell boundary on each axis is crossed
+    let cell_distance = direction.recip().abs();
+    let mut boundary_distance = Vec3::ZERO;
+    for axis in 0..3 {
+        boundary_distance[axis] = if direction[axis] > 0. {
+            (cell[axis] as f32 + 1. - origin[axis]) * cell_distance[axis]
+        } else if direction[axis] < 0. {
+            (origin[axis] - cell[axis] as f32) * cell_distance[axis]
+        } else {
+            f32::INFINITY
+        };
+    }
+
+    let mut normal = Directions::Zero;
+    let mut distance = 0.;
+    loop {
+        let voxel = world.get_voxel(cell)?;
+        if voxel != EMPTY {
+            return Some(RaycastHit {
+                position: cell,
+                voxel,
+                normal,
+                distance,
+            });
+        }
+        // cross whichever boundary comes first
+        let axis = (0..3)
+            .min_by(|a, b| boundary_distance[*a].total_cmp(&boundary_distance[*b]))
+            .unwrap();
+        distance = boundary_distance[axis];
+        if distance > max_distance {
+            return None;
+        }
+        cell[axis] += step[axis];
+        boundary_distance[axis] += cell_distance[axis];
+        // the ray enters the next cell through the face pointing back at it
+        let mut face = IVec3::ZERO;
+        face[axis] = -step[axis];
+        normal = Directions::from_vector(face).unwrap();
+    }
+}
+
+/// The voxel a player is looking at, within their reach, updated every
+/// frame.
+#[derive(Component, Default)]
+pub struct LookingAt(pub Option<RaycastHit>);
+
+pub fn update_looking_at(
+    settings: Res<PlayerSettings>,
+    world: Res<World>,
+    mut query: Query<(&Transform, &mut LookingAt), With<PlayerController>>,
+) {
+    for (transform, mut looking_at) in &mut query {
+        let hit = raycast(
+            &world,
+            transform.translation,
+            transform.forward(),
+            settings.reach,
+        );
+        if looking_at.0 != hit {
+            looking_at.0 = hit;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::directions::Directions;
+    use crate::material::STONE;
+    use crate::raycast::raycast;
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::World;
+    use bevy::prelude::*;
+
+    // ground below y = 0, with chunks loaded around the origin
+    fn flat_world() -> World {
+        let ground = |position: IVec3| {
+            if position.y < 0 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::new(VoxelMapSettings::default(), ground);
+        for x in -1..=0 {
+            for y in -1..=0 {
+                for z in -1..=0 {
+                    world.load_chunk(ChunkPosition::new(x, y, z));
+                }
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn rays_hit_the_face_they_enter() {
+        let mut world = flat_world();
+        let hit = raycast(&world, Vec3::new(-3.5, 5.5, -2.5), Vec3::NEG_Y, 10.).unwrap();
+        assert_eq!(hit.position, IVec3::new(-4, -1, -3));
+        assert_eq!(hit.normal, Directions::Y);
+        assert_eq!(hit.adjacent(), IVec3::new(-4, 0, -3));
+        assert!((hit.distance - 5.5).abs() < 1e-5);
+
+        world.set_voxel(IVec3::new(-6, 0, -1), Voxel::new(STONE));
+        let hit = raycast(
+            &world,
+            Vec3::new(-1.5, 0.5, -0.5),
+            Vec3::new(-1., 0., -0.1),
+            10.,
+        )
+        .unwrap();
+        assert_eq!(hit.position, IVec3::new(-6, 0, -1));
+        assert_eq!(hit.normal, Directions::X);
+    }
+
+    #[test]
+    fn rays_stop_at_their_distance_and_at_unloaded_chunks() {
+        let world = flat_world();
+        assert!(raycast(&world, Vec3::new(-3.5, 5.5, -2.5), Vec3::NEG_Y, 5.).is_none());
+        assert!(raycast(&world, Vec3::new(-3.5, 5.5, -2.5), Vec3::Y, 100.).is_none());
+        // rays starting inside a voxel hit it straight away
+        let hit = raycast(&world, Vec3::new(-3.5, -0.5, -2.5), Vec3::X, 5.).unwrap();
+        assert_eq!(hit.normal, Directions::Zero);
+        assert_eq!(hit.distance, 0.);
+    }
+}
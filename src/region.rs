@@ -0,0 +1,314 @@
+use crate::storage::chunk::ChunkPosition;
+use bevy::prelude::*;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Region files hold a cube of chunks this many across.
+pub const REGION_DIM: i32 = 16;
+const REGION_CHUNKS: usize = (REGION_DIM * REGION_DIM * REGION_DIM) as usize;
+
+const MAGIC: [u8; 4] = *b"VXRG";
+const VERSION: u32 = 1;
+// the magic and version, then an offset and length for every chunk
+const HEADER_SIZE: u64 = 8 + REGION_CHUNKS as u64 * 8;
+
+#[derive(Debug, Error)]
+pub enum RegionError {
+    #[error("could not access region file: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a region file")]
+    NotARegion,
+    #[error("unsupported region file version {0}")]
+    UnsupportedVersion(u32),
+    #[error("region file is too large to address more data")]
+    TooLarge,
+}
+
+/// Splits a chunk position into the position of the region holding it and
+/// the chunk's index within that region.
+pub fn region_of(chunk_pos: ChunkPosition) -> (IVec3, usize) {
+    let chunk_pos = chunk_pos.as_ivec3();
+    let region = chunk_pos.div_euclid(IVec3::splat(REGION_DIM));
+    let local = chunk_pos.rem_euclid(IVec3::splat(REGION_DIM));
+    let index = local.x + local.y * REGION_DIM + local.z * REGION_DIM * REGION_DIM;
+    (region, index as usize)
+}
+
+/// A file holding the data of a region's chunks, each compressed on its
+/// own. The file starts with a table giving where each chunk's data lies,
+/// followed by the data itself. Rewritten chunks reuse their old space when
+/// they fit in it, and are appended to the end of the file otherwise.
+pub struct RegionFile {
+    file: File,
+    // the offset and length of each chunk's data, with a length of 0 for
+    // chunks that haven't been saved
+    table: Vec<(u32, u32)>,
+}
+
+impl RegionFile {
+    /// Opens the region file at the given path for writing, creating it if
+    /// it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, RegionError> {
+        if !path.exists() {
+            // the empty region is written beside the file and moved into
+            // place, so that readers never see half a header
+            let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+            header.extend_from_slice(&MAGIC);
+            header.extend_from_slice(&VERSION.to_le_bytes());
+            header.resize(HEADER_SIZE as usize, 0);
+            let partial = path.with_extension("partial");
+            std::fs::write(&partial, header)?;
+            std::fs::rename(partial, path)?;
+        }
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Opens the region file at the given path for reading only.
+    pub fn open_read_only(path: &Path) -> Result<Self, RegionError> {
+        Self::from_file(File::open(path)?)
+    }
+
+    fn from_file(mut file: File) -> Result<Self, RegionError> {
+        let mut header = vec![0; HEADER_SIZE as usize];
+        file.read_exact(&mut header).map_err(|error| {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                RegionError::NotARegion
+            } else {
+                error.into()
+            }
+        })?;
+        if header[..4] != MAGIC {
+            return Err(RegionError::NotARegion);
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(RegionError::UnsupportedVersion(version));
+        }
+        let table = header[8..]
+            .chunks_exact(8)
+            .map(|entry| {
+                let offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let length = u32::from_le_bytes(entry[4..].try_into().unwrap());
+                (offset, length)
+            })
+            .collect();
+        Ok(Self { file, table })
+    }
+
+    /// The decompressed data of the chunk at the given index, if it has been
+    /// saved.
+    pub fn read(&mut self, index: usize) -> Result<Option<Vec<u8>>, RegionError> {
+        let (offset, length) = self.table[index];
+        if length == 0 {
+            return Ok(None);
+        }
+        let mut compressed = vec![0; length as usize];
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(&mut compressed)?;
+        let mut data = Vec::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Compresses and saves the data of the chunk at the given index,
+    /// replacing whatever was saved for it before.
+    pub fn write(&mut self, index: usize, data: &[u8]) -> Result<(), RegionError> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        let (old_offset, old_length) = self.table[index];
+        let offset = if old_length != 0 && compressed.len() <= old_length as usize {
+            old_offset as u64
+        } else {
+            self.file.seek(SeekFrom::End(0))?
+        };
+        // the table only holds 32 bit offsets, so data can't start past them
+        let entry_offset = u32::try_from(offset).map_err(|_| RegionError::TooLarge)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&compressed)?;
+        // the table is only updated once the data is in place
+        self.set_entry(index, (entry_offset, compressed.len() as u32))
+    }
+
+    /// Forgets the data of the chunk at the given index, so that it reads as
+    /// never having been saved.
+    pub fn remove(&mut self, index: usize) -> Result<(), RegionError> {
+        if self.table[index].1 == 0 {
+            return Ok(());
+        }
+        self.set_entry(index, (0, 0))
+    }
+
+    fn set_entry(&mut self, index: usize, entry: (u32, u32)) -> Result<(), RegionError> {
+        self.table[index] = entry;
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&entry.0.to_le_bytes());
+        bytes[4..].copy_from_slice(&entry.1.to_le_bytes());
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// A directory of region files, which together hold every saved chunk of a
+/// world. Chunks may be loaded from any thread while another saves them, as
+/// long as only one thread saves at a time and no chunk is loaded while it is
+/// being saved.
+pub struct RegionStore {
+    dir: PathBuf,
+}
+
+impl RegionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, region: IVec3) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    /// The saved data of the chunk, if it has been saved.
+    pub fn load_chunk(&self, chunk_pos: ChunkPosition) -> Result<Option<Vec<u8>>, RegionError> {
+        let (region, index) = region_of(chunk_pos);
+        let path = self.path(region);
+        if !path.exists() {
+            return Ok(None);
+        }
+        RegionFile::open_read_only(&path)?.read(index)
+    }
+
+    /// Saves the data of each chunk, or removes the chunk where its data is
+    /// None, opening each region file once however many of the chunks it
+    /// holds.
+    pub fn save_chunks(
+        &self,
+        chunks: impl IntoIterator<Item = (ChunkPosition, Option<Vec<u8>>)>,
+    ) -> Result<(), RegionError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut chunks: Vec<(IVec3, usize, Option<Vec<u8>>)> = chunks
+            .into_iter()
+            .map(|(chunk_pos, data)| {
+                let (region, index) = region_of(chunk_pos);
+                (region, index, data)
+            })
+            .collect();
+        chunks.sort_by_key(|(region, _, _)| region.to_array());
+        let mut open: Option<(IVec3, RegionFile)> = None;
+        for (region, index, data) in chunks {
+            let path = self.path(region);
+            // there is nothing to remove from regions that were never saved
+            if data.is_none() && !path.exists() {
+                continue;
+            }
+            let file = match &mut open {
+                Some((open_region, file)) if *open_region == region => file,
+                _ => &mut open.insert((region, RegionFile::open(&path)?)).1,
+            };
+            match data {
+                Some(data) => file.write(index, &data)?,
+                None => file.remove(index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::region::{region_of, RegionError, RegionFile, RegionStore, REGION_DIM};
+    use crate::storage::chunk::ChunkPosition;
+    use bevy::prelude::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voxelite-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn regions_extend_into_negative_space() {
+        assert_eq!(region_of(ChunkPosition::new(0, 0, 0)), (IVec3::ZERO, 0));
+        assert_eq!(
+            region_of(ChunkPosition::new(-1, 16, 2)),
+            (
+                IVec3::new(-1, 1, 0),
+                (15 + 2 * REGION_DIM * REGION_DIM) as usize
+            )
+        );
+    }
+
+    #[test]
+    fn chunks_survive_reopening_and_rewriting() {
+        let dir = temp_dir("region-file");
+        let path = dir.join("test.region");
+        let small = vec![7; 100];
+        let large: Vec<u8> = (0..20000).map(|i| (i * 7919 % 251) as u8).collect();
+        {
+            let mut file = RegionFile::open(&path).unwrap();
+            file.write(3, &small).unwrap();
+            file.write(4000, &small).unwrap();
+            // too large for its old space, so moves to the end of the file
+            file.write(3, &large).unwrap();
+        }
+        let mut file = RegionFile::open_read_only(&path).unwrap();
+        assert_eq!(file.read(3).unwrap(), Some(large));
+        assert!(file.write(5, &small).is_err());
+        assert_eq!(file.read(4000).unwrap(), Some(small));
+        assert_eq!(file.read(5).unwrap(), None);
+
+        std::fs::write(dir.join("other.region"), b"not a region").unwrap();
+        assert!(matches!(
+            RegionFile::open(&dir.join("other.region")),
+            Err(RegionError::NotARegion)
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_past_the_addressable_size_fail() {
+        let dir = temp_dir("region-full");
+        let mut file = RegionFile::open(&dir.join("full.region")).unwrap();
+        // sparse, so this takes no space on disk
+        file.file.set_len(u32::MAX as u64 + 1).unwrap();
+        assert!(matches!(
+            file.write(0, &[1; 10]),
+            Err(RegionError::TooLarge)
+        ));
+        assert_eq!(file.read(0).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stores_split_chunks_between_regions() {
+        let dir = temp_dir("region-store");
+        let store = RegionStore::new(dir.join("regions"));
+        let positions = [
+            ChunkPosition::new(0, 0, 0),
+            ChunkPosition::new(-1, 0, 0),
+            ChunkPosition::new(15, 15, 15),
+        ];
+        assert_eq!(store.load_chunk(positions[0]).unwrap(), None);
+        let chunks = positions.map(|pos| (pos, Some(vec![pos.x as u8; 10])));
+        store.save_chunks(chunks.clone()).unwrap();
+        for (pos, data) in chunks {
+            assert_eq!(store.load_chunk(pos).unwrap(), data);
+        }
+        assert_eq!(std::fs::read_dir(dir.join("regions")).unwrap().count(), 2);
+        // removing chunks doesn't create regions for them
+        let removed = [positions[0], ChunkPosition::new(100, 0, 0)];
+        store.save_chunks(removed.map(|pos| (pos, None))).unwrap();
+        assert_eq!(store.load_chunk(positions[0]).unwrap(), None);
+        assert!(store.load_chunk(positions[2]).unwrap().is_some());
+        assert_eq!(std::fs::read_dir(dir.join("regions")).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,651 @@
+use crate::chunk::{Chunk, ChunkShape};
+use crate::material::VoxelMaterialRegistry;
+use crate::region::{RegionError, RegionStore};
+use crate::storage::chunk::{Chunk as RowStorage, ChunkPosition};
+use crate::storage::ChunkStorage;
+use crate::voxel::Voxel;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use ndshape::ConstShape;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use thiserror::Error;
+
+const METADATA_FILE: &str = "world.ron";
+const REGIONS_DIR: &str = "regions";
+// the first byte of a saved chunk says how the rest of it is stored: either
+// the saved id of every voxel's material, in `ChunkShape` order, or the
+// index and saved material id of each voxel edited since generation
+const FULL_CHUNK: u8 = 0;
+const EDITED_CHUNK: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("could not access world save: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Region(#[from] RegionError),
+    #[error("could not parse world metadata: {0}")]
+    ParseMetadata(#[from] ron::error::SpannedError),
+    #[error("could not write world metadata: {0}")]
+    WriteMetadata(#[from] ron::Error),
+    #[error("saved chunk {0:?} is corrupt")]
+    CorruptChunk(ChunkPosition),
+    #[error("saved material {0} has not been reserved in the registry")]
+    UnknownMaterial(u16),
+    #[error("material {0} is not registered, so can't be saved")]
+    UnregisteredMaterial(u16),
+    #[error("no runtime id is left for saved material {0}")]
+    RegistryFull(String),
+}
+
+/// Everything about a saved world other than its chunks.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldMetadata {
+    /// the seed the world's terrain is generated from
+    pub seed: u64,
+    /// the string id of each material, indexed by the material ids saved in
+    /// chunks. Runtime ids depend on the order materials are registered in,
+    /// so they aren't saved.
+    pub materials: Vec<String>,
+    /// where the player was when the world was last saved
+    #[serde(default)]
+    pub player_position: Option<[f32; 3]>,
+}
+
+/// A world saved in a directory, holding its metadata in `world.ron` and its
+/// chunks in region files. Only chunks that differ from what the terrain
+/// generator builds are saved, with saved material ids that are loaded back
+/// as the runtime ids the materials have in this session. Chunks are loaded
+/// with the same storage backend as the world they belong to.
+pub struct WorldSave<S: ChunkStorage = RowStorage> {
+    dir: PathBuf,
+    regions: RegionStore,
+    max_edited_voxels: usize,
+    state: Mutex<SaveState>,
+    // chunks handed to a `SaveThread` that it hasn't saved yet, which are
+    // loaded from here rather than from their region files
+    pending: Mutex<HashMap<ChunkPosition, Arc<Chunk<S>>>>,
+}
+
+struct SaveState {
+    metadata: WorldMetadata,
+    // the runtime id of each saved material id, once reserved, and the
+    // reverse
+    runtime_ids: Vec<u16>,
+    saved_ids: HashMap<u16, u16>,
+}
+
+impl SaveState {
+    /// The saved id of a runtime material id, adding the material to the
+    /// material table if it hasn't been saved before.
+    fn saved_id(
+        &mut self,
+        runtime: u16,
+        registry: &VoxelMaterialRegistry,
+    ) -> Result<u16, SaveError> {
+        if let Some(saved) = self.saved_ids.get(&runtime) {
+            return Ok(*saved);
+        }
+        let string_id = registry
+            .get_string_id(runtime)
+            .ok_or(SaveError::UnregisteredMaterial(runtime))?;
+        let saved = self.metadata.materials.len() as u16;
+        self.metadata.materials.push(string_id.to_owned());
+        self.runtime_ids.push(runtime);
+        self.saved_ids.insert(runtime, saved);
+        Ok(saved)
+    }
+}
+
+impl<S: ChunkStorage> WorldSave<S> {
+    /// Opens the world saved in the given directory, or starts a new one with
+    /// the given seed if nothing has been saved there.
+    pub fn open(dir: impl Into<PathBuf>, seed: u64) -> Result<Self, SaveError> {
+        let dir = dir.into();
+        let metadata_path = dir.join(METADATA_FILE);
+        let metadata = if metadata_path.exists() {
+            ron::from_str(&std::fs::read_to_string(metadata_path)?)?
+        } else {
+            let metadata = WorldMetadata { seed, ..default() };
+            write_metadata(&dir, &metadata)?;
+            metadata
+        };
+        Ok(Self {
+            regions: RegionStore::new(dir.join(REGIONS_DIR)),
+            dir,
+            max_edited_voxels: 0,
+            state: Mutex::new(SaveState {
+                metadata,
+                runtime_ids: Vec::new(),
+                saved_ids: HashMap::new(),
+            }),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Saves chunks that differ from their generated voxels in at most this
+    /// many voxels as just those voxels, which are applied to the generated
+    /// chunk when it is loaded. This is much smaller for small edits, but
+    /// loads a different chunk if the terrain generator changes.
+    pub fn with_max_edited_voxels(mut self, max_edited_voxels: usize) -> Self {
+        self.max_edited_voxels = max_edited_voxels;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.state.lock().unwrap().metadata.seed
+    }
+
+    pub fn player_position(&self) -> Option<Vec3> {
+        let state = self.state.lock().unwrap();
+        state.metadata.player_position.map(Vec3::from_array)
+    }
+
+    /// Finds the runtime id of every saved material, reserving an id in the
+    /// registry for those that haven't been registered yet. This must be
+    /// called before chunks are loaded or saved, and fails if the registry
+    /// has no room left for the saved materials.
+    pub fn reserve_materials(&self, registry: &mut VoxelMaterialRegistry) -> Result<(), SaveError> {
+        let mut state = self.state.lock().unwrap();
+        state.runtime_ids = state
+            .metadata
+            .materials
+            .iter()
+            .map(|string_id| {
+                registry
+                    .get_id(string_id)
+                    .or_else(|| registry.reserve(string_id))
+                    .ok_or_else(|| SaveError::RegistryFull(string_id.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+        state.saved_ids = state
+            .runtime_ids
+            .iter()
+            .enumerate()
+            .map(|(saved, runtime)| (*runtime, saved as u16))
+            .collect();
+        Ok(())
+    }
+
+    /// Loads the saved chunk at the given position, or returns None if it
+    /// hasn't been saved. Chunks saved as edits are applied to the chunk
+    /// `generate` builds.
+    pub fn load_chunk(
+        &self,
+        chunk_pos: ChunkPosition,
+        generate: impl FnOnce() -> Chunk<S>,
+    ) -> Result<Option<Chunk<S>>, SaveError> {
+        if let Some(chunk) = self.pending.lock().unwrap().get(&chunk_pos) {
+            return Ok(Some(Chunk::clone(chunk)));
+        }
+        let Some(data) = self.regions.load_chunk(chunk_pos)? else {
+            return Ok(None);
+        };
+        let corrupt = || SaveError::CorruptChunk(chunk_pos);
+        let (kind, data) = data.split_first().ok_or_else(corrupt)?;
+        let (width, expected_len) = match *kind {
+            FULL_CHUNK => (2, Some(ChunkShape::USIZE * 2)),
+            EDITED_CHUNK => (4, None),
+            _ => return Err(corrupt()),
+        };
+        if data.len() % width != 0 || expected_len.is_some_and(|len| data.len() != len) {
+            return Err(corrupt());
+        }
+        let values: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        let state = self.state.lock().unwrap();
+        let runtime_id = |saved: u16| {
+            let runtime = state.runtime_ids.get(saved as usize);
+            runtime.copied().ok_or(SaveError::UnknownMaterial(saved))
+        };
+        if *kind == FULL_CHUNK {
+            let materials = values
+                .into_iter()
+                .map(runtime_id)
+                .collect::<Result<Vec<u16>, SaveError>>()?;
+            let chunk = Chunk::from_voxels(|position| {
+                Voxel::new(materials[ChunkShape::linearize(position) as usize])
+            });
+            return Ok(Some(chunk));
+        }
+        let mut edits = Vec::with_capacity(values.len() / 2);
+        for edit in values.chunks_exact(2) {
+            if edit[0] as u32 >= ChunkShape::SIZE {
+                return Err(corrupt());
+            }
+            let position = ChunkShape::delinearize(edit[0] as u32);
+            edits.push((position, Voxel::new(runtime_id(edit[1])?)));
+        }
+        // the lock isn't held while generating, which takes a while
+        drop(state);
+        let mut chunk = generate();
+        for (position, voxel) in edits {
+            chunk.set(position, voxel);
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Saves each of the given chunks, replacing whatever was saved for them
+    /// before. Chunks are compared with the chunk `generate` builds for their
+    /// position: those that match it aren't saved at all, and those that
+    /// differ in at most `max_edited_voxels` voxels only save those voxels.
+    /// Materials saved for the first time are added to the world's material
+    /// table. This takes a while for every chunk, so the game leaves it to a
+    /// `SaveThread`.
+    pub fn save_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (ChunkPosition, &'a Chunk<S>)>,
+        registry: &VoxelMaterialRegistry,
+        generate: impl Fn(ChunkPosition) -> Chunk<S>,
+    ) -> Result<(), SaveError> {
+        // the index of every voxel that differs from the generated chunk
+        let chunks: Vec<(ChunkPosition, &Chunk<S>, Vec<u32>)> = chunks
+            .into_iter()
+            .map(|(chunk_pos, chunk)| {
+                let generated = generate(chunk_pos);
+                let edited = (0..ChunkShape::SIZE)
+                    .filter(|index| {
+                        let position = ChunkShape::delinearize(*index);
+                        chunk.get(position) != generated.get(position)
+                    })
+                    .collect();
+                (chunk_pos, chunk, edited)
+            })
+            .collect();
+
+        let mut new_materials = false;
+        let mut encoded = Vec::with_capacity(chunks.len());
+        for (chunk_pos, chunk, edited) in chunks {
+            if edited.is_empty() {
+                encoded.push((chunk_pos, None));
+                continue;
+            }
+            let full = edited.len() > self.max_edited_voxels;
+            let indices: Vec<u32> = if full {
+                (0..ChunkShape::SIZE).collect()
+            } else {
+                edited
+            };
+            let materials: Vec<u16> = indices
+                .iter()
+                .map(|index| chunk.get(ChunkShape::delinearize(*index)).material)
+                .collect();
+            let saved_ids = self.saved_ids(&materials, registry, &mut new_materials)?;
+            let mut data = Vec::with_capacity(1 + indices.len() * 4);
+            if full {
+                data.push(FULL_CHUNK);
+            } else {
+                data.push(EDITED_CHUNK);
+            }
+            for (index, material) in indices.into_iter().zip(materials) {
+                if !full {
+                    data.extend_from_slice(&(index as u16).to_le_bytes());
+                }
+                data.extend_from_slice(&saved_ids[&material].to_le_bytes());
+            }
+            encoded.push((chunk_pos, Some(data)));
+        }
+        // chunks saved with new materials can't be loaded without them, so
+        // the material table is saved first
+        if new_materials {
+            write_metadata(&self.dir, &self.state.lock().unwrap().metadata)?;
+        }
+        self.regions.save_chunks(encoded)?;
+        Ok(())
+    }
+
+    /// The saved id of each distinct runtime material id given, noting
+    /// whether any of them were added to the material table. The lock on the
+    /// save's state is only held for this, so that loading chunks never
+    /// waits for whole chunks to be encoded.
+    fn saved_ids(
+        &self,
+        materials: &[u16],
+        registry: &VoxelMaterialRegistry,
+        new_materials: &mut bool,
+    ) -> Result<HashMap<u16, u16>, SaveError> {
+        let mut state = self.state.lock().unwrap();
+        let materials_before = state.metadata.materials.len();
+        let mut saved_ids = HashMap::new();
+        for material in materials {
+            if !saved_ids.contains_key(material) {
+                saved_ids.insert(*material, state.saved_id(*material, registry)?);
+            }
+        }
+        *new_materials |= state.metadata.materials.len() != materials_before;
+        Ok(saved_ids)
+    }
+
+    /// Saves the world's metadata, with the player's current position.
+    pub fn save_metadata(&self, player_position: Option<Vec3>) -> Result<(), SaveError> {
+        let mut state = self.state.lock().unwrap();
+        if player_position.is_some() {
+            state.metadata.player_position = player_position.map(|position| position.to_array());
+        }
+        write_metadata(&self.dir, &state.metadata)
+    }
+}
+
+/// Chunks for a `SaveThread` to save, with the material registry as it was
+/// when they were handed over.
+struct SaveJob<S: ChunkStorage> {
+    chunks: Vec<(ChunkPosition, Arc<Chunk<S>>)>,
+    registry: VoxelMaterialRegistry,
+}
+
+enum SaveMessage<S: ChunkStorage> {
+    Save(SaveJob<S>),
+    // answered once every chunk sent before it has been saved
+    Flush(Sender<()>),
+}
+
+/// Saves chunks to a `WorldSave` on a thread of its own, which is the only
+/// one to write the save's region files. Comparing chunks with their
+/// generated terrain, encoding them and compressing them takes far longer
+/// than a frame, so none of it happens on the thread handing chunks over.
+/// Dropping the thread waits for it to save every chunk it was given.
+pub struct SaveThread<S: ChunkStorage = RowStorage> {
+    save: Arc<WorldSave<S>>,
+    sender: Option<Sender<SaveMessage<S>>>,
+    // jobs that failed to save, whose chunks are still pending
+    failed: Arc<Mutex<Vec<SaveJob<S>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<S: ChunkStorage> SaveThread<S> {
+    /// Starts a thread saving chunks to `save`, comparing them with the
+    /// chunks `generate` builds. See `WorldSave::save_chunks`.
+    pub fn spawn(
+        save: Arc<WorldSave<S>>,
+        generate: impl Fn(ChunkPosition) -> Chunk<S> + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = channel();
+        let thread_save = save.clone();
+        let failed = Arc::new(Mutex::new(Vec::new()));
+        let thread_failed = failed.clone();
+        let thread = std::thread::spawn(move || {
+            let save = thread_save;
+            for message in receiver {
+                let mut job = match message {
+                    SaveMessage::Save(job) => job,
+                    SaveMessage::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                let chunks = job.chunks.iter().map(|(pos, chunk)| (*pos, &**chunk));
+                let result = save.save_chunks(chunks, &job.registry, &generate);
+                // chunks handed over again since keep their newer copies
+                let mut pending = save.pending.lock().unwrap();
+                job.chunks.retain(|(pos, chunk)| {
+                    pending
+                        .get(pos)
+                        .is_some_and(|newer| Arc::ptr_eq(newer, chunk))
+                });
+                match result {
+                    Ok(()) => {
+                        for (pos, _) in &job.chunks {
+                            pending.remove(pos);
+                        }
+                    }
+                    // the chunks stay pending, so their edits are kept until
+                    // they are retried
+                    Err(error) => {
+                        error!("Can't save chunks: {}", error);
+                        thread_failed.lock().unwrap().push(job);
+                    }
+                }
+            }
+        });
+        Self {
+            save,
+            sender: Some(sender),
+            failed,
+            thread: Some(thread),
+        }
+    }
+
+    /// Hands the chunks over to be saved. Until they are, loading them from
+    /// the save returns these copies.
+    pub fn save_chunks(
+        &self,
+        chunks: Vec<(ChunkPosition, Chunk<S>)>,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        let chunks: Vec<(ChunkPosition, Arc<Chunk<S>>)> = chunks
+            .into_iter()
+            .map(|(pos, chunk)| (pos, Arc::new(chunk)))
+            .collect();
+        let mut pending = self.save.pending.lock().unwrap();
+        for (pos, chunk) in &chunks {
+            pending.insert(*pos, chunk.clone());
+        }
+        drop(pending);
+        let job = SaveJob {
+            chunks,
+            registry: registry.clone(),
+        };
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(SaveMessage::Save(job));
+        }
+    }
+
+    /// Hands every chunk that failed to save over to be saved again, unless
+    /// a newer copy of it has been handed over since. Returns how many
+    /// chunks were handed over.
+    pub fn retry_failed(&self) -> usize {
+        let jobs = std::mem::take(&mut *self.failed.lock().unwrap());
+        let pending = self.save.pending.lock().unwrap();
+        let mut count = 0;
+        for mut job in jobs {
+            job.chunks.retain(|(pos, chunk)| {
+                pending
+                    .get(pos)
+                    .is_some_and(|newer| Arc::ptr_eq(newer, chunk))
+            });
+            count += job.chunks.len();
+            if let Some(sender) = &self.sender {
+                let _ = sender.send(SaveMessage::Save(job));
+            }
+        }
+        count
+    }
+
+    /// Waits until every chunk handed over so far has been saved, or has
+    /// failed to save.
+    pub fn flush(&self) {
+        let (done, finished) = channel();
+        if let Some(sender) = &self.sender {
+            if sender.send(SaveMessage::Flush(done)).is_ok() {
+                let _ = finished.recv();
+            }
+        }
+    }
+}
+
+impl<S: ChunkStorage> Drop for SaveThread<S> {
+    fn drop(&mut self) {
+        // the thread finishes its queue and stops once the channel closes
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_metadata(dir: &Path, metadata: &WorldMetadata) -> Result<(), SaveError> {
+    std::fs::create_dir_all(dir)?;
+    let text = ron::ser::to_string_pretty(metadata, Default::default())?;
+    // written beside the metadata and moved into place, so that a crash
+    // mid-write leaves the old metadata rather than half of the new
+    let path = dir.join(METADATA_FILE);
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, text)?;
+    std::fs::rename(partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::{Chunk, ChunkShape};
+    use crate::material::{VoxelMaterial, VoxelMaterialRegistry, DIRT, STONE};
+    use crate::save::{SaveThread, WorldSave, EDITED_CHUNK, FULL_CHUNK, REGIONS_DIR};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::voxel::{Voxel, EMPTY};
+    use bevy::prelude::*;
+    use ndshape::ConstShape;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn saves_remap_materials_between_sessions() {
+        let dir = std::env::temp_dir().join(format!("voxelite-save-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let marble = registry
+            .register("marble", VoxelMaterial::new("Marble", Color::WHITE))
+            .unwrap();
+        let chunk: Chunk = Chunk::from_voxels(|[x, y, _]| match (x, y) {
+            (0, _) => Voxel::new(marble),
+            (_, 0) => Voxel::new(STONE),
+            _ => EMPTY,
+        });
+        let pos = ChunkPosition::new(-3, 1, 20);
+        {
+            let save = WorldSave::open(&dir, 42).unwrap();
+            save.reserve_materials(&mut registry).unwrap();
+            let generate = |_| Chunk::from_voxels(|_| EMPTY);
+            save.save_chunks([(pos, &chunk)], &registry, generate)
+                .unwrap();
+            save.save_metadata(Some(Vec3::new(1., 2., 3.))).unwrap();
+        }
+
+        // the next session registers its materials in another order, and
+        // hasn't loaded marble's definition yet
+        let mut registry = VoxelMaterialRegistry::default();
+        registry.register("glass", VoxelMaterial::new("Glass", Color::WHITE));
+        let save: WorldSave = WorldSave::open(&dir, 7).unwrap();
+        assert_eq!(save.seed(), 42);
+        assert_eq!(save.player_position(), Some(Vec3::new(1., 2., 3.)));
+        save.reserve_materials(&mut registry).unwrap();
+        let marble = registry.get_id("marble").unwrap();
+        assert_eq!(marble, registry.get_id("glass").unwrap() + 1);
+        let loaded = save.load_chunk(pos, || unreachable!()).unwrap().unwrap();
+        assert_eq!(loaded.get([0, 5, 5]), Voxel::new(marble));
+        assert_eq!(loaded.get([5, 0, 5]), Voxel::new(STONE));
+        assert_eq!(loaded.get([5, 5, 5]), EMPTY);
+        let unsaved = save.load_chunk(ChunkPosition::new(0, 0, 0), || unreachable!());
+        assert!(unsaved.unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_edits_to_generated_chunks_are_saved() {
+        let dir = std::env::temp_dir().join(format!("voxelite-edits-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let save: WorldSave = WorldSave::open(&dir, 0).unwrap().with_max_edited_voxels(2);
+        save.reserve_materials(&mut registry).unwrap();
+        let ground =
+            || Chunk::from_voxels(|[_, y, _]| if y < 4 { Voxel::new(STONE) } else { EMPTY });
+        let generate = |_| ground();
+        let [unedited, small, large] = [0, 1, 2].map(|x| ChunkPosition::new(x, 0, 0));
+        let mut small_edit = ground();
+        small_edit.set([1, 2, 3], EMPTY);
+        small_edit.set([1, 20, 3], Voxel::new(DIRT));
+        let mut large_edit = small_edit.clone();
+        large_edit.set([9, 9, 9], Voxel::new(DIRT));
+        let chunks = [
+            (unedited, &ground()),
+            (small, &small_edit),
+            (large, &large_edit),
+        ];
+        save.save_chunks(chunks, &registry, generate).unwrap();
+
+        assert!(save.load_chunk(unedited, ground).unwrap().is_none());
+        let raw = |pos| save.regions.load_chunk(pos).unwrap().unwrap();
+        assert_eq!((raw(small)[0], raw(small).len()), (EDITED_CHUNK, 1 + 2 * 4));
+        assert_eq!(raw(large)[0], FULL_CHUNK);
+        for (pos, edited) in [(small, &small_edit), (large, &large_edit)] {
+            let loaded = save.load_chunk(pos, ground).unwrap().unwrap();
+            for index in 0..ChunkShape::SIZE {
+                let position = ChunkShape::delinearize(index);
+                assert_eq!(loaded.get(position), edited.get(position));
+            }
+        }
+        // chunks edited back to their generated voxels are removed
+        save.save_chunks([(small, &ground())], &registry, generate)
+            .unwrap();
+        assert!(save.load_chunk(small, ground).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_load_while_the_save_thread_writes_them() {
+        let dir = std::env::temp_dir().join(format!("voxelite-thread-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let save: Arc<WorldSave> = Arc::new(WorldSave::open(&dir, 0).unwrap());
+        save.reserve_materials(&mut registry).unwrap();
+        // generating the chunk to compare with holds the thread up until the
+        // test lets it go
+        let (release, released) = channel::<()>();
+        let released = Mutex::new(released);
+        let saver = SaveThread::spawn(save.clone(), move |_| {
+            released.lock().unwrap().recv().unwrap();
+            Chunk::from_voxels(|_| EMPTY)
+        });
+        let pos = ChunkPosition::new(4, -2, 0);
+        let edited = Chunk::from_voxels(|[x, _, _]| if x == 3 { Voxel::new(DIRT) } else { EMPTY });
+        saver.save_chunks(vec![(pos, edited)], &registry);
+        let pending = save.load_chunk(pos, || unreachable!()).unwrap().unwrap();
+        assert_eq!(pending.get([3, 9, 9]), Voxel::new(DIRT));
+        assert!(save.regions.load_chunk(pos).unwrap().is_none());
+
+        release.send(()).unwrap();
+        saver.flush();
+        assert!(save.pending.lock().unwrap().is_empty());
+        let saved = save.load_chunk(pos, || unreachable!()).unwrap().unwrap();
+        assert_eq!(saved.get([3, 9, 9]), Voxel::new(DIRT));
+        drop(saver);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_that_fail_to_save_stay_pending_until_retried() {
+        let dir = std::env::temp_dir().join(format!("voxelite-retry-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let save: Arc<WorldSave> = Arc::new(WorldSave::open(&dir, 0).unwrap());
+        save.reserve_materials(&mut registry).unwrap();
+        // a file where the regions directory belongs makes every save fail
+        std::fs::write(dir.join(REGIONS_DIR), b"in the way").unwrap();
+        let saver = SaveThread::spawn(save.clone(), |_| Chunk::from_voxels(|_| EMPTY));
+        let pos = ChunkPosition::new(1, 0, 0);
+        let edited = Chunk::from_voxels(|[x, _, _]| if x == 3 { Voxel::new(DIRT) } else { EMPTY });
+        saver.save_chunks(vec![(pos, edited)], &registry);
+        saver.flush();
+        let pending = save.load_chunk(pos, || unreachable!()).unwrap().unwrap();
+        assert_eq!(pending.get([3, 9, 9]), Voxel::new(DIRT));
+
+        std::fs::remove_file(dir.join(REGIONS_DIR)).unwrap();
+        assert_eq!(saver.retry_failed(), 1);
+        saver.flush();
+        assert!(save.pending.lock().unwrap().is_empty());
+        assert_eq!(saver.retry_failed(), 0);
+        let saved = save.load_chunk(pos, || unreachable!()).unwrap().unwrap();
+        assert_eq!(saved.get([3, 9, 9]), Voxel::new(DIRT));
+        drop(saver);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
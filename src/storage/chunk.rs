@@ -1,4 +1,9 @@
-use super::row::{CHUNK_SIZE, Row};
+use std::ops::Add;
+
+use bevy::prelude::IVec3;
+
+use super::ChunkStorage;
+use super::row::{CHUNK_SIZE, RowSlot, Voxel};
 
 /*
  * Chunk
@@ -10,33 +15,153 @@ use super::row::{CHUNK_SIZE, Row};
  * efficient memory usage and voxel read times, at the cost of voxel write
  * times.
  */
+#[derive(Clone)]
 pub struct Chunk {
-	rows: [Box<dyn Row>; CHUNK_SIZE * CHUNK_SIZE]
+	rows: Box<[RowSlot; CHUNK_SIZE * CHUNK_SIZE]>
 }
 impl Chunk {
 	pub fn new() -> Self {
+		Chunk::filled(Default::default())
+	}
+	// rows run along the x axis, and are indexed by y then z
+	fn row_index(y: usize, z: usize) -> usize {
+		y + z * CHUNK_SIZE
+	}
+}
+impl ChunkStorage for Chunk {
+	fn filled(voxel: Voxel) -> Self {
+		Chunk {
+			rows: Box::new(std::array::from_fn(|_| RowSlot::new(voxel)))
+		}
+	}
+	/*
+	 * Each row is compacted once, which is much faster than putting every
+	 * voxel.
+	 */
+	fn from_fn(mut voxel: impl FnMut(usize, usize, usize) -> Voxel) -> Self {
 		Chunk {
-			rows: Vec::new()
+			rows: Box::new(std::array::from_fn(|row_index| {
+				let (y, z) = (row_index % CHUNK_SIZE, row_index / CHUNK_SIZE);
+				RowSlot::compact(& std::array::from_fn(|x| voxel(x, y, z)))
+			}))
 		}
 	}
+	fn get(& self, x: usize, y: usize, z: usize) -> Voxel {
+		self.rows[Chunk::row_index(y, z)].row().get(x)
+	}
+	fn put(& mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+		self.rows[Chunk::row_index(y, z)].put(x, voxel);
+	}
+	// includes the row table and any rows allocated on the heap
+	fn memory_usage(& self) -> usize {
+		std::mem::size_of::<Self>() +
+			std::mem::size_of_val(self.rows.as_ref()) +
+			self.rows.iter().map(RowSlot::heap_size).sum::<usize>()
+	}
+}
+impl Default for Chunk {
+	fn default() -> Self {
+		Chunk::new()
+	}
 }
 
 /*
  * ChunkPosition
  *
  * Used as a key for VoxelMap, and determines the relative location of
- * chunks to each other.
+ * chunks to each other. Coordinates are signed, so chunks may extend in
+ * every direction from the origin.
  */
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkPosition {
-	x: i32,
-	y: i32,
-	z: i32
+	pub x: i32,
+	pub y: i32,
+	pub z: i32
 }
 impl ChunkPosition {
+	pub const fn new(x: i32, y: i32, z: i32) -> Self {
+		ChunkPosition { x, y, z }
+	}
+	pub fn as_ivec3(& self) -> IVec3 {
+		IVec3::new(self.x, self.y, self.z)
+	}
+	/*
+	 * The number of chunks between this position and another along the
+	 * axis on which they are furthest apart (the Chebyshev distance).
+	 */
+	pub fn distance(& self, other: ChunkPosition) -> i32 {
+		(self.as_ivec3() - other.as_ivec3()).abs().max_element()
+	}
 	pub fn exceeds_bounds(& self, bounds: ChunkPosition) -> bool {
 		self.x.abs() > bounds.x ||
 			self.y.abs() > bounds.y ||
 			self.z.abs() > bounds.z
 	}
 }
+impl From<IVec3> for ChunkPosition {
+	fn from(vector: IVec3) -> Self {
+		ChunkPosition::new(vector.x, vector.y, vector.z)
+	}
+}
+impl Add<IVec3> for ChunkPosition {
+	type Output = ChunkPosition;
+
+	fn add(self, offset: IVec3) -> ChunkPosition {
+		ChunkPosition::new(self.x + offset.x, self.y + offset.y, self.z + offset.z)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Chunk;
+	use crate::storage::ChunkStorage;
+	use crate::storage::row::{Voxel, CHUNK_SIZE};
+
+	#[test]
+	fn chunk_reads_back_writes() {
+		let mut chunk = Chunk::new();
+		chunk.put(0, 0, 0, Voxel(1));
+		chunk.put(31, 5, 17, Voxel(0x0203));
+		chunk.put(12, 31, 31, Voxel(0xffff));
+		assert_eq!(chunk.get(0, 0, 0), Voxel(1));
+		assert_eq!(chunk.get(1, 0, 0), Voxel(0));
+		assert_eq!(chunk.get(31, 5, 17), Voxel(0x0203));
+		assert_eq!(chunk.get(12, 31, 31), Voxel(0xffff));
+		assert_eq!(chunk.get(12, 31, 30), Voxel(0));
+	}
+
+	#[test]
+	fn chunk_from_fn_matches_puts() {
+		let voxel = |x: usize, y: usize, z: usize| Voxel(((x * y + z) % 5) as u16);
+		let built = Chunk::from_fn(voxel);
+		let mut put = Chunk::new();
+		for x in 0..CHUNK_SIZE {
+			for y in 0..CHUNK_SIZE {
+				for z in 0..CHUNK_SIZE {
+					put.put(x, y, z, voxel(x, y, z));
+				}
+			}
+		}
+		for x in 0..CHUNK_SIZE {
+			for y in 0..CHUNK_SIZE {
+				for z in 0..CHUNK_SIZE {
+					assert_eq!(built.get(x, y, z), voxel(x, y, z));
+					assert_eq!(put.get(x, y, z), voxel(x, y, z));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn mostly_empty_chunks_are_small() {
+		let flat_size = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * std::mem::size_of::<Voxel>();
+		let mut chunk = Chunk::new();
+		for x in 0..CHUNK_SIZE {
+			for z in 0..CHUNK_SIZE {
+				chunk.put(x, 0, z, Voxel(1));
+			}
+		}
+		chunk.put(4, 1, 4, Voxel(2));
+		assert!(chunk.memory_usage() * 3 < flat_size);
+	}
+}
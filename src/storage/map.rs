@@ -1,38 +1,63 @@
 use bevy::utils::HashMap;
 
-use super::chunk::{Chunk, ChunkPosition};
+use super::chunk::ChunkPosition;
 
 /*
  * VoxelMap
  *
- * Stores Chunks in a HashMap, indexed to by ChunkPosition.
+ * Stores chunks in a HashMap, indexed to by ChunkPosition. Chunks are
+ * only created once they are requested, so the map may extend in every
+ * direction (including negative space) up to the configured bounds.
  */
-pub struct VoxelMap {
-	chunks: HashMap<ChunkPosition, Chunk>,
+pub struct VoxelMap<C> {
+	chunks: HashMap<ChunkPosition, C>,
 	settings: VoxelMapSettings
 }
-impl VoxelMap {
+impl<C> VoxelMap<C> {
 	pub fn new(settings: VoxelMapSettings) -> Self {
 		VoxelMap {
 			chunks: HashMap::default(),
 			settings
 		}
 	}
-	pub fn get_chunk(& self, position: ChunkPosition) -> Option<& Chunk> {
-		if position.exceeds_bounds(self.settings.bounds) {
+	pub fn get_chunk(& self, position: ChunkPosition) -> Option<& C> {
+		self.chunks.get(& position)
+	}
+	pub fn get_chunk_mut(& mut self, position: ChunkPosition) -> Option<& mut C> {
+		self.chunks.get_mut(& position)
+	}
+	pub fn contains_chunk(& self, position: ChunkPosition) -> bool {
+		self.chunks.contains_key(& position)
+	}
+	pub fn in_bounds(& self, position: ChunkPosition) -> bool {
+		!position.exceeds_bounds(self.settings.bounds)
+	}
+	/*
+	 * Returns the chunk at the given position, calling `create` to build it
+	 * first if it does not exist yet. Positions outside of the map's bounds
+	 * are never created, and return None.
+	 */
+	pub fn get_or_create_chunk(
+		& mut self,
+		position: ChunkPosition,
+		create: impl FnOnce(ChunkPosition) -> C
+	) -> Option<& mut C> {
+		if !self.in_bounds(position) {
 			return None
 		}
-		if !self.chunks.contains_key(& position) {
-			self.chunks.insert(position, Chunk::new());
-		}
-		self.chunks.get(& position)
+		Some(self.chunks.entry(position).or_insert_with(|| create(position)))
+	}
+	/*
+	 * Drops every chunk for which `keep` returns false, freeing its voxel
+	 * data. Dropped chunks will be created again if they are requested.
+	 */
+	pub fn retain(& mut self, mut keep: impl FnMut(ChunkPosition) -> bool) {
+		self.chunks.retain(|position, _| keep(*position));
 	}
 }
-impl Default for VoxelMap {
+impl<C> Default for VoxelMap<C> {
 	fn default() -> Self {
-		VoxelMap {
-			..Default::default()
-		}
+		VoxelMap::new(Default::default())
 	}
 }
 
@@ -43,7 +68,7 @@ impl Default for VoxelMap {
  * global values for the VoxelMap.
  */
 pub struct VoxelMapSettings {
-	bounds: ChunkPosition
+	pub bounds: ChunkPosition
 }
 impl Default for VoxelMapSettings {
 	fn default() -> Self {
@@ -1,3 +1,83 @@
-mod row;
-mod chunk;
-mod map;
+use row::Voxel;
+
+pub mod chunk;
+pub mod map;
+pub mod palette;
+pub mod row;
+
+/*
+ * ChunkStorage
+ *
+ * The interface shared by every chunk storage backend. Backends differ
+ * only in how they compress a chunk's 32^3 voxels: storage::chunk::Chunk
+ * compresses each row separately, which suits chunks that are mostly a
+ * single voxel, while palette::PaletteChunk suits chunks with many
+ * distinct voxels scattered throughout.
+ */
+pub trait ChunkStorage: Clone + Send + Sync + 'static {
+	fn filled(voxel: Voxel) -> Self;
+	fn from_fn(voxel: impl FnMut(usize, usize, usize) -> Voxel) -> Self;
+	fn get(& self, x: usize, y: usize, z: usize) -> Voxel;
+	fn put(& mut self, x: usize, y: usize, z: usize, voxel: Voxel);
+	// approximate number of bytes used by the chunk
+	fn memory_usage(& self) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::prelude::*;
+
+	use super::chunk::Chunk;
+	use super::palette::PaletteChunk;
+	use super::row::{CHUNK_SIZE, Voxel};
+	use super::ChunkStorage;
+
+	fn assert_equivalent(row_chunk: & Chunk, palette_chunk: & PaletteChunk) {
+		for x in 0..CHUNK_SIZE {
+			for y in 0..CHUNK_SIZE {
+				for z in 0..CHUNK_SIZE {
+					assert_eq!(row_chunk.get(x, y, z), palette_chunk.get(x, y, z));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn backends_are_equivalent_after_random_puts() {
+		let mut rng = StdRng::seed_from_u64(7);
+		for materials in [2, 40, 300, u16::MAX] {
+			let mut row_chunk = Chunk::filled(Voxel(0));
+			let mut palette_chunk = PaletteChunk::filled(Voxel(0));
+			for _ in 0..5000 {
+				let [x, y, z] = [(); 3].map(|_| rng.gen_range(0..CHUNK_SIZE));
+				let voxel = Voxel(rng.gen_range(0..materials));
+				row_chunk.put(x, y, z, voxel);
+				palette_chunk.put(x, y, z, voxel);
+			}
+			assert_equivalent(& row_chunk, & palette_chunk);
+		}
+	}
+
+	#[test]
+	fn backends_are_equivalent_from_fn() {
+		let terrain = |x: usize, y: usize, z: usize| {
+			if y < 8 + (x * z) % 3 {
+				Voxel(1 + ((x + z) % 4) as u16)
+			} else {
+				Voxel(0)
+			}
+		};
+		let row_chunk = Chunk::from_fn(terrain);
+		let palette_chunk = PaletteChunk::from_fn(terrain);
+		assert_equivalent(& row_chunk, & palette_chunk);
+	}
+
+	#[test]
+	fn palette_is_smaller_for_noisy_chunks() {
+		let mut rng = StdRng::seed_from_u64(11);
+		let noise = |_, _, _| Voxel(rng.gen_range(0..12));
+		let palette_chunk = PaletteChunk::from_fn(noise);
+		let row_chunk = Chunk::from_fn(|x, y, z| palette_chunk.get(x, y, z));
+		assert!(palette_chunk.memory_usage() * 2 < row_chunk.memory_usage());
+	}
+}
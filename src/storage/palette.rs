@@ -0,0 +1,195 @@
+use super::ChunkStorage;
+use super::row::{CHUNK_SIZE, Voxel};
+
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/*
+ * PaletteChunk
+ *
+ * Stores each distinct voxel of the chunk once in a palette, and every
+ * voxel as a bit-packed index into that palette. The bit width of the
+ * indices grows as the palette does, so chunks with few distinct voxels
+ * stay small no matter how they are arranged, and chunks with many
+ * distinct voxels cost at most one u16 per voxel.
+ */
+#[derive(Clone)]
+pub struct PaletteChunk {
+	palette: Vec<Voxel>,
+	// how many voxels of the chunk refer to each palette entry; entries
+	// with a count of zero are reused before the palette grows
+	counts: Vec<u32>,
+	bits: u32,
+	indices: Vec<u64>
+}
+impl PaletteChunk {
+	// voxels are linearized x first, then y, then z
+	fn linearize(x: usize, y: usize, z: usize) -> usize {
+		x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE
+	}
+	// the number of bits needed to index a palette of the given length
+	fn bits_for(palette_len: usize) -> u32 {
+		usize::BITS - palette_len.saturating_sub(1).leading_zeros()
+	}
+	// indices never straddle two words, so some bits of each word may be
+	// left unused
+	fn indices_per_word(bits: u32) -> usize {
+		(u64::BITS / bits) as usize
+	}
+	fn index(& self, voxel_index: usize) -> usize {
+		if self.bits == 0 {
+			return 0
+		}
+		let per_word = PaletteChunk::indices_per_word(self.bits);
+		let word = self.indices[voxel_index / per_word];
+		let shift = (voxel_index % per_word) as u32 * self.bits;
+		((word >> shift) & ((1 << self.bits) - 1)) as usize
+	}
+	fn set_index(& mut self, voxel_index: usize, palette_index: usize) {
+		let per_word = PaletteChunk::indices_per_word(self.bits);
+		let word = & mut self.indices[voxel_index / per_word];
+		let shift = (voxel_index % per_word) as u32 * self.bits;
+		let mask = ((1u64 << self.bits) - 1) << shift;
+		*word = (*word & !mask) | ((palette_index as u64) << shift);
+	}
+	/*
+	 * Repacks every index with the given bit width.
+	 */
+	fn resize(& mut self, bits: u32) {
+		let old_indices: Vec<usize> = (0..CHUNK_VOLUME).map(|index| self.index(index)).collect();
+		self.bits = bits;
+		self.indices = if bits == 0 {
+			Vec::new()
+		} else {
+			vec![0; CHUNK_VOLUME.div_ceil(PaletteChunk::indices_per_word(bits))]
+		};
+		if bits != 0 {
+			for (voxel_index, palette_index) in old_indices.into_iter().enumerate() {
+				self.set_index(voxel_index, palette_index);
+			}
+		}
+	}
+	/*
+	 * Returns the palette index of the voxel, adding it to the palette (and
+	 * widening the indices if needed) when it isn't present.
+	 */
+	fn palette_index(& mut self, voxel: Voxel) -> usize {
+		if let Some(index) = self.palette.iter().position(|entry| *entry == voxel) {
+			return index
+		}
+		if let Some(index) = self.counts.iter().position(|count| *count == 0) {
+			self.palette[index] = voxel;
+			return index
+		}
+		self.palette.push(voxel);
+		self.counts.push(0);
+		let bits = PaletteChunk::bits_for(self.palette.len());
+		if bits > self.bits {
+			self.resize(bits);
+		}
+		self.palette.len() - 1
+	}
+	pub fn palette_len(& self) -> usize {
+		self.palette.len()
+	}
+}
+impl ChunkStorage for PaletteChunk {
+	fn filled(voxel: Voxel) -> Self {
+		PaletteChunk {
+			palette: vec![voxel],
+			counts: vec![CHUNK_VOLUME as u32],
+			bits: 0,
+			indices: Vec::new()
+		}
+	}
+	fn from_fn(mut voxel: impl FnMut(usize, usize, usize) -> Voxel) -> Self {
+		let mut palette = Vec::new();
+		let mut counts = Vec::new();
+		let mut voxel_indices = Vec::with_capacity(CHUNK_VOLUME);
+		for z in 0..CHUNK_SIZE {
+			for y in 0..CHUNK_SIZE {
+				for x in 0..CHUNK_SIZE {
+					let voxel = voxel(x, y, z);
+					let index = match palette.iter().position(|entry| *entry == voxel) {
+						Some(index) => index,
+						None => {
+							palette.push(voxel);
+							counts.push(0);
+							palette.len() - 1
+						}
+					};
+					counts[index] += 1;
+					voxel_indices.push(index);
+				}
+			}
+		}
+		let mut chunk = PaletteChunk {
+			palette,
+			counts,
+			bits: 0,
+			indices: Vec::new()
+		};
+		let bits = PaletteChunk::bits_for(chunk.palette.len());
+		if bits > 0 {
+			chunk.resize(bits);
+			for (voxel_index, palette_index) in voxel_indices.into_iter().enumerate() {
+				chunk.set_index(voxel_index, palette_index);
+			}
+		}
+		chunk
+	}
+	fn get(& self, x: usize, y: usize, z: usize) -> Voxel {
+		self.palette[self.index(PaletteChunk::linearize(x, y, z))]
+	}
+	fn put(& mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+		let voxel_index = PaletteChunk::linearize(x, y, z);
+		let old_index = self.index(voxel_index);
+		if self.palette[old_index] == voxel {
+			return
+		}
+		self.counts[old_index] -= 1;
+		let new_index = self.palette_index(voxel);
+		self.counts[new_index] += 1;
+		self.set_index(voxel_index, new_index);
+	}
+	fn memory_usage(& self) -> usize {
+		std::mem::size_of::<Self>() +
+			self.palette.capacity() * std::mem::size_of::<Voxel>() +
+			self.counts.capacity() * std::mem::size_of::<u32>() +
+			self.indices.capacity() * std::mem::size_of::<u64>()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::PaletteChunk;
+	use crate::storage::ChunkStorage;
+	use crate::storage::row::{CHUNK_SIZE, Voxel};
+
+	#[test]
+	fn palette_widens_indices_as_it_grows() {
+		let mut chunk = PaletteChunk::filled(Voxel(0));
+		assert_eq!(chunk.bits, 0);
+		chunk.put(1, 2, 3, Voxel(10));
+		assert_eq!(chunk.bits, 1);
+		chunk.put(4, 5, 6, Voxel(20));
+		assert_eq!(chunk.bits, 2);
+		for material in 0..40 {
+			chunk.put(material % CHUNK_SIZE, 7, material / CHUNK_SIZE, Voxel(100 + material as u16));
+		}
+		assert_eq!(chunk.bits, 6);
+		assert_eq!(chunk.get(1, 2, 3), Voxel(10));
+		assert_eq!(chunk.get(4, 5, 6), Voxel(20));
+		assert_eq!(chunk.get(7, 7, 1), Voxel(139));
+		assert_eq!(chunk.get(0, 0, 0), Voxel(0));
+	}
+
+	#[test]
+	fn unused_palette_entries_are_reused() {
+		let mut chunk = PaletteChunk::filled(Voxel(0));
+		chunk.put(0, 0, 0, Voxel(1));
+		chunk.put(0, 0, 0, Voxel(2));
+		chunk.put(0, 0, 0, Voxel(3));
+		assert_eq!(chunk.palette_len(), 2);
+		assert_eq!(chunk.get(0, 0, 0), Voxel(3));
+	}
+}
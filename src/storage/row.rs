@@ -7,34 +7,42 @@ pub const CHUNK_SIZE: usize = 32;
  * This struct stores the characteristics that every cell holds at a
  * minimum. This includes the material that fills the cell.
  */
-struct Voxel(u16);
-impl Default for Voxel {
-	fn default() -> Self {
-		Voxel(0)
-	}
-}
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Voxel(pub u16);
 
 /*
  * Row
+ *
+ * A line of CHUNK_SIZE voxels along the x axis of a chunk. Compressed rows
+ * only store the bits of each voxel that differ across the row: the bits
+ * set in compress_mask are shared by every voxel, and their value is given
+ * by compress_value. Uncompressed rows have a compress_mask of zero.
  */
 pub trait Row {
-	fn is_compressed() -> bool;
 	fn compress_value(& self) -> Voxel;
 	fn compress_mask(& self) -> u16;
 	fn get(& self, index: usize) -> Voxel;
-	fn put(& self, index: usize, voxel: Voxel);
+	fn put(& mut self, index: usize, voxel: Voxel);
+
+	// whether the voxel can be put into this row without changing row kind
+	fn can_hold(& self, voxel: Voxel) -> bool {
+		voxel.0 & self.compress_mask() == self.compress_value().0
+	}
+	fn to_array(& self) -> [Voxel; CHUNK_SIZE] {
+		std::array::from_fn(|index| self.get(index))
+	}
 }
 
 /*
  * FullRow
+ *
+ * Stores every voxel of the row in full.
  */
+#[derive(Clone)]
 pub struct FullRow {
 	voxels: [Voxel; CHUNK_SIZE]
 }
 impl Row for FullRow {
-	fn is_compressed() -> bool {
-		false
-	}
 	fn compress_value(& self) -> Voxel {
 		Default::default()
 	}
@@ -44,76 +52,233 @@ impl Row for FullRow {
 	fn get(& self, index: usize) -> Voxel {
 		self.voxels[index]
 	}
-	fn put(& self, index: usize, voxel: Voxel) {
+	fn put(& mut self, index: usize, voxel: Voxel) {
 		self.voxels[index] = voxel;
 	}
 }
 
 /*
- * HalfRow {
+ * HalfRow
+ *
+ * Used when every voxel in the row shares either its first (high) or
+ * second (low) byte. The shared byte is stored once, and only the other
+ * byte is stored per voxel.
  */
+#[derive(Clone)]
 pub struct HalfRow {
 	voxels: [u8; CHUNK_SIZE],
 	half_voxel: u8,
 	first_half: bool
 }
-impl Row for HalfRow {
-	fn is_compressed() -> bool {
-		true
-	}
-	fn compress_value(& self) -> Voxel {
+impl HalfRow {
+	fn join(& self, byte: u8) -> Voxel {
 		if self.first_half {
-
+			Voxel(u16::from_be_bytes([self.half_voxel, byte]))
 		} else {
-
+			Voxel(u16::from_be_bytes([byte, self.half_voxel]))
 		}
-		Default::default()
 	}
-	fn compress_mask(& self) -> u16 {
+	fn split(& self, voxel: Voxel) -> u8 {
+		let [first, second] = voxel.0.to_be_bytes();
 		if self.first_half {
-
+			second
 		} else {
-
+			first
 		}
-		0xffff
 	}
-	fn get(& self, index: usize) -> Voxel {
-		if self.first_half {
-
-		} else {
-
-		}
-		Default::default()
+}
+impl Row for HalfRow {
+	fn compress_value(& self) -> Voxel {
+		self.join(0)
 	}
-	fn put(& self, index: usize, voxel: Voxel) {
+	fn compress_mask(& self) -> u16 {
 		if self.first_half {
-
+			0xff00
 		} else {
-
+			0x00ff
 		}
 	}
+	fn get(& self, index: usize) -> Voxel {
+		self.join(self.voxels[index])
+	}
+	fn put(& mut self, index: usize, voxel: Voxel) {
+		debug_assert!(self.can_hold(voxel));
+		self.voxels[index] = self.split(voxel);
+	}
 }
 
 /*
  * SingleRow
+ *
+ * Used when every voxel in the row is the same.
  */
+#[derive(Default, Clone)]
 pub struct SingleRow {
 	voxel: Voxel
 }
 impl Row for SingleRow {
-	fn is_compressed() -> bool {
-		true
-	}
 	fn compress_value(& self) -> Voxel {
 		self.voxel
 	}
 	fn compress_mask(& self) -> u16 {
 		0xffff
 	}
-	fn get(& self, index: usize) -> Voxel {
+	fn get(& self, _index: usize) -> Voxel {
 		self.voxel
 	}
-	fn put(& self, index: usize, voxel: Voxel) {
+	fn put(& mut self, _index: usize, voxel: Voxel) {
+		debug_assert!(self.can_hold(voxel));
 		self.voxel = voxel;
 	}
 }
+
+/*
+ * RowSlot
+ *
+ * Holds a row of whichever kind is most compact for its contents. Single
+ * rows are kept inline so that uniform rows (the common case, such as
+ * rows of air) need no allocation; the larger kinds are boxed.
+ */
+#[derive(Clone)]
+pub enum RowSlot {
+	Single(SingleRow),
+	Half(Box<HalfRow>),
+	Full(Box<FullRow>)
+}
+impl RowSlot {
+	pub fn new(voxel: Voxel) -> Self {
+		RowSlot::Single(SingleRow { voxel })
+	}
+	/*
+	 * Picks the most compact row kind able to hold the given voxels.
+	 */
+	pub fn compact(voxels: & [Voxel; CHUNK_SIZE]) -> Self {
+		let first = voxels[0];
+		if voxels.iter().all(|voxel| *voxel == first) {
+			return RowSlot::new(first)
+		}
+		let [high, low] = first.0.to_be_bytes();
+		for first_half in [true, false] {
+			let half_voxel = if first_half { high } else { low };
+			let mut row = HalfRow {
+				voxels: [0; CHUNK_SIZE],
+				half_voxel,
+				first_half
+			};
+			if voxels.iter().all(|voxel| row.can_hold(*voxel)) {
+				for (index, voxel) in voxels.iter().enumerate() {
+					row.put(index, *voxel);
+				}
+				return RowSlot::Half(Box::new(row))
+			}
+		}
+		RowSlot::Full(Box::new(FullRow { voxels: *voxels }))
+	}
+	pub fn row(& self) -> & dyn Row {
+		match self {
+			RowSlot::Single(row) => row,
+			RowSlot::Half(row) => row.as_ref(),
+			RowSlot::Full(row) => row.as_ref()
+		}
+	}
+	fn row_mut(& mut self) -> & mut dyn Row {
+		match self {
+			RowSlot::Single(row) => row,
+			RowSlot::Half(row) => row.as_mut(),
+			RowSlot::Full(row) => row.as_mut()
+		}
+	}
+	/*
+	 * Writes a voxel, promoting the row to a less compressed kind if it
+	 * can't hold the voxel, or demoting it to a more compressed kind if the
+	 * write made that possible. Writes that keep the row's kind are made in
+	 * place.
+	 */
+	pub fn put(& mut self, index: usize, voxel: Voxel) {
+		if self.row().get(index) == voxel {
+			return
+		}
+		if !self.row().can_hold(voxel) {
+			let mut voxels = self.row().to_array();
+			voxels[index] = voxel;
+			*self = RowSlot::compact(& voxels);
+			return
+		}
+		self.row_mut().put(index, voxel);
+		if self.can_demote() {
+			*self = RowSlot::compact(& self.row().to_array());
+		}
+	}
+	// whether a more compressed row kind could hold this row's voxels
+	fn can_demote(& self) -> bool {
+		match self {
+			RowSlot::Single(_) => false,
+			RowSlot::Half(row) => row.voxels.iter().all(|byte| *byte == row.voxels[0]),
+			RowSlot::Full(row) => {
+				let [high, low] = row.voxels[0].0.to_be_bytes();
+				row.voxels.iter().all(|voxel| voxel.0.to_be_bytes()[0] == high)
+					|| row.voxels.iter().all(|voxel| voxel.0.to_be_bytes()[1] == low)
+			}
+		}
+	}
+	// bytes allocated on the heap by this row
+	pub fn heap_size(& self) -> usize {
+		match self {
+			RowSlot::Single(_) => 0,
+			RowSlot::Half(_) => std::mem::size_of::<HalfRow>(),
+			RowSlot::Full(_) => std::mem::size_of::<FullRow>()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{RowSlot, Voxel, CHUNK_SIZE};
+
+	#[test]
+	fn rows_promote_and_demote() {
+		let mut slot = RowSlot::new(Voxel(0));
+		slot.put(3, Voxel(0x0007));
+		assert!(matches!(slot, RowSlot::Half(_)));
+		assert_eq!(slot.row().compress_mask(), 0xff00);
+		slot.put(4, Voxel(0x0700));
+		assert!(matches!(slot, RowSlot::Full(_)));
+		slot.put(4, Voxel(0));
+		assert!(matches!(slot, RowSlot::Half(_)));
+		slot.put(3, Voxel(0));
+		assert!(matches!(slot, RowSlot::Single(_)));
+		assert_eq!(slot.row().to_array(), [Voxel(0); CHUNK_SIZE]);
+	}
+
+	#[test]
+	fn half_rows_share_either_byte() {
+		let mut voxels = [Voxel(0x1234); CHUNK_SIZE];
+		voxels[9] = Voxel(0x5634);
+		let slot = RowSlot::compact(& voxels);
+		assert!(matches!(slot, RowSlot::Half(_)));
+		assert_eq!(slot.row().compress_mask(), 0x00ff);
+		assert_eq!(slot.row().compress_value(), Voxel(0x0034));
+		assert_eq!(slot.row().to_array(), voxels);
+		assert!(!slot.row().can_hold(Voxel(0x1235)));
+	}
+
+	#[test]
+	fn full_rows_are_written_in_place() {
+		let mut voxels = [Voxel(0); CHUNK_SIZE];
+		voxels[0] = Voxel(0x0101);
+		voxels[1] = Voxel(0x0202);
+		voxels[2] = Voxel(0x0303);
+		let mut slot = RowSlot::compact(& voxels);
+		let RowSlot::Full(row) = & slot else {
+			panic!("expected a full row")
+		};
+		let address: * const _ = row.as_ref();
+		slot.put(5, Voxel(0x0405));
+		voxels[5] = Voxel(0x0405);
+		let RowSlot::Full(row) = & slot else {
+			panic!("expected a full row")
+		};
+		assert_eq!(address, row.as_ref() as * const _);
+		assert_eq!(slot.row().to_array(), voxels);
+	}
+}
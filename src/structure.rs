@@ -0,0 +1,255 @@
+use crate::chunk::CHUNK_DIM;
+use crate::material::{LEAVES, LOG, STONE};
+use crate::noise::{mix, SplitMix};
+use crate::storage::chunk::ChunkPosition;
+use crate::voxel::Voxel;
+use bevy::prelude::*;
+
+/// How far any voxel of a structure may lie from its origin along each axis.
+/// Structures never reach further than the chunks neighbouring the one their
+/// origin lies in, so a chunk only has to gather structures from its
+/// neighbours when it is decorated.
+pub const MAX_STRUCTURE_REACH: i32 = CHUNK_DIM as i32;
+
+/// One voxel of a structure, relative to the structure's origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StructureVoxel {
+    pub offset: IVec3,
+    pub voxel: Voxel,
+    /// whether the voxel replaces solid terrain, or only fills empty space
+    pub replace: bool,
+}
+
+/// A shape of voxels placed into the world during generation, such as a tree
+/// or a boulder.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Structure {
+    voxels: Vec<StructureVoxel>,
+}
+
+impl Structure {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a voxel to the structure. Voxels placed later win over earlier
+    /// ones at the same offset.
+    pub fn place(&mut self, offset: IVec3, voxel: Voxel, replace: bool) {
+        assert!(
+            offset.abs().max_element() <= MAX_STRUCTURE_REACH,
+            "structure voxel {offset} is out of reach of its origin"
+        );
+        self.voxels.push(StructureVoxel {
+            offset,
+            voxel,
+            replace,
+        });
+    }
+
+    pub fn voxels(&self) -> &[StructureVoxel] {
+        &self.voxels
+    }
+}
+
+/// A structure positioned in the world, with its origin in world space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlacedStructure {
+    pub origin: IVec3,
+    pub structure: Structure,
+}
+
+/// The kinds of structure biomes scatter over their surface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoration {
+    Tree,
+    Boulder,
+    /// a few crumbling stone pillars, always laid out the same way
+    Ruin,
+}
+
+impl Decoration {
+    /// Builds the decoration with its origin on the first empty voxel above
+    /// the surface, drawing any variation from `rng`.
+    pub fn build(&self, rng: &mut SplitMix) -> Structure {
+        let mut structure = Structure::new();
+        match self {
+            Decoration::Tree => {
+                let height = 4 + rng.below(3) as i32;
+                for y in height - 2..=height + 1 {
+                    let radius: i32 = if y > height - 1 { 1 } else { 2 };
+                    for x in -radius..=radius {
+                        for z in -radius..=radius {
+                            // round off the corners of the canopy, leaving a
+                            // few in place so trees don't all look alike
+                            let corner = x.abs() == radius && z.abs() == radius;
+                            if corner && rng.chance(0.7) {
+                                continue;
+                            }
+                            structure.place(IVec3::new(x, y, z), Voxel::new(LEAVES), false);
+                        }
+                    }
+                }
+                for y in 0..height {
+                    structure.place(IVec3::new(0, y, 0), Voxel::new(LOG), true);
+                }
+            }
+            Decoration::Boulder => {
+                let radius = 1.5 + rng.unit() * 1.5;
+                let extent = radius.ceil() as i32;
+                // sink the boulder partway into the ground
+                let center = Vec3::new(0., radius * 0.5 - 1., 0.);
+                for x in -extent..=extent {
+                    for y in -extent..=extent {
+                        for z in -extent..=extent {
+                            let offset = IVec3::new(x, y, z);
+                            if offset.as_vec3().distance(center) <= radius {
+                                structure.place(offset, Voxel::new(STONE), true);
+                            }
+                        }
+                    }
+                }
+            }
+            Decoration::Ruin => {
+                let pillars = [
+                    (IVec3::new(-2, 0, -2), 3),
+                    (IVec3::new(2, 0, -2), 4),
+                    (IVec3::new(-2, 0, 2), 2),
+                    (IVec3::new(2, 0, 2), 4),
+                ];
+                for (corner, height) in pillars {
+                    // foundations keep the pillars from floating on slopes
+                    for y in -2..height {
+                        structure.place(corner + IVec3::Y * y, Voxel::new(STONE), true);
+                    }
+                }
+                for z in -2..=2 {
+                    structure.place(IVec3::new(2, 4, z), Voxel::new(STONE), true);
+                }
+            }
+        }
+        structure
+    }
+}
+
+/// A random number generator seeded from the world seed and a chunk position,
+/// so that a chunk always draws the same numbers no matter when, or in which
+/// order, chunks are generated.
+pub fn chunk_rng(seed: u64, chunk_pos: ChunkPosition) -> SplitMix {
+    let mut hash = seed;
+    for coordinate in [chunk_pos.x, chunk_pos.y, chunk_pos.z] {
+        hash = mix(hash ^ coordinate as u32 as u64);
+    }
+    SplitMix::new(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::{Chunk, CHUNK_DIM};
+    use crate::material::{LOG, STONE};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::structure::{chunk_rng, PlacedStructure, Structure};
+    use crate::terrain::{LayeredTerrain, TerrainGenerator};
+    use crate::voxel::{Voxel, EMPTY};
+    use bevy::prelude::*;
+
+    // flat ground at y < 0, with a log bar crossing the border between chunks
+    // (0, 0, 0) and (1, 0, 0), and a stone pillar in chunk (0, 0, 0)
+    struct BarTerrain;
+
+    impl TerrainGenerator for BarTerrain {
+        fn voxel(&self, position: IVec3) -> Voxel {
+            if position.y < 0 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        }
+
+        fn structures(&self, origin_chunk: ChunkPosition) -> Vec<PlacedStructure> {
+            if origin_chunk != ChunkPosition::new(0, 0, 0) {
+                return Vec::new();
+            }
+            let mut bar = Structure::new();
+            for x in 0..6 {
+                bar.place(IVec3::new(x, 0, 0), Voxel::new(LOG), true);
+            }
+            let mut pillar = Structure::new();
+            for y in -2..3 {
+                pillar.place(IVec3::new(0, y, 0), Voxel::new(STONE), false);
+            }
+            vec![
+                PlacedStructure {
+                    origin: IVec3::new(29, 0, 4),
+                    structure: bar,
+                },
+                PlacedStructure {
+                    origin: IVec3::new(3, 0, 3),
+                    structure: pillar,
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn structures_straddle_chunk_borders() {
+        let mut left: Chunk = Chunk::generate(ChunkPosition::new(0, 0, 0), &BarTerrain);
+        let mut right: Chunk = Chunk::generate(ChunkPosition::new(1, 0, 0), &BarTerrain);
+        // decorate the chunks in the opposite order to the one they were
+        // generated in
+        right.decorate(ChunkPosition::new(1, 0, 0), &BarTerrain);
+        left.decorate(ChunkPosition::new(0, 0, 0), &BarTerrain);
+        for x in 29..CHUNK_DIM {
+            assert_eq!(left.get([x, 0, 4]).material, LOG);
+        }
+        for x in 0..3 {
+            assert_eq!(right.get([x, 0, 4]).material, LOG);
+        }
+        assert_eq!(right.get([3, 0, 4]), EMPTY);
+        assert_eq!(left.get([3, 2, 3]).material, STONE);
+    }
+
+    #[test]
+    fn decorations_do_not_depend_on_generation_order() {
+        let terrain = LayeredTerrain::new(21);
+        let positions: Vec<ChunkPosition> = (-1..=1)
+            .flat_map(|x| (0..=1).map(move |y| ChunkPosition::new(x, y, 0)))
+            .collect();
+        let generate = |pos: ChunkPosition| {
+            let mut chunk: Chunk = Chunk::generate(pos, &terrain);
+            chunk.decorate(pos, &terrain);
+            chunk
+        };
+        let forwards: Vec<Chunk> = positions.iter().map(|pos| generate(*pos)).collect();
+        let mut backwards: Vec<Chunk> = positions.iter().rev().map(|pos| generate(*pos)).collect();
+        backwards.reverse();
+        for (first, second) in forwards.iter().zip(&backwards) {
+            for x in 0..CHUNK_DIM {
+                for y in 0..CHUNK_DIM {
+                    let local = [x, y, (x * 7 + y) % CHUNK_DIM];
+                    assert_eq!(first.get(local), second.get(local));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layered_terrain_places_decorations() {
+        let terrain = LayeredTerrain::new(21);
+        let placed: usize = (-4..4)
+            .flat_map(|x| (-2..4).map(move |y| ChunkPosition::new(x, y, x)))
+            .map(|pos| terrain.structures(pos).len())
+            .sum();
+        assert!(placed > 0);
+        let origin = ChunkPosition::new(1, 0, 1);
+        assert_eq!(terrain.structures(origin), terrain.structures(origin));
+    }
+
+    #[test]
+    fn chunk_rng_is_keyed_by_position() {
+        let draw = |seed, pos| chunk_rng(seed, pos).next_u64();
+        let pos = ChunkPosition::new(3, -1, 8);
+        assert_eq!(draw(1, pos), draw(1, pos));
+        assert_ne!(draw(1, pos), draw(2, pos));
+        assert_ne!(draw(1, pos), draw(1, ChunkPosition::new(-1, 3, 8)));
+    }
+}
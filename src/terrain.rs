@@ -0,0 +1,279 @@
+use crate::biome::{Biome, BiomeColumn, BiomeMap};
+use crate::chunk::CHUNK_DIM;
+use crate::material::STONE;
+use crate::noise::{Perlin, SplitMix};
+use crate::storage::chunk::ChunkPosition;
+use crate::structure::{chunk_rng, PlacedStructure};
+use crate::voxel::{Voxel, EMPTY};
+use bevy::prelude::*;
+
+/// Decorations are scattered by jittering one candidate site within each cell
+/// of a grid this many voxels wide, so they never crowd each other too much.
+const DECORATION_CELL: i32 = 4;
+
+/// Decides which voxel fills each position of the world. Generators are
+/// evaluated in world space, so chunks generated separately (in any order)
+/// always line up at their borders.
+pub trait TerrainGenerator: Send + Sync + 'static {
+    fn voxel(&self, position: IVec3) -> Voxel;
+
+    /// Fills `voxels` with the column of voxels at `x` and `z`, starting at
+    /// `bottom` and going up. Generators that do work once per column, rather
+    /// than once per voxel, override this; chunks are generated a column at a
+    /// time.
+    fn column(&self, x: i32, z: i32, bottom: i32, voxels: &mut [Voxel]) {
+        for (y, voxel) in (bottom..).zip(voxels) {
+            *voxel = self.voxel(IVec3::new(x, y, z));
+        }
+    }
+
+    /// The structures whose origins lie in the given chunk, placed over the
+    /// terrain by `Chunk::decorate`. Like voxels, they must only depend on
+    /// the chunk position, never on which chunks have been generated.
+    fn structures(&self, _origin_chunk: ChunkPosition) -> Vec<PlacedStructure> {
+        Vec::new()
+    }
+}
+
+impl<F: Fn(IVec3) -> Voxel + Send + Sync + 'static> TerrainGenerator for F {
+    fn voxel(&self, position: IVec3) -> Voxel {
+        self(position)
+    }
+}
+
+/// Terrain built from layered noise: a fractal heightmap, shaped by the
+/// biome of each column, gives the rolling surface, 3D noise near the surface
+/// carves overhangs into it, and two intersecting sheets of ridged 3D noise
+/// below the surface carve winding tunnels.
+pub struct LayeredTerrain {
+    seed: u64,
+    height_noise: Perlin,
+    overhang_noise: Perlin,
+    cave_noise: Perlin,
+    /// gives the height profile and surface materials of each column
+    pub biomes: BiomeMap,
+    pub height_scale: f32,
+    pub overhang_amplitude: f32,
+    pub overhang_scale: f32,
+    pub cave_scale: f32,
+    /// caves are carved where both sheets of ridged cave noise fall below
+    /// this
+    pub cave_threshold: f32,
+}
+
+impl LayeredTerrain {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            // each layer gets its own noise, so they don't correlate
+            height_noise: Perlin::new(seed),
+            overhang_noise: Perlin::new(seed.wrapping_add(1)),
+            cave_noise: Perlin::new(seed.wrapping_add(2)),
+            biomes: BiomeMap::new(seed.wrapping_add(3), Biome::defaults()),
+            height_scale: 160.,
+            overhang_amplitude: 6.,
+            overhang_scale: 24.,
+            cave_scale: 48.,
+            cave_threshold: 0.1,
+        }
+    }
+
+    fn surface_height(&self, x: f32, z: f32, column: &BiomeColumn) -> f32 {
+        let point = Vec2::new(x, z) / self.height_scale;
+        column.base_height + self.height_noise.fbm2(point, 5) * column.height_amplitude
+    }
+
+    /// Roughly how far below the surface the position is; negative above it.
+    /// `surface` is the column's `surface_height`.
+    fn depth(&self, position: Vec3, surface: f32) -> f32 {
+        let depth = surface - position.y;
+        // the overhang noise only matters within its amplitude of the surface
+        if depth.abs() > self.overhang_amplitude {
+            return depth;
+        }
+        let point = position / self.overhang_scale;
+        depth + self.overhang_noise.fbm3(point, 2) * self.overhang_amplitude
+    }
+
+    /// The height of the highest solid voxel of the column near its
+    /// heightmap surface, if overhangs haven't carved that stretch away.
+    fn top_voxel(&self, x: i32, z: i32, column: &BiomeColumn) -> Option<i32> {
+        let height = self.surface_height(x as f32, z as f32, column);
+        let top = (height + self.overhang_amplitude).ceil() as i32;
+        let bottom = (height - self.overhang_amplitude).floor() as i32;
+        (bottom..=top)
+            .rev()
+            .find(|y| self.depth(Vec3::new(x as f32, *y as f32, z as f32), height) > 0.)
+    }
+
+    fn is_cave(&self, position: Vec3, depth: f32) -> bool {
+        // keep a crust over caves so they rarely break through the surface
+        if depth < 4. {
+            return false;
+        }
+        let point = position / self.cave_scale;
+        let sheet = |offset: f32| self.cave_noise.fbm3(point + offset, 2).abs();
+        sheet(0.) < self.cave_threshold && sheet(100.) < self.cave_threshold
+    }
+
+    fn column_voxel(&self, position: Vec3, column: &BiomeColumn, surface: f32) -> Voxel {
+        let depth = self.depth(position, surface);
+        if depth <= 0. || self.is_cave(position, depth) {
+            EMPTY
+        } else if depth < 1. {
+            Voxel::new(column.biome.surface)
+        } else if depth < 1. + column.biome.subsurface_depth {
+            Voxel::new(column.biome.subsurface)
+        } else {
+            Voxel::new(STONE)
+        }
+    }
+}
+
+impl TerrainGenerator for LayeredTerrain {
+    fn voxel(&self, position: IVec3) -> Voxel {
+        let position = position.as_vec3();
+        let column = self.biomes.column(position.x, position.z);
+        let surface = self.surface_height(position.x, position.z, &column);
+        self.column_voxel(position, &column, surface)
+    }
+
+    fn column(&self, x: i32, z: i32, bottom: i32, voxels: &mut [Voxel]) {
+        // the biome and surface height are shared by the whole column
+        let (x, z) = (x as f32, z as f32);
+        let column = self.biomes.column(x, z);
+        let surface = self.surface_height(x, z, &column);
+        for (y, voxel) in (bottom..).zip(voxels) {
+            *voxel = self.column_voxel(Vec3::new(x, y as f32, z), &column, surface);
+        }
+    }
+
+    fn structures(&self, origin_chunk: ChunkPosition) -> Vec<PlacedStructure> {
+        // sites are drawn for the whole column of chunks, and each chunk keeps
+        // the decorations that stand on a surface within it
+        let column_pos = ChunkPosition::new(origin_chunk.x, 0, origin_chunk.z);
+        let mut rng = chunk_rng(self.seed, column_pos);
+        let origin = origin_chunk.as_ivec3() * CHUNK_DIM as i32;
+        let cells = CHUNK_DIM as i32 / DECORATION_CELL;
+        let mut structures = Vec::new();
+        for cell_x in 0..cells {
+            for cell_z in 0..cells {
+                // every site draws the same numbers whether it is used or not,
+                // so that one site never shifts the rest
+                let jitter_x = rng.below(DECORATION_CELL as u32) as i32;
+                let jitter_z = rng.below(DECORATION_CELL as u32) as i32;
+                let roll = rng.unit();
+                let structure_seed = rng.next_u64();
+
+                let x = origin.x + cell_x * DECORATION_CELL + jitter_x;
+                let z = origin.z + cell_z * DECORATION_CELL + jitter_z;
+                let column = self.biomes.column(x as f32, z as f32);
+                let chance = column.decoration_density * (DECORATION_CELL * DECORATION_CELL) as f32;
+                if roll >= chance {
+                    continue;
+                }
+                let Some(top) = self.top_voxel(x, z, &column) else {
+                    continue;
+                };
+                let y = top + 1;
+                if y.div_euclid(CHUNK_DIM as i32) != origin_chunk.y {
+                    continue;
+                }
+                let mut structure_rng = SplitMix::new(structure_seed);
+                let Some(decoration) = structure_rng.choose(&column.biome.decorations) else {
+                    continue;
+                };
+                structures.push(PlacedStructure {
+                    origin: IVec3::new(x, y, z),
+                    structure: decoration.build(&mut structure_rng),
+                });
+            }
+        }
+        structures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::{Chunk, CHUNK_DIM};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::terrain::{LayeredTerrain, TerrainGenerator};
+    use crate::voxel::EMPTY;
+    use bevy::prelude::*;
+
+    #[test]
+    fn terrain_is_deterministic() {
+        let first = LayeredTerrain::new(1234);
+        let second = LayeredTerrain::new(1234);
+        for x in -40..40 {
+            for y in -20..60 {
+                let position = IVec3::new(x * 3, y, -x);
+                assert_eq!(first.voxel(position), second.voxel(position));
+            }
+        }
+    }
+
+    #[test]
+    fn columns_match_individual_voxels() {
+        let terrain = LayeredTerrain::new(5);
+        let mut column = [EMPTY; 64];
+        for (x, z) in [(0, 0), (-17, 230), (512, -9)] {
+            terrain.column(x, z, -32, &mut column);
+            for (y, voxel) in (-32..).zip(column) {
+                assert_eq!(voxel, terrain.voxel(IVec3::new(x, y, z)));
+            }
+        }
+    }
+
+    #[test]
+    fn terrain_has_ground_and_sky() {
+        let terrain = LayeredTerrain::new(99);
+        for x in -10..10 {
+            let high = IVec3::new(x * 50, 100, x * 20);
+            let low = IVec3::new(x * 50, -100, x * 20);
+            assert_eq!(terrain.voxel(high), EMPTY);
+            // caves may hollow out stretches of a deep column, but never the
+            // whole of it
+            let column_solid = (-120..-80)
+                .filter(|y| terrain.voxel(IVec3::new(low.x, *y, low.z)) != EMPTY)
+                .count();
+            assert!(column_solid > 0);
+        }
+    }
+
+    #[test]
+    fn decorations_are_pinned_for_a_seed() {
+        // saves only store how chunks differ from their regenerated terrain,
+        // so decorations must never move between builds
+        let terrain = LayeredTerrain::new(21);
+        let placed: Vec<(IVec3, usize)> = terrain
+            .structures(ChunkPosition::new(-1, 0, -1))
+            .iter()
+            .map(|placed| (placed.origin, placed.structure.voxels().len()))
+            .take(3)
+            .collect();
+        assert_eq!(
+            placed,
+            [
+                (IVec3::new(-32, 13, -3), 53),
+                (IVec3::new(-28, 14, -23), 33),
+                (IVec3::new(-27, 14, -9), 69),
+            ]
+        );
+    }
+
+    #[test]
+    fn chunks_are_generated_in_world_space() {
+        let terrain = LayeredTerrain::new(5);
+        let chunk_pos = ChunkPosition::new(-1, 0, 2);
+        let chunk: Chunk = Chunk::generate(chunk_pos, &terrain);
+        let origin = chunk_pos.as_ivec3() * CHUNK_DIM as i32;
+        for x in 0..CHUNK_DIM {
+            for y in 0..CHUNK_DIM {
+                let local = UVec3::new(x, y, (x + y) % CHUNK_DIM);
+                let world_position = origin + local.as_ivec3();
+                assert_eq!(chunk.get(local.to_array()), terrain.voxel(world_position));
+            }
+        }
+    }
+}
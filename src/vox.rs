@@ -0,0 +1,726 @@
+use crate::material::{VoxelMaterialRegistry, VOID};
+use crate::voxel::{Voxel, EMPTY};
+use crate::world::World;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::path::Path;
+use thiserror::Error;
+
+/// Models in .vox files are at most this many voxels across.
+const MAX_MODEL_DIM: u32 = 256;
+const VERSION: i32 = 150;
+/// Volumes hold at most this many voxels, so that a mistyped export box or a
+/// malformed .vox file can't use up all of the memory.
+pub const MAX_VOLUME_VOXELS: u64 = 1 << 24;
+/// .vox scene graphs may place models at most this far from the origin along
+/// each axis, which keeps every position well within range of an i32.
+const MAX_SCENE_REACH: i32 = 1 << 20;
+
+#[derive(Debug, Error)]
+pub enum VoxError {
+    #[error("could not access .vox file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("not a .vox file")]
+    NotVox,
+    #[error(".vox file ends in the middle of a chunk")]
+    Truncated,
+    #[error("a .vox palette holds at most 255 materials, but {0} are used")]
+    TooManyMaterials(usize),
+    #[error("a volume of {0:?} voxels is larger than volumes may be")]
+    TooLarge([i64; 3]),
+    #[error(".vox file places a model too far from the origin")]
+    TooFar,
+    #[error(".vox scene graph reaches node {0} more than once")]
+    RepeatedNode(i32),
+}
+
+/// A box of voxels, which isn't part of any world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelVolume {
+    size: UVec3,
+    voxels: Vec<Voxel>,
+}
+
+impl VoxelVolume {
+    /// An empty volume of the given size, which may hold at most
+    /// `MAX_VOLUME_VOXELS` voxels.
+    pub fn new(size: UVec3) -> Result<Self, VoxError> {
+        let size = checked_size(size.to_array().map(i64::from))?;
+        Ok(Self {
+            size,
+            voxels: vec![EMPTY; (size.x * size.y * size.z) as usize],
+        })
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn index(&self, position: UVec3) -> usize {
+        assert!(
+            position.cmplt(self.size).all(),
+            "{} is outside the volume",
+            position
+        );
+        (position.x + position.y * self.size.x + position.z * self.size.x * self.size.y) as usize
+    }
+
+    pub fn get(&self, position: UVec3) -> Voxel {
+        self.voxels[self.index(position)]
+    }
+
+    pub fn set(&mut self, position: UVec3, voxel: Voxel) {
+        let index = self.index(position);
+        self.voxels[index] = voxel;
+    }
+
+    /// Copies the voxels of the world between the two corners, inclusive.
+    /// Voxels in chunks that aren't loaded are copied as empty.
+    pub fn from_world(world: &World, min: IVec3, max: IVec3) -> Result<Self, VoxError> {
+        let mut volume = Self::new(box_size(min, max)?)?;
+        for position in box_positions(volume.size) {
+            let voxel = world.get_voxel(min + position.as_ivec3());
+            volume.set(position, voxel.unwrap_or(EMPTY));
+        }
+        Ok(volume)
+    }
+
+    /// Copies the volume's filled voxels into the world, with the volume's
+    /// first corner at `origin`. Empty voxels leave the world as it was.
+    /// Returns how many voxels were placed, which leaves out those in chunks
+    /// that aren't loaded.
+    pub fn place_in(&self, world: &mut World, origin: IVec3) -> usize {
+        let edits = box_positions(self.size)
+            .map(|position| (origin + position.as_ivec3(), self.get(position)))
+            .filter(|(_, voxel)| *voxel != EMPTY);
+        world.set_voxels(edits)
+    }
+}
+
+/// The size of the box between two corners, inclusive, if a volume may be
+/// that large.
+fn box_size(min: IVec3, max: IVec3) -> Result<UVec3, VoxError> {
+    let [min, max] = [min, max].map(|corner| corner.to_array().map(i64::from));
+    checked_size([0, 1, 2].map(|axis| (max[axis] - min[axis] + 1).max(0)))
+}
+
+/// The size as a `UVec3`, if a volume may be that large.
+fn checked_size(size: [i64; 3]) -> Result<UVec3, VoxError> {
+    let voxels = size.iter().try_fold(1_u64, |total, axis| {
+        total.checked_mul(u64::try_from(*axis).ok()?)
+    });
+    let fits = size.iter().all(|axis| u32::try_from(*axis).is_ok());
+    match voxels {
+        Some(voxels) if fits && voxels <= MAX_VOLUME_VOXELS => {
+            Ok(UVec3::from_array(size.map(|axis| axis as u32)))
+        }
+        _ => Err(VoxError::TooLarge(size)),
+    }
+}
+
+/// Every position in a box of the given size, starting from zero.
+fn box_positions(size: UVec3) -> impl Iterator<Item = UVec3> {
+    (0..size.z).flat_map(move |z| {
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| UVec3::new(x, y, z)))
+    })
+}
+
+/// .vox files are z up, where the world is y up.
+fn vox_to_world(position: IVec3) -> IVec3 {
+    IVec3::new(position.x, position.z, -position.y)
+}
+
+/// Loads every model of a .vox file into one volume, placed by the file's
+/// scene graph. See `read_vox`.
+pub fn load_vox(
+    path: impl AsRef<Path>,
+    registry: &VoxelMaterialRegistry,
+) -> Result<VoxelVolume, VoxError> {
+    read_vox(&std::fs::read(path)?, registry)
+}
+
+/// Saves a volume as a .vox file. See `write_vox`.
+pub fn save_vox(
+    path: impl AsRef<Path>,
+    volume: &VoxelVolume,
+    registry: &VoxelMaterialRegistry,
+) -> Result<(), VoxError> {
+    std::fs::write(path, write_vox(volume, registry)?)?;
+    Ok(())
+}
+
+/// Reads every model of a .vox file into one volume just large enough to
+/// hold them, each placed and rotated by the transforms of the file's scene
+/// graph. Each palette entry becomes the registered material whose color is
+/// nearest to it. Files without a palette are read as all white.
+pub fn read_vox(bytes: &[u8], registry: &VoxelMaterialRegistry) -> Result<VoxelVolume, VoxError> {
+    let mut reader = Reader { bytes };
+    if reader.take(4).ok() != Some(b"VOX ".as_slice()) {
+        return Err(VoxError::NotVox);
+    }
+    let _version = reader.i32()?;
+    let (id, _, children) = reader.chunk()?;
+    if id != *b"MAIN" {
+        return Err(VoxError::NotVox);
+    }
+
+    let mut models: Vec<Model> = Vec::new();
+    let mut size = None;
+    let mut palette = [[255; 4]; 256];
+    let mut nodes: HashMap<i32, Node> = HashMap::new();
+    let mut chunks = Reader { bytes: children };
+    while !chunks.bytes.is_empty() {
+        let (id, content, _) = chunks.chunk()?;
+        let mut content = Reader { bytes: content };
+        match &id {
+            b"SIZE" => {
+                // voxel positions are single bytes, so nothing lies further out
+                let read = IVec3::new(content.i32()?, content.i32()?, content.i32()?);
+                size = Some(read.min(IVec3::splat(MAX_MODEL_DIM as i32)));
+            }
+            b"XYZI" => {
+                let count = content.i32()?;
+                let mut voxels = Vec::new();
+                for _ in 0..count {
+                    let voxel = content.take(4)?;
+                    let position = IVec3::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32);
+                    voxels.push((position, voxel[3]));
+                }
+                // every model's size comes right before its voxels
+                let size = size.take().unwrap_or(IVec3::ZERO);
+                models.push(Model { size, voxels });
+            }
+            b"RGBA" => {
+                for color in &mut palette {
+                    color.copy_from_slice(content.take(4)?);
+                }
+            }
+            b"nTRN" => {
+                let node = content.i32()?;
+                let _attributes = content.dict()?;
+                let child = content.i32()?;
+                let _reserved = content.i32()?;
+                let _layer = content.i32()?;
+                let frames = content.i32()?;
+                // models aren't animated, so only the first frame is used
+                let frame = if frames > 0 {
+                    content.dict()?
+                } else {
+                    HashMap::new()
+                };
+                let transform = VoxTransform::from_frame(&frame)?;
+                nodes.insert(node, Node::Transform(child, transform));
+            }
+            b"nGRP" => {
+                let node = content.i32()?;
+                let _attributes = content.dict()?;
+                let count = content.i32()?;
+                let children = (0..count)
+                    .map(|_| content.i32())
+                    .collect::<Result<_, _>>()?;
+                nodes.insert(node, Node::Group(children));
+            }
+            b"nSHP" => {
+                let node = content.i32()?;
+                let _attributes = content.dict()?;
+                let count = content.i32()?;
+                let mut shapes = Vec::new();
+                for _ in 0..count {
+                    shapes.push(content.i32()?);
+                    let _attributes = content.dict()?;
+                }
+                nodes.insert(node, Node::Shape(shapes));
+            }
+            _ => {}
+        }
+    }
+
+    // the transform of each model, from the root of the scene graph
+    let mut placed: Vec<(usize, VoxTransform)> = Vec::new();
+    if nodes.contains_key(&0) {
+        let (root, mut visited) = (VoxTransform::IDENTITY, HashSet::new());
+        place_node(0, root, &nodes, &mut placed, &mut visited, 0)?;
+    } else {
+        // files without a scene graph keep every model at the origin
+        for (index, model) in models.iter().enumerate() {
+            let transform = VoxTransform {
+                translation: model.size / 2,
+                ..VoxTransform::IDENTITY
+            };
+            placed.push((index, transform));
+        }
+    }
+    placed.retain(|(index, _)| {
+        models
+            .get(*index)
+            .is_some_and(|model| model.size.cmpgt(IVec3::ZERO).all())
+    });
+
+    let mut min = IVec3::MAX;
+    let mut max = IVec3::MIN;
+    for (index, transform) in &placed {
+        let size = models[*index].size;
+        // rotations map opposite corners of a model to opposite corners
+        for corner in [IVec3::ZERO, size - IVec3::ONE] {
+            let position = vox_to_world(transform.place(corner, size));
+            min = min.min(position);
+            max = max.max(position);
+        }
+    }
+    if placed.is_empty() {
+        return VoxelVolume::new(UVec3::ZERO);
+    }
+
+    let mut volume = VoxelVolume::new(box_size(min, max)?)?;
+    let mut materials: [Option<u16>; 256] = [None; 256];
+    for (index, transform) in &placed {
+        let model = &models[*index];
+        for (position, color_index) in &model.voxels {
+            if *color_index == 0 || position.cmpge(model.size).any() {
+                continue;
+            }
+            let material = *materials[*color_index as usize].get_or_insert_with(|| {
+                // palette entries are numbered from 1
+                nearest_material(registry, palette[*color_index as usize - 1])
+            });
+            let position = vox_to_world(transform.place(*position, model.size)) - min;
+            volume.set(position.as_uvec3(), Voxel::new(material));
+        }
+    }
+    Ok(volume)
+}
+
+/// Adds every model below the scene graph node to `placed`, with the
+/// transform from the root to it. Scene graphs are trees, so graphs reaching
+/// a node twice are refused: reused nodes could otherwise place a number of
+/// models exponential in the size of the file.
+fn place_node(
+    node: i32,
+    transform: VoxTransform,
+    nodes: &HashMap<i32, Node>,
+    placed: &mut Vec<(usize, VoxTransform)>,
+    visited: &mut HashSet<i32>,
+    depth: usize,
+) -> Result<(), VoxError> {
+    if !visited.insert(node) {
+        return Err(VoxError::RepeatedNode(node));
+    }
+    // graphs deeper than any editor writes are cut off, rather than
+    // overflowing the stack
+    if depth > 64 {
+        return Ok(());
+    }
+    match nodes.get(&node) {
+        Some(Node::Transform(child, own)) => {
+            let transform = transform.then(own);
+            place_node(*child, transform, nodes, placed, visited, depth + 1)?;
+        }
+        Some(Node::Group(children)) => {
+            for child in children {
+                place_node(*child, transform, nodes, placed, visited, depth + 1)?;
+            }
+        }
+        Some(Node::Shape(models)) => {
+            for model in models {
+                if let Ok(model) = usize::try_from(*model) {
+                    placed.push((model, transform));
+                }
+            }
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// The registered material whose color is nearest to the palette color,
+/// leaving out the void material.
+fn nearest_material(registry: &VoxelMaterialRegistry, color: [u8; 4]) -> u16 {
+    let color = Vec4::from_array(color.map(|channel| channel as f32 / 255.));
+    registry
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| *id != VOID as usize)
+        .map(|(id, material)| {
+            let distance =
+                (Vec4::from_array(material.get_color().as_rgba_f32()) - color).length_squared();
+            (id as u16, distance)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(VOID, |(id, _)| id)
+}
+
+/// Writes a volume as a .vox file. Volumes larger than a .vox model are
+/// split into several models, placed by the scene graph. Each material in
+/// the volume gets its own palette entry, colored as the material is.
+pub fn write_vox(
+    volume: &VoxelVolume,
+    registry: &VoxelMaterialRegistry,
+) -> Result<Vec<u8>, VoxError> {
+    let size = volume.size();
+    // .vox files are z up, so the world's y and z axes swap
+    let vox_size = UVec3::new(size.x, size.z, size.y);
+    let world_position =
+        |position: UVec3| UVec3::new(position.x, position.z, size.z - 1 - position.y);
+
+    let mut colors: Vec<u16> = Vec::new();
+    let mut color_indices: HashMap<u16, u8> = HashMap::new();
+    let mut models = Vec::new();
+    let max_dim = UVec3::splat(MAX_MODEL_DIM);
+    let tiles = ((vox_size + max_dim - UVec3::ONE) / max_dim).max(UVec3::ONE);
+    for tile in box_positions(tiles) {
+        let origin = tile * max_dim;
+        // models can't be empty, so empty volumes are saved as one empty voxel
+        let tile_size = max_dim.min(vox_size.saturating_sub(origin)).max(UVec3::ONE);
+        let mut voxels = Vec::new();
+        for local in box_positions(tile_size) {
+            let position = origin + local;
+            if position.cmpge(vox_size).any() {
+                continue;
+            }
+            let material = volume.get(world_position(position)).material;
+            if material == VOID {
+                continue;
+            }
+            let color_index = match color_indices.get(&material) {
+                Some(color_index) => *color_index,
+                None => {
+                    colors.push(material);
+                    let color_index = colors.len().min(u8::MAX as usize) as u8;
+                    color_indices.insert(material, color_index);
+                    color_index
+                }
+            };
+            voxels.push([local.x as u8, local.y as u8, local.z as u8, color_index]);
+        }
+        models.push((origin, tile_size, voxels));
+    }
+    if colors.len() > u8::MAX as usize {
+        return Err(VoxError::TooManyMaterials(colors.len()));
+    }
+
+    let mut children = Vec::new();
+    for (_, tile_size, voxels) in &models {
+        let mut content = Vec::new();
+        for axis in tile_size.to_array() {
+            content.extend_from_slice(&(axis as i32).to_le_bytes());
+        }
+        write_chunk(&mut children, b"SIZE", &content, &[]);
+        let mut content = (voxels.len() as i32).to_le_bytes().to_vec();
+        content.extend(voxels.iter().flatten());
+        write_chunk(&mut children, b"XYZI", &content, &[]);
+    }
+    // a root transform holds a group, which holds a transform and shape for
+    // each model
+    let mut content = Vec::new();
+    write_transform(&mut content, 0, 1, -1, IVec3::ZERO);
+    write_chunk(&mut children, b"nTRN", &content, &[]);
+    let mut content = Vec::new();
+    for value in [1, 0, models.len() as i32] {
+        content.extend_from_slice(&value.to_le_bytes());
+    }
+    for index in 0..models.len() {
+        content.extend_from_slice(&(2 + 2 * index as i32).to_le_bytes());
+    }
+    write_chunk(&mut children, b"nGRP", &content, &[]);
+    for (index, (origin, tile_size, _)) in models.iter().enumerate() {
+        let node = 2 + 2 * index as i32;
+        // models are placed by their center
+        let translation = (*origin + *tile_size / 2).as_ivec3();
+        let mut content = Vec::new();
+        write_transform(&mut content, node, node + 1, 0, translation);
+        write_chunk(&mut children, b"nTRN", &content, &[]);
+        let mut content = Vec::new();
+        for value in [node + 1, 0, 1, index as i32, 0] {
+            content.extend_from_slice(&value.to_le_bytes());
+        }
+        write_chunk(&mut children, b"nSHP", &content, &[]);
+    }
+    let mut content = Vec::new();
+    for index in 0..256 {
+        let color = colors
+            .get(index)
+            .and_then(|material| registry.get(*material))
+            .map_or([255; 4], |material| material.get_color().as_rgba_u8());
+        content.extend_from_slice(&color);
+    }
+    write_chunk(&mut children, b"RGBA", &content, &[]);
+
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    write_chunk(&mut bytes, b"MAIN", &[], &children);
+    Ok(bytes)
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+/// Writes the content of a transform node with a single frame.
+fn write_transform(out: &mut Vec<u8>, node: i32, child: i32, layer: i32, translation: IVec3) {
+    for value in [node, 0, child, -1, layer, 1] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    let translation = format!("{} {} {}", translation.x, translation.y, translation.z);
+    out.extend_from_slice(&1_i32.to_le_bytes());
+    for string in ["_t", translation.as_str()] {
+        out.extend_from_slice(&(string.len() as i32).to_le_bytes());
+        out.extend_from_slice(string.as_bytes());
+    }
+}
+
+struct Model {
+    size: IVec3,
+    voxels: Vec<(IVec3, u8)>,
+}
+
+/// A node of a .vox file's scene graph.
+enum Node {
+    Transform(i32, VoxTransform),
+    Group(Vec<i32>),
+    Shape(Vec<i32>),
+}
+
+/// The rotation and translation of a .vox scene graph transform node.
+/// Rotations only ever swap and flip axes, so they are kept as whole numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct VoxTransform {
+    // the rows of the rotation matrix
+    rotation: [IVec3; 3],
+    translation: IVec3,
+}
+
+impl VoxTransform {
+    const IDENTITY: Self = Self {
+        rotation: [IVec3::X, IVec3::Y, IVec3::Z],
+        translation: IVec3::ZERO,
+    };
+
+    fn from_frame(frame: &HashMap<String, String>) -> Result<Self, VoxError> {
+        let rotation = frame
+            .get("_r")
+            .and_then(|rotation| rotation.trim().parse().ok())
+            .map_or(Self::IDENTITY.rotation, decode_rotation);
+        let translation: Vec<i32> = frame
+            .get("_t")
+            .map(|translation| {
+                translation
+                    .split_whitespace()
+                    .filter_map(|axis| axis.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let translation = match translation[..] {
+            [x, y, z] => IVec3::new(x, y, z),
+            _ => IVec3::ZERO,
+        };
+        if translation.abs().cmpgt(IVec3::splat(MAX_SCENE_REACH)).any() {
+            return Err(VoxError::TooFar);
+        }
+        Ok(Self {
+            rotation,
+            translation,
+        })
+    }
+
+    fn rotate(&self, vector: IVec3) -> IVec3 {
+        IVec3::from_array(self.rotation.map(|row| row.dot(vector)))
+    }
+
+    /// This transform applied after `child`.
+    fn then(&self, child: &VoxTransform) -> Self {
+        // each row of the product mixes the child's rows by this row
+        let rotation = self.rotation.map(|row| {
+            child.rotation[0] * row.x + child.rotation[1] * row.y + child.rotation[2] * row.z
+        });
+        Self {
+            rotation,
+            translation: self.rotate(child.translation) + self.translation,
+        }
+    }
+
+    /// Where a voxel of a model of the given size ends up, as models are
+    /// rotated about and placed by their center.
+    fn place(&self, position: IVec3, size: IVec3) -> IVec3 {
+        self.rotate(position - size / 2) + self.translation
+    }
+}
+
+/// Decodes the packed rotation of a transform node: the first two pairs of
+/// bits give the column of the one nonzero entry in the first two rows, and
+/// the next three bits whether each row's entry is negative.
+fn decode_rotation(packed: u8) -> [IVec3; 3] {
+    let first = (packed & 3) as usize;
+    let second = (packed >> 2 & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return VoxTransform::IDENTITY.rotation;
+    }
+    let third = 3 - first - second;
+    let mut rotation = [IVec3::ZERO; 3];
+    for (row, column) in [first, second, third].into_iter().enumerate() {
+        let negative = packed >> (4 + row) & 1 == 1;
+        rotation[row][column] = if negative { -1 } else { 1 };
+    }
+    rotation
+}
+
+/// A chunk's id, content, and children.
+type RawChunk<'a> = ([u8; 4], &'a [u8], &'a [u8]);
+
+/// Reads values from the front of a .vox file's bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VoxError> {
+        if len > self.bytes.len() {
+            return Err(VoxError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, VoxError> {
+        usize::try_from(self.i32()?).map_err(|_| VoxError::Truncated)
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let count = self.i32()?;
+        (0..count)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn chunk(&mut self) -> Result<RawChunk<'a>, VoxError> {
+        let id = self.take(4)?.try_into().unwrap();
+        let content_len = self.len()?;
+        let children_len = self.len()?;
+        Ok((id, self.take(content_len)?, self.take(children_len)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::{VoxelMaterialRegistry, GRASS, LEAVES, STONE};
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::vox::{
+        box_positions, decode_rotation, read_vox, write_chunk, write_vox, VoxError, VoxTransform,
+        VoxelVolume, VERSION,
+    };
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::World;
+    use bevy::prelude::*;
+    use bevy::utils::HashMap;
+
+    #[test]
+    fn volumes_survive_a_round_trip() {
+        let registry = VoxelMaterialRegistry::default();
+        // wider than a .vox model, so it is split in two
+        let mut volume = VoxelVolume::new(UVec3::new(300, 3, 2)).unwrap();
+        volume.set(UVec3::new(0, 0, 0), Voxel::new(STONE));
+        volume.set(UVec3::new(299, 2, 1), Voxel::new(GRASS));
+        volume.set(UVec3::new(255, 1, 0), Voxel::new(LEAVES));
+        volume.set(UVec3::new(256, 2, 0), Voxel::new(STONE));
+        let bytes = write_vox(&volume, &registry).unwrap();
+        assert_eq!(bytes.windows(4).filter(|id| *id == b"XYZI").count(), 2);
+        assert_eq!(read_vox(&bytes, &registry).unwrap(), volume);
+        assert!(read_vox(b"not a vox file", &registry).is_err());
+        assert!(read_vox(&bytes[..bytes.len() - 10], &registry).is_err());
+    }
+
+    #[test]
+    fn scene_graphs_reusing_nodes_are_refused() {
+        // a group holding itself twice, which would place 2^64 models if
+        // followed
+        let mut content = Vec::new();
+        for value in [0_i32, 0, 2, 0, 0] {
+            content.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut children = Vec::new();
+        write_chunk(&mut children, b"nGRP", &content, &[]);
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        write_chunk(&mut bytes, b"MAIN", &[], &children);
+        assert!(matches!(
+            read_vox(&bytes, &VoxelMaterialRegistry::default()),
+            Err(VoxError::RepeatedNode(0))
+        ));
+    }
+
+    #[test]
+    fn transforms_rotate_about_model_centers() {
+        assert_eq!(decode_rotation(4), VoxTransform::IDENTITY.rotation);
+        // a quarter turn about z, which takes x to y
+        let rotation = decode_rotation(0b0010001);
+        assert_eq!(rotation, [IVec3::new(0, -1, 0), IVec3::X, IVec3::Z]);
+        let transform = VoxTransform {
+            rotation,
+            translation: IVec3::new(10, 0, 0),
+        };
+        let size = IVec3::new(4, 2, 1);
+        assert_eq!(
+            transform.place(IVec3::new(3, 0, 0), size),
+            IVec3::new(11, 1, 0)
+        );
+        let twice = transform.then(&transform);
+        assert_eq!(twice.rotate(IVec3::X), -IVec3::X);
+        assert_eq!(twice.translation, IVec3::new(10, 10, 0));
+    }
+
+    #[test]
+    fn volumes_are_copied_to_and_from_worlds() {
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        world.load_chunk(ChunkPosition::new(0, 0, 0));
+        let mut volume = VoxelVolume::new(UVec3::new(2, 2, 2)).unwrap();
+        for position in box_positions(volume.size()) {
+            if position.y == 0 {
+                volume.set(position, Voxel::new(STONE));
+            }
+        }
+        world.set_voxel(IVec3::new(5, 6, 5), Voxel::new(GRASS));
+        // the volume's empty voxels leave the world alone, and voxels
+        // beyond the loaded chunk aren't placed
+        assert_eq!(volume.place_in(&mut world, IVec3::new(5, 5, 5)), 4);
+        assert_eq!(volume.place_in(&mut world, IVec3::new(31, 5, 5)), 2);
+        let copied = VoxelVolume::from_world(&world, IVec3::new(5, 5, 5), IVec3::new(6, 6, 6));
+        volume.set(UVec3::new(0, 1, 0), Voxel::new(GRASS));
+        assert_eq!(copied.unwrap(), volume);
+    }
+
+    #[test]
+    fn oversized_volumes_are_refused() {
+        let world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        let too_large =
+            |result: Result<VoxelVolume, VoxError>| matches!(result, Err(VoxError::TooLarge(_)));
+        let box_in_world = |max| VoxelVolume::from_world(&world, IVec3::ZERO, max);
+        assert!(too_large(box_in_world(IVec3::splat(100000))));
+        assert!(too_large(VoxelVolume::from_world(
+            &world,
+            IVec3::MIN,
+            IVec3::MAX
+        )));
+        assert!(too_large(VoxelVolume::new(UVec3::splat(u32::MAX))));
+        // boxes with no voxels are fine however wide they are
+        assert!(box_in_world(IVec3::new(i32::MAX, -1, 0)).is_ok());
+        let frame = HashMap::from([("_t".to_owned(), "2000000000 0 0".to_owned())]);
+        assert!(matches!(
+            VoxTransform::from_frame(&frame),
+            Err(VoxError::TooFar)
+        ));
+    }
+}
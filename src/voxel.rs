@@ -1,13 +1,22 @@
+use crate::material::VOID;
+use crate::storage::row::Voxel as StoredVoxel;
 use block_mesh::{MergeVoxel, Voxel as MeshableVoxel, VoxelVisibility};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Voxel {
-    material: bool,
+    pub material: u16,
 }
 
-pub const EMPTY: Voxel = Voxel { material: false };
-pub const FULL: Voxel = Voxel { material: true };
+pub const EMPTY: Voxel = Voxel::new(VOID);
 
+impl Voxel {
+    pub const fn new(material: u16) -> Self {
+        Self { material }
+    }
+}
+
+// voxels don't know their material's properties, so only tell empty from
+// opaque; `VoxelMaterialRegistry::get_visibility` also finds translucent ones
 impl MeshableVoxel for Voxel {
     fn get_visibility(&self) -> VoxelVisibility {
         if *self == EMPTY {
@@ -25,3 +34,15 @@ impl MergeVoxel for Voxel {
         *self
     }
 }
+
+impl From<Voxel> for StoredVoxel {
+    fn from(voxel: Voxel) -> Self {
+        StoredVoxel(voxel.material)
+    }
+}
+
+impl From<StoredVoxel> for Voxel {
+    fn from(voxel: StoredVoxel) -> Self {
+        Voxel::new(voxel.0)
+    }
+}
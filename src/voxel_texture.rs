@@ -0,0 +1,257 @@
+use crate::material::{MaterialsChanged, VoxelMaterialRegistry};
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat, TextureViewDescriptor,
+    TextureViewDimension,
+};
+use bevy::render::texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor};
+
+/// The width and height, in pixels, of every voxel texture.
+pub const VOXEL_TEXTURE_SIZE: u32 = 16;
+
+/// The material chunk meshes are drawn with.
+pub type ChunkMaterial = ExtendedMaterial<StandardMaterial, VoxelTextureExtension>;
+
+pub struct VoxelTexturePlugin;
+
+impl Plugin for VoxelTexturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<ChunkMaterial>::default())
+            .add_systems(Startup, setup_voxel_textures)
+            .add_systems(
+                Update,
+                (load_voxel_textures, build_voxel_texture_array).chain(),
+            );
+    }
+}
+
+/// Multiplies the vertex colors of chunk meshes by the texture array layer
+/// each quad was given by the mesher.
+#[derive(Asset, AsBindGroup, TypePath, Debug, Clone)]
+pub struct VoxelTextureExtension {
+    #[texture(100, dimension = "2d_array")]
+    #[sampler(101)]
+    pub textures: Handle<Image>,
+}
+
+impl MaterialExtension for VoxelTextureExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/voxel.wgsl".into()
+    }
+}
+
+/// The materials shared by every chunk mesh, and the textures packed into
+/// their texture array.
+#[derive(Resource)]
+pub struct VoxelTextures {
+    pub material: Handle<ChunkMaterial>,
+    /// draws the meshes of translucent voxels with alpha blending
+    pub translucent_material: Handle<ChunkMaterial>,
+    array: Handle<Image>,
+    // the registry's texture paths, and the image loaded for each of them
+    paths: Vec<String>,
+    images: Vec<Handle<Image>>,
+    // the layers each material's faces were last meshed with
+    layers: Vec<[u32; 3]>,
+    // whether the texture array needs rebuilding
+    dirty: bool,
+}
+
+fn setup_voxel_textures(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+) {
+    let array = images.add(build_texture_array(&[]));
+    // chunk meshes are colored per vertex by voxel material
+    let mut base = StandardMaterial::from(Color::WHITE);
+    base.perceptual_roughness = 0.9;
+    let material = materials.add(ChunkMaterial {
+        base: base.clone(),
+        extension: VoxelTextureExtension {
+            textures: array.clone(),
+        },
+    });
+    base.alpha_mode = AlphaMode::Blend;
+    let translucent_material = materials.add(ChunkMaterial {
+        base,
+        extension: VoxelTextureExtension {
+            textures: array.clone(),
+        },
+    });
+    commands.insert_resource(VoxelTextures {
+        material,
+        translucent_material,
+        array,
+        paths: Vec::new(),
+        images: Vec::new(),
+        layers: Vec::new(),
+        dirty: false,
+    });
+}
+
+/// Loads the textures of newly registered materials. Materials whose faces
+/// moved to other layers are announced as changed, so their chunks are
+/// re-meshed.
+fn load_voxel_textures(
+    registry: Res<VoxelMaterialRegistry>,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<VoxelTextures>,
+    mut changed: EventWriter<MaterialsChanged>,
+) {
+    if !registry.is_changed() {
+        return;
+    }
+    let paths = registry.texture_paths();
+    if textures.paths != paths {
+        textures.images = paths
+            .iter()
+            .map(|path| asset_server.load(path.to_string()))
+            .collect();
+        textures.paths = paths.iter().map(|path| path.to_string()).collect();
+        textures.dirty = true;
+    }
+    let layers = registry.texture_layers();
+    let ids: Vec<u16> = (0..layers.len())
+        .filter(|id| {
+            textures
+                .layers
+                .get(*id)
+                .is_some_and(|old| *old != layers[*id])
+        })
+        .map(|id| id as u16)
+        .collect();
+    if !ids.is_empty() {
+        changed.send(MaterialsChanged { ids });
+    }
+    textures.layers = layers.to_vec();
+}
+
+/// Rebuilds the texture array whenever one of its textures loads or
+/// changes.
+fn build_voxel_texture_array(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut textures: ResMut<VoxelTextures>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+) {
+    for event in events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } = event {
+            if textures.images.iter().any(|image| image.id() == *id) {
+                textures.dirty = true;
+            }
+        }
+    }
+    if !textures.dirty {
+        return;
+    }
+    let layers: Vec<(&str, Option<&Image>)> = textures
+        .paths
+        .iter()
+        .zip(&textures.images)
+        .map(|(path, image)| (path.as_str(), images.get(image)))
+        .collect();
+    let array = build_texture_array(&layers);
+    images.insert(textures.array.clone(), array);
+    // touching the materials rebuilds their bind groups with the new array
+    materials.get_mut(&textures.material);
+    materials.get_mut(&textures.translucent_material);
+    textures.dirty = false;
+}
+
+/// Packs voxel textures into the layers of an array texture, after a first
+/// layer of plain white for untextured faces. Textures that haven't loaded
+/// yet, or that aren't `VOXEL_TEXTURE_SIZE` pixels square, are left white.
+pub fn build_texture_array(textures: &[(&str, Option<&Image>)]) -> Image {
+    let layer_size = (VOXEL_TEXTURE_SIZE * VOXEL_TEXTURE_SIZE * 4) as usize;
+    let mut data = vec![255; layer_size * (textures.len() + 1)];
+    for (layer, (path, image)) in textures.iter().enumerate() {
+        let Some(image) = image else {
+            continue;
+        };
+        let converted = image
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .filter(|converted| converted.data.len() == layer_size);
+        let fits = image.size() == UVec2::splat(VOXEL_TEXTURE_SIZE);
+        match converted {
+            Some(converted) if fits => {
+                let start = layer_size * (layer + 1);
+                data[start..start + layer_size].copy_from_slice(&converted.data);
+            }
+            _ => warn!(
+                "Voxel texture {} must be a {}x{} pixel color image",
+                path, VOXEL_TEXTURE_SIZE, VOXEL_TEXTURE_SIZE
+            ),
+        }
+    }
+
+    let mut array = Image::new(
+        Extent3d {
+            width: VOXEL_TEXTURE_SIZE,
+            height: VOXEL_TEXTURE_SIZE,
+            depth_or_array_layers: textures.len() as u32 + 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // a single layer would otherwise be viewed as a plain 2d texture
+    array.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    // texture coordinates count voxels across each quad, so the sampler
+    // repeats the texture once per voxel
+    array.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::nearest()
+    });
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel_texture::{build_texture_array, VOXEL_TEXTURE_SIZE};
+    use bevy::prelude::*;
+    use bevy::render::render_resource::{
+        Extent3d, TextureDimension, TextureFormat, TextureViewDimension,
+    };
+
+    fn filled(size: u32, pixel: [u8; 4]) -> Image {
+        Image::new_fill(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &pixel,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    #[test]
+    fn texture_arrays_have_a_layer_per_texture() {
+        let red = filled(VOXEL_TEXTURE_SIZE, [255, 0, 0, 255]);
+        let too_large = filled(VOXEL_TEXTURE_SIZE * 2, [0, 0, 255, 255]);
+        let array = build_texture_array(&[
+            ("red.png", Some(&red)),
+            ("loading.png", None),
+            ("too_large.png", Some(&too_large)),
+        ]);
+        assert_eq!(array.texture_descriptor.size.depth_or_array_layers, 4);
+        assert_eq!(
+            array.texture_view_descriptor.unwrap().dimension,
+            Some(TextureViewDimension::D2Array)
+        );
+        let layer_size = (VOXEL_TEXTURE_SIZE * VOXEL_TEXTURE_SIZE * 4) as usize;
+        let layers: Vec<&[u8]> = array.data.chunks(layer_size).collect();
+        assert_eq!(layers[1], red.data.as_slice());
+        // every other layer is white
+        for layer in [0, 2, 3] {
+            assert!(layers[layer].iter().all(|byte| *byte == 255));
+        }
+    }
+}
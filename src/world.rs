@@ -1,153 +1,1195 @@
-use crate::chunk::{Chunk, CHUNK_DIM};
+use crate::chunk::{voxel_chunk, Chunk, CHUNK_DIM};
 use crate::directions::Directions;
-use crate::meshing_chunk::MeshingChunk;
+use crate::light::{Light, LightMap};
+use crate::material::{MaterialsChanged, VoxelMaterialRegistry};
+use crate::meshing_chunk::{ChunkMeshes, MeshingChunk, MAX_LOD_SCALE};
 use crate::player_controller::{PlayerController, PlayerSettings};
-use crate::voxel::EMPTY;
+use crate::save::{SaveThread, WorldSave};
+use crate::storage::chunk::{Chunk as RowStorage, ChunkPosition};
+use crate::storage::map::{VoxelMap, VoxelMapSettings};
+use crate::storage::ChunkStorage;
+use crate::terrain::{LayeredTerrain, TerrainGenerator};
+use crate::voxel::Voxel;
+use crate::voxel_texture::VoxelTextures;
+use bevy::app::AppExit;
 use bevy::pbr::wireframe::Wireframe;
 use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
 use bevy::tasks::{AsyncComputeTaskPool, Task};
-use bevy::utils::HashSet;
+use bevy::utils::{HashMap, HashSet};
 use futures_lite::future::{block_on, poll_once};
-use ndshape::{RuntimeShape, Shape};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-pub struct WorldPlugin;
+pub struct WorldPlugin {
+    /// seeds the world's terrain generator; the same seed always generates
+    /// the same world
+    pub seed: u64,
+    /// at most this many chunks are generated at once, so that generation
+    /// leaves threads free for meshing
+    pub max_generation_tasks: usize,
+    /// at most this many newly loaded chunks are lit each frame, since
+    /// lighting a chunk floods light through all of its voxels
+    pub max_lit_chunks: usize,
+    /// the directory the world is saved in, or None to not save it. A world
+    /// saved there before is loaded, along with its seed.
+    pub save_dir: Option<PathBuf>,
+    /// how many seconds pass between saves of every edited chunk
+    pub autosave_interval: f32,
+    /// chunks with at most this many voxels edited save only those voxels,
+    /// applying them to the regenerated chunk when loaded. See
+    /// `WorldSave::with_max_edited_voxels`.
+    pub max_edited_voxels: usize,
+    /// whether chunks near players are meshed and drawn. Servers without a
+    /// renderer turn this off, and only generate, light and save chunks.
+    pub meshing: bool,
+}
+
+impl Default for WorldPlugin {
+    fn default() -> Self {
+        Self {
+            seed: 0x5eed,
+            max_generation_tasks: 8,
+            max_lit_chunks: 4,
+            save_dir: None,
+            autosave_interval: 60.,
+            max_edited_voxels: 0,
+            meshing: true,
+        }
+    }
+}
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource::<World>(World::generate([10, 4, 10]))
-            .add_systems(Update, spawn_mesh_tasks)
-            .add_systems(Update, handle_mesh_tasks);
+        let save = self.save_dir.as_ref().and_then(|dir| {
+            WorldSave::open(dir, self.seed)
+                .map(|save| save.with_max_edited_voxels(self.max_edited_voxels))
+                .map_err(|error| error!("Can't open world save in {:?}: {}", dir, error))
+                .ok()
+        });
+        let seed = save.as_ref().map_or(self.seed, |save| save.seed());
+        let generator = LayeredTerrain::new(seed);
+        let mut world = World::new(VoxelMapSettings::default(), generator);
+        world.max_generation_tasks = self.max_generation_tasks;
+        world.max_lit_chunks = self.max_lit_chunks;
+        if let Some(save) = save {
+            world = world.with_save(save);
+        }
+        // the view distances that chunks are loaded within are player settings
+        app.init_resource::<PlayerSettings>()
+            .insert_resource::<World>(world)
+            .insert_resource(Autosave(Timer::from_seconds(
+                self.autosave_interval,
+                TimerMode::Repeating,
+            )))
+            .add_systems(Startup, reserve_saved_materials)
+            .add_systems(PostStartup, restore_player_position)
+            .add_systems(
+                Update,
+                (
+                    evict_chunks,
+                    handle_generation_tasks,
+                    spawn_generation_tasks,
+                    update_light,
+                    autosave,
+                )
+                    .chain(),
+            )
+            .add_systems(Last, save_on_exit);
+        if self.meshing {
+            app.add_systems(
+                Update,
+                (
+                    unload_chunk_meshes.before(evict_chunks),
+                    (
+                        mark_changed_materials_dirty,
+                        spawn_mesh_tasks,
+                        remesh_dirty_chunks,
+                        handle_mesh_tasks,
+                    )
+                        .chain()
+                        .after(update_light)
+                        .before(autosave),
+                ),
+            );
+        }
     }
 }
 
+/// Keeps the chunks within the view distance of its entity generated and
+/// loaded. Players carry one, and a server keeps one at the spawn point.
+#[derive(Component, Default)]
+pub struct ChunkLoader;
+
+/// The loaded chunks of a world, kept in row storage unless another
+/// `storage::ChunkStorage` backend is chosen, with everything needed to
+/// generate, light, mesh and save them.
 #[derive(Resource)]
-pub struct World {
-    shape: RuntimeShape<u32, 3>,
-    chunks: Vec<Chunk>,
-    visible: HashSet<UVec3>,
+pub struct World<S: ChunkStorage = RowStorage> {
+    chunks: VoxelMap<Chunk<S>>,
+    generator: Arc<dyn TerrainGenerator>,
+    // maps each meshed chunk to the entity holding its mesh (or its pending
+    // meshing task)
+    visible: HashMap<ChunkPosition, Entity>,
+    // how many voxels across each cell of a visible chunk's mesh is
+    lods: HashMap<ChunkPosition, u32>,
+    // meshed chunks whose meshes are out of date
+    dirty: HashSet<ChunkPosition>,
+    // chunks being generated in the background
+    generating: HashMap<ChunkPosition, Task<Chunk<S>>>,
+    max_generation_tasks: usize,
+    max_lit_chunks: usize,
+    light: LightMap,
+    // chunks loaded since light was last updated
+    unlit: Vec<ChunkPosition>,
+    // voxels edited since light was last updated
+    light_edits: Vec<IVec3>,
+    save: Option<Arc<WorldSave<S>>>,
+    saver: Option<SaveThread<S>>,
+    // loaded chunks that have been edited since they were last saved
+    unsaved: HashSet<ChunkPosition>,
 }
 
 impl World {
-    fn chunk_pos(world_position: Vec3) -> UVec3 {
-        (world_position / CHUNK_DIM as f32).as_uvec3()
+    pub fn new(settings: VoxelMapSettings, generator: impl TerrainGenerator) -> Self {
+        World::with_storage(settings, generator)
+    }
+
+    fn chunk_pos(world_position: Vec3) -> ChunkPosition {
+        (world_position / CHUNK_DIM as f32)
+            .floor()
+            .as_ivec3()
+            .into()
+    }
+
+    fn world_position(chunk_pos: ChunkPosition) -> Vec3 {
+        chunk_pos.as_ivec3().as_vec3() * CHUNK_DIM as f32
+    }
+}
+
+impl<S: ChunkStorage> World<S> {
+    /// Like `new`, but keeps chunks in the storage backend `S` rather than
+    /// row storage.
+    pub fn with_storage(settings: VoxelMapSettings, generator: impl TerrainGenerator) -> Self {
+        World {
+            chunks: VoxelMap::new(settings),
+            generator: Arc::new(generator),
+            visible: HashMap::new(),
+            lods: HashMap::new(),
+            dirty: HashSet::new(),
+            generating: HashMap::new(),
+            max_generation_tasks: 8,
+            max_lit_chunks: 4,
+            light: LightMap::default(),
+            unlit: Vec::new(),
+            light_edits: Vec::new(),
+            save: None,
+            saver: None,
+            unsaved: HashSet::new(),
+        }
+    }
+
+    /// Saves the world as its chunks are unloaded, and loads saved chunks
+    /// rather than generating them again. Chunks are saved in the background.
+    pub fn with_save(mut self, save: WorldSave<S>) -> Self {
+        let save = Arc::new(save);
+        let generator = self.generator.clone();
+        self.saver = Some(SaveThread::spawn(save.clone(), move |pos| {
+            generate_chunk(pos, &*generator)
+        }));
+        self.save = Some(save);
+        self
+    }
+
+    pub fn save(&self) -> Option<&WorldSave<S>> {
+        self.save.as_deref()
     }
 
-    fn world_position(chunk_pos: UVec3) -> Vec3 {
-        chunk_pos.as_vec3() * CHUNK_DIM as f32
+    /// The voxel at the given world coordinates, or None if its chunk isn't
+    /// loaded.
+    pub fn get_voxel(&self, position: IVec3) -> Option<Voxel> {
+        Self::voxel_in(&self.chunks, position)
     }
 
-    fn bounded_add(&self, pos: UVec3, add: IVec3) -> Option<UVec3> {
-        let unbounded_pos = pos.as_ivec3() + add;
-        let bounds = self.shape.as_array();
-        let in_bounds =
-            |coord: i32, bound_index: usize| coord >= 0 && coord < bounds[bound_index] as i32;
-        if in_bounds(unbounded_pos.x, 0)
-            && in_bounds(unbounded_pos.y, 1)
-            && in_bounds(unbounded_pos.z, 2)
-        {
-            Some(unbounded_pos.as_uvec3())
-        } else {
-            None
+    fn voxel_in(chunks: &VoxelMap<Chunk<S>>, position: IVec3) -> Option<Voxel> {
+        let (chunk_pos, local) = voxel_chunk(position);
+        Some(chunks.get_chunk(chunk_pos)?.get(local))
+    }
+
+    /// The light at the given world coordinates, or None if its chunk isn't
+    /// loaded or hasn't been lit yet.
+    pub fn get_light(&self, position: IVec3) -> Option<Light> {
+        self.light.get(position)
+    }
+
+    /// Changes the voxel at the given world coordinates, re-meshing every
+    /// chunk whose mesh it appears in. Returns false, changing nothing, if
+    /// its chunk isn't loaded.
+    pub fn set_voxel(&mut self, position: IVec3, voxel: Voxel) -> bool {
+        let (chunk_pos, local) = voxel_chunk(position);
+        let Some(chunk) = self.chunks.get_chunk_mut(chunk_pos) else {
+            return false;
+        };
+        if chunk.get(local) == voxel {
+            return true;
         }
+        chunk.set(local, voxel);
+        self.unsaved.insert(chunk_pos);
+        self.light_edits.push(position);
+        self.mark_meshes_dirty(position);
+        true
     }
 
-    fn generate(dimensions: [u32; 3]) -> Self {
-        let shape = RuntimeShape::<u32, 3>::new(dimensions);
-        let mut chunks = Vec::<Chunk>::new();
-        for index in 0..shape.size() {
-            info!("generated {} of {} chunks", index, shape.size());
-            chunks.push(Chunk::generate(
-                UVec3::from_array(shape.delinearize(index)),
-                |_| EMPTY,
-            ));
+    /// Marks every visible chunk whose mesh shows the voxel at the given world
+    /// coordinates for re-meshing.
+    fn mark_meshes_dirty(&mut self, position: IVec3) {
+        let (chunk_pos, local) = voxel_chunk(position);
+        // the voxel's own chunk is the first of these, and neighbours follow
+        // when the voxel lies in the region copied into their padding
+        let copy_shapes = MeshingChunk::COPY_SHAPES;
+        for (direction, (shape, offset, _)) in Directions::all().iter().zip(copy_shapes) {
+            let in_padding = (0..3)
+                .all(|axis| (offset[axis]..offset[axis] + shape[axis]).contains(&local[axis]));
+            // the region is copied by the chunk which has this one as its
+            // neighbour in the direction
+            let meshed_pos = chunk_pos + -direction.to_vector();
+            if in_padding && self.visible.contains_key(&meshed_pos) {
+                self.dirty.insert(meshed_pos);
+            }
         }
-        World {
-            shape,
-            chunks,
-            visible: HashSet::new(),
+    }
+
+    /// Sets the level of detail a chunk is meshed at, re-meshing it if it
+    /// changed. Full resolution neighbours are re-meshed too, as they only
+    /// skirt their sides facing chunks meshed at another level of detail.
+    fn set_lod(&mut self, chunk_pos: ChunkPosition, scale: u32) {
+        if self.lods.insert(chunk_pos, scale) == Some(scale) {
+            return;
+        }
+        if self.visible.contains_key(&chunk_pos) {
+            self.dirty.insert(chunk_pos);
+        }
+        for direction in &Directions::all()[1..] {
+            let neighbour = chunk_pos + direction.to_vector();
+            if self.lods.get(&neighbour) == Some(&1) && self.visible.contains_key(&neighbour) {
+                self.dirty.insert(neighbour);
+            }
+        }
+    }
+
+    /// Moves every visible chunk to the level of detail for its distance
+    /// from the nearest player.
+    pub fn update_lods(&mut self, player_chunks: &[ChunkPosition], lod_distance: i32) {
+        let positions: Vec<ChunkPosition> = self.visible.keys().copied().collect();
+        for pos in positions {
+            let scale = lod_scale(nearest_distance(pos, player_chunks), lod_distance);
+            self.set_lod(pos, scale);
+        }
+    }
+
+    /// The sides of a chunk, in the order of `Directions::all` after the
+    /// chunk itself, whose faces are meshed even where the neighbouring
+    /// chunk hides them. Downsampled chunks don't match their neighbours
+    /// exactly, so they skirt every side, and full resolution chunks skirt
+    /// the sides facing them.
+    fn skirts(&self, chunk_pos: ChunkPosition) -> [bool; 6] {
+        let scale = self.lods.get(&chunk_pos).copied().unwrap_or(1);
+        let mut skirts = [false; 6];
+        for (skirt, direction) in skirts.iter_mut().zip(&Directions::all()[1..]) {
+            let neighbour = self.lods.get(&(chunk_pos + direction.to_vector()));
+            *skirt = scale > 1 || neighbour.is_some_and(|other| *other != scale);
+        }
+        skirts
+    }
+
+    /// Applies a batch of edits, as `set_voxel` does. Every affected chunk is
+    /// re-meshed once, however many of the edits touch it. Returns how many
+    /// of the edits were applied.
+    pub fn set_voxels(&mut self, edits: impl IntoIterator<Item = (IVec3, Voxel)>) -> usize {
+        edits
+            .into_iter()
+            .filter(|(position, voxel)| self.set_voxel(*position, *voxel))
+            .count()
+    }
+
+    /// Loads the chunk at the given position on the calling thread, if it
+    /// has not been loaded yet, generating it if it hasn't been saved.
+    /// Returns false if the position lies outside the world's bounds.
+    pub fn load_chunk(&mut self, chunk_pos: ChunkPosition) -> bool {
+        let generator = &*self.generator;
+        let save = self.save.as_deref();
+        let unlit = &mut self.unlit;
+        self.chunks
+            .get_or_create_chunk(chunk_pos, |pos| {
+                unlit.push(pos);
+                load_or_generate_chunk(pos, generator, save)
+            })
+            .is_some()
+    }
+
+    /// Saves every chunk edited since it was last saved, and every chunk that
+    /// failed to save before, along with the world's metadata.
+    pub fn save_all(&mut self, registry: &VoxelMaterialRegistry, player_position: Option<Vec3>) {
+        if let Some(saver) = &self.saver {
+            saver.retry_failed();
+        }
+        let positions: Vec<ChunkPosition> = self.unsaved.iter().copied().collect();
+        self.save_chunks(&positions, registry);
+        if let Some(save) = &self.save {
+            if let Err(error) = save.save_metadata(player_position) {
+                error!("Can't save world metadata: {}", error);
+            }
+        }
+    }
+
+    /// Waits until every chunk handed over to be saved has been written.
+    pub fn flush_saves(&self) {
+        if let Some(saver) = &self.saver {
+            saver.flush();
+        }
+    }
+
+    /// Hands copies of the given chunks over to be saved in the background,
+    /// if they are loaded and have been edited since they were last saved.
+    fn save_chunks(&mut self, positions: &[ChunkPosition], registry: &VoxelMaterialRegistry) {
+        let Some(saver) = &self.saver else {
+            return;
+        };
+        let chunks: Vec<(ChunkPosition, Chunk<S>)> = positions
+            .iter()
+            .filter(|pos| self.unsaved.remove(*pos))
+            .filter_map(|pos| Some((*pos, self.chunks.get_chunk(*pos)?.clone())))
+            .collect();
+        if !chunks.is_empty() {
+            saver.save_chunks(chunks, registry);
+        }
+    }
+
+    /// Lights every chunk loaded since this was last called, then relights
+    /// the world around every voxel edited since, re-meshing the chunks whose
+    /// light changed.
+    pub fn update_light(&mut self, registry: &VoxelMaterialRegistry) {
+        self.update_light_limited(registry, usize::MAX);
+    }
+
+    /// Like `update_light`, but only lights up to `max_chunks` of the loaded
+    /// chunks, in the order they were loaded, leaving the rest for later.
+    fn update_light_limited(&mut self, registry: &VoxelMaterialRegistry, max_chunks: usize) {
+        let chunks = &self.chunks;
+        let light = &mut self.light;
+        let voxels = |position| Self::voxel_in(chunks, position);
+        let mut lit = 0;
+        self.unlit.retain(|chunk_pos| {
+            if !chunks.contains_chunk(*chunk_pos) {
+                return false;
+            }
+            if lit >= max_chunks {
+                return true;
+            }
+            light.light_chunk(*chunk_pos, &voxels, registry);
+            lit += 1;
+            false
+        });
+        for position in self.light_edits.drain(..) {
+            self.light.update_voxel(position, &voxels, registry);
+        }
+        for chunk_pos in self.light.take_changed() {
+            if self.visible.contains_key(&chunk_pos) {
+                self.dirty.insert(chunk_pos);
+            }
+        }
+    }
+
+    /// Whether the chunk has been generated, or never will be because it lies
+    /// outside the world's bounds.
+    fn is_ready(&self, chunk_pos: ChunkPosition) -> bool {
+        self.chunks.contains_chunk(chunk_pos) || !self.chunks.in_bounds(chunk_pos)
+    }
+
+    /// Every chunk within `distance` of a player that has been neither
+    /// generated nor started, nearest to a player first.
+    fn chunks_to_generate(
+        &self,
+        player_chunks: &[ChunkPosition],
+        distance: i32,
+    ) -> Vec<ChunkPosition> {
+        let mut positions = HashSet::new();
+        for player_chunk in player_chunks {
+            for x in -distance..=distance {
+                for y in -distance..=distance {
+                    for z in -distance..=distance {
+                        let pos = *player_chunk + IVec3::new(x, y, z);
+                        if !self.is_ready(pos) && !self.generating.contains_key(&pos) {
+                            positions.insert(pos);
+                        }
+                    }
+                }
+            }
         }
+        let mut positions: Vec<ChunkPosition> = positions.into_iter().collect();
+        positions.sort_by_key(|pos| {
+            player_chunks
+                .iter()
+                .map(|player_chunk| (pos.as_ivec3() - player_chunk.as_ivec3()).length_squared())
+                .min()
+        });
+        positions
+    }
+
+    /// Starts generating the given chunks in the background, in order, until
+    /// `max_generation_tasks` chunks are being generated.
+    fn spawn_generation_tasks(&mut self, positions: impl IntoIterator<Item = ChunkPosition>) {
+        let pool = AsyncComputeTaskPool::get();
+        for pos in positions {
+            if self.generating.len() >= self.max_generation_tasks {
+                break;
+            }
+            let generator = self.generator.clone();
+            let save = self.save.clone();
+            let task = pool
+                .spawn(async move { load_or_generate_chunk(pos, &*generator, save.as_deref()) });
+            self.generating.insert(pos, task);
+        }
+    }
+
+    /// Moves every chunk that has finished generating into the map.
+    fn finish_generation_tasks(&mut self) {
+        let chunks = &mut self.chunks;
+        let unlit = &mut self.unlit;
+        self.generating.retain(|pos, task| {
+            let Some(chunk) = block_on(poll_once(task)) else {
+                return true;
+            };
+            chunks.get_or_create_chunk(*pos, |_| chunk);
+            unlit.push(*pos);
+            false
+        });
     }
 
-    fn get_meshing_chunk(&self, chunk_pos: UVec3) -> MeshingChunk {
-        let mut chunks: [Option<&Chunk>; 7] = [None; 7];
+    /// Drops the voxel data of every chunk further than `evict_distance` from
+    /// all of the given player chunks, saving them first, and cancels any
+    /// that are still being generated.
+    fn evict_chunks(
+        &mut self,
+        player_chunks: &[ChunkPosition],
+        evict_distance: i32,
+        registry: &VoxelMaterialRegistry,
+    ) {
+        let keep = |pos: ChunkPosition| nearest_distance(pos, player_chunks) <= evict_distance;
+        let evicted: Vec<ChunkPosition> = self
+            .unsaved
+            .iter()
+            .copied()
+            .filter(|pos| !keep(*pos))
+            .collect();
+        self.save_chunks(&evicted, registry);
+        self.chunks.retain(keep);
+        self.light.retain(keep);
+        self.unlit.retain(|pos| keep(*pos));
+        // dropping a task cancels it
+        self.generating.retain(|pos, _| keep(*pos));
+    }
+
+    fn get_meshing_chunk(&self, chunk_pos: ChunkPosition) -> MeshingChunk {
+        let mut chunks: [Option<&Chunk<S>>; 7] = [None; 7];
+        let mut lights = [None; 7];
         let directions = Directions::all();
         for index in 0..chunks.len() {
-            if let Some(pos) = self.bounded_add(chunk_pos, directions[index].to_vector()) {
-                let world_index = self.shape.linearize(pos.to_array()) as usize;
-                chunks[index] = Some(&self.chunks[world_index]);
-            }
+            let pos = chunk_pos + directions[index].to_vector();
+            chunks[index] = self.chunks.get_chunk(pos);
+            lights[index] = self.light.get_chunk(pos);
+        }
+        MeshingChunk::new(chunks).with_light(lights)
+    }
+
+    /// Meshes a loaded chunk at full resolution on the calling thread, with
+    /// its vertices in world coordinates. Neighbouring chunks that aren't
+    /// loaded are meshed as empty, and chunks that haven't been lit as dark.
+    pub fn mesh_chunk(
+        &self,
+        chunk_pos: ChunkPosition,
+        registry: &VoxelMaterialRegistry,
+    ) -> Option<ChunkMeshes> {
+        if !self.chunks.contains_chunk(chunk_pos) {
+            return None;
+        }
+        let mut meshes = self.get_meshing_chunk(chunk_pos).mesh(registry);
+        // mesh positions count from the padding around the chunk
+        let offset = World::world_position(chunk_pos) - Vec3::ONE;
+        offset_positions(&mut meshes.opaque, offset);
+        offset_positions(&mut meshes.translucent, offset);
+        Some(meshes)
+    }
+
+    fn spawn_mesh_task(
+        &self,
+        chunk_pos: ChunkPosition,
+        registry: &VoxelMaterialRegistry,
+    ) -> MeshResultTask {
+        let meshing_chunk = self
+            .get_meshing_chunk(chunk_pos)
+            .with_skirts(self.skirts(chunk_pos));
+        let scale = self.lods.get(&chunk_pos).copied().unwrap_or(1);
+        let registry = registry.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let meshes = meshing_chunk.downsample(scale).mesh(&registry);
+            MeshResult(chunk_pos, meshes)
+        });
+        MeshResultTask(task)
+    }
+}
+
+/// Runs both stages of generation for one chunk.
+fn generate_chunk<S: ChunkStorage>(
+    chunk_pos: ChunkPosition,
+    generator: &dyn TerrainGenerator,
+) -> Chunk<S> {
+    let mut chunk = Chunk::generate(chunk_pos, generator);
+    chunk.decorate(chunk_pos, generator);
+    chunk
+}
+
+/// Loads the chunk from the save if it was saved there, and generates it
+/// otherwise.
+fn load_or_generate_chunk<S: ChunkStorage>(
+    chunk_pos: ChunkPosition,
+    generator: &dyn TerrainGenerator,
+    save: Option<&WorldSave<S>>,
+) -> Chunk<S> {
+    if let Some(save) = save {
+        match save.load_chunk(chunk_pos, || generate_chunk(chunk_pos, generator)) {
+            Ok(Some(chunk)) => return chunk,
+            Ok(None) => {}
+            Err(error) => warn!("Regenerating chunk {:?}: {}", chunk_pos, error),
         }
-        MeshingChunk::new(chunks)
     }
+    generate_chunk(chunk_pos, generator)
 }
 
+/// How many voxels across each cell of a chunk's mesh is at the given
+/// distance from the nearest player: full resolution out to `lod_distance`,
+/// then halving in resolution each time the distance doubles.
+fn lod_scale(distance: i32, lod_distance: i32) -> u32 {
+    let mut scale = 1;
+    let mut reach = lod_distance.max(1);
+    while distance > reach && scale < MAX_LOD_SCALE {
+        scale *= 2;
+        reach *= 2;
+    }
+    scale
+}
+
+fn nearest_distance(chunk_pos: ChunkPosition, player_chunks: &[ChunkPosition]) -> i32 {
+    player_chunks
+        .iter()
+        .map(|player_chunk| chunk_pos.distance(*player_chunk))
+        .min()
+        .unwrap_or(i32::MAX)
+}
+
+/// Counts down to the next save of every edited chunk.
+#[derive(Resource)]
+struct Autosave(Timer);
+
 #[derive(Component)]
 struct MeshResultTask(Task<MeshResult>);
-struct MeshResult(UVec3, Mesh);
+struct MeshResult(ChunkPosition, ChunkMeshes);
 
-fn spawn_mesh_tasks(
+/// The mesh of a chunk's translucent voxels, which is drawn by a child of
+/// the chunk's entity so that it is sorted by its own position.
+#[derive(Component)]
+struct TranslucentMesh(Handle<Mesh>);
+
+/// The opaque and translucent meshes of a chunk that has been meshed before.
+type ChunkMeshHandles<'a> = (&'a Handle<Mesh>, &'a TranslucentMesh);
+
+/// Meshes are kept a little beyond the view distance, and voxel data a
+/// little beyond that, so that meshes never lose the neighbours they were
+/// built from.
+fn unload_distance(settings: &PlayerSettings) -> i32 {
+    settings.unload_distance.max(settings.view_distance + 1) as i32
+}
+
+fn unload_chunk_meshes(
     mut commands: Commands,
     settings: Res<PlayerSettings>,
     mut world: ResMut<World>,
     query: Query<&Transform, With<PlayerController>>,
 ) {
-    let thread_pool = AsyncComputeTaskPool::get();
+    let player_chunks: Vec<ChunkPosition> = query
+        .iter()
+        .map(|transform| World::chunk_pos(transform.translation))
+        .collect();
+    let unload_distance = unload_distance(&settings);
+
+    let world = &mut *world;
+    world.visible.retain(|pos, entity| {
+        let keep = nearest_distance(*pos, &player_chunks) <= unload_distance;
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+            world.dirty.remove(pos);
+            world.lods.remove(pos);
+        }
+        keep
+    });
+}
+
+fn evict_chunks(
+    settings: Res<PlayerSettings>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut world: ResMut<World>,
+    query: Query<&Transform, With<ChunkLoader>>,
+) {
+    let loader_chunks: Vec<ChunkPosition> = query
+        .iter()
+        .map(|transform| World::chunk_pos(transform.translation))
+        .collect();
+    let evict_distance = (settings.evict_distance as i32).max(unload_distance(&settings) + 1);
+    world.evict_chunks(&loader_chunks, evict_distance, &registry);
+}
 
-    for player_transform in &query {
-        let player_chunk = World::chunk_pos(player_transform.translation);
+fn handle_generation_tasks(mut world: ResMut<World>) {
+    world.finish_generation_tasks();
+}
+
+fn spawn_generation_tasks(
+    settings: Res<PlayerSettings>,
+    mut world: ResMut<World>,
+    query: Query<&Transform, With<ChunkLoader>>,
+) {
+    let loader_chunks: Vec<ChunkPosition> = query
+        .iter()
+        .map(|transform| World::chunk_pos(transform.translation))
+        .collect();
+    // meshes reach out to the view distance, and their padding one chunk
+    // further
+    let distance = settings.view_distance as i32 + 1;
+    let positions = world.chunks_to_generate(&loader_chunks, distance);
+    world.spawn_generation_tasks(positions);
+}
+
+fn spawn_mesh_tasks(
+    mut commands: Commands,
+    settings: Res<PlayerSettings>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut world: ResMut<World>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    let player_chunks: Vec<ChunkPosition> = query
+        .iter()
+        .map(|transform| World::chunk_pos(transform.translation))
+        .collect();
+    let lod_distance = settings.lod_distance as i32;
+    world.update_lods(&player_chunks, lod_distance);
+    for player_chunk in &player_chunks {
+        let player_chunk = *player_chunk;
         let dist = settings.view_distance as i32;
 
-        for x in -dist..dist {
-            for y in -dist..dist {
-                for z in -dist..dist {
-                    if let Some(pos) = world.bounded_add(player_chunk, IVec3::new(x, y, z)) {
-                        if !world.visible.contains(&pos) {
-                            let meshing_chunk = world.get_meshing_chunk(pos);
-                            let task = thread_pool
-                                .spawn(async move { MeshResult(pos, meshing_chunk.mesh()) });
-                            commands.spawn(MeshResultTask(task));
-                            world.visible.insert(pos);
-                        }
+        for x in -dist..=dist {
+            for y in -dist..=dist {
+                for z in -dist..=dist {
+                    let pos = player_chunk + IVec3::new(x, y, z);
+                    if world.visible.contains_key(&pos) {
+                        continue;
                     }
+                    // the mesher samples the six neighbouring chunks for
+                    // padding, so meshing waits until they are all generated
+                    let ready = Directions::all()
+                        .iter()
+                        .all(|direction| world.is_ready(pos + direction.to_vector()));
+                    if !ready || !world.light.contains_chunk(pos) {
+                        continue;
+                    }
+                    let distance = nearest_distance(pos, &player_chunks);
+                    world.set_lod(pos, lod_scale(distance, lod_distance));
+                    let entity = commands.spawn(world.spawn_mesh_task(pos, &registry)).id();
+                    world.visible.insert(pos, entity);
                 }
             }
         }
     }
 }
 
+fn mark_changed_materials_dirty(
+    mut events: EventReader<MaterialsChanged>,
+    mut world: ResMut<World>,
+) {
+    let world = &mut *world;
+    for event in events.read() {
+        for pos in world.visible.keys() {
+            let Some(chunk) = world.chunks.get_chunk(*pos) else {
+                continue;
+            };
+            if event.ids.iter().any(|id| chunk.contains_material(*id)) {
+                world.dirty.insert(*pos);
+            }
+        }
+    }
+}
+
+fn update_light(registry: Res<VoxelMaterialRegistry>, mut world: ResMut<World>) {
+    let max_lit_chunks = world.max_lit_chunks;
+    world.update_light_limited(&registry, max_lit_chunks);
+}
+
+fn remesh_dirty_chunks(
+    mut commands: Commands,
+    registry: Res<VoxelMaterialRegistry>,
+    mut world: ResMut<World>,
+) {
+    let dirty: Vec<ChunkPosition> = world.dirty.drain().collect();
+    for pos in dirty {
+        if let Some(entity) = world.visible.get(&pos) {
+            // the old mesh stays on the entity until the new one replaces it
+            commands
+                .entity(*entity)
+                .insert(world.spawn_mesh_task(pos, &registry));
+        }
+    }
+}
+
 fn handle_mesh_tasks(
     mut commands: Commands,
-    mut tasks: Query<(Entity, &mut MeshResultTask)>,
+    mut tasks: Query<(Entity, &mut MeshResultTask, Option<ChunkMeshHandles>)>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    textures: Res<VoxelTextures>,
 ) {
-    for (entity, mut task) in &mut tasks {
-        if let Some(MeshResult(chunk_pos, mesh)) = block_on(poll_once(&mut task.0)) {
-            let mut material = StandardMaterial::from(Color::rgb(0., 0., 0.));
-            material.perceptual_roughness = 0.9;
-            commands.spawn((
-                PbrBundle {
-                    mesh: meshes.add(mesh),
-                    material: materials.add(material),
-                    transform: Transform::from_translation(World::world_position(chunk_pos))
-                        .with_scale(Vec3::from_array([1.; 3])),
-                    // we scale the mesh so that it is approximately one meter per
-                    // voxel.
+    for (entity, mut task, old_meshes) in &mut tasks {
+        if let Some(MeshResult(chunk_pos, chunk_meshes)) = block_on(poll_once(&mut task.0)) {
+            let ChunkMeshes {
+                opaque,
+                mut translucent,
+            } = chunk_meshes;
+            // the translucent mesh is drawn from the center of the chunk, which
+            // is where chunks are sorted from when drawing them back to front
+            let center = Vec3::splat(CHUNK_DIM as f32 / 2. + 1.);
+            offset_positions(&mut translucent, -center);
+            // re-meshed chunks swap the new meshes into their existing assets,
+            // so the old ones are drawn right up until the new ones are ready
+            if let Some((old_mesh, old_translucent)) = old_meshes {
+                meshes.insert(old_mesh, opaque);
+                meshes.insert(&old_translucent.0, translucent);
+                commands.entity(entity).remove::<MeshResultTask>();
+                continue;
+            }
+            // mesh positions count from the padding around the chunk, a
+            // voxel before its first voxel
+            let translation = World::world_position(chunk_pos) - Vec3::ONE;
+            let translucent = meshes.add(translucent);
+            let child = commands
+                .spawn(MaterialMeshBundle {
+                    mesh: translucent.clone(),
+                    material: textures.translucent_material.clone(),
+                    transform: Transform::from_translation(center),
                     ..Default::default()
-                },
-                Wireframe,
-            ));
-            commands.entity(entity).remove::<MeshResultTask>();
+                })
+                .id();
+            // the chunk mesh replaces the task on the same entity, so that
+            // unloading the chunk despawns whichever one is present
+            commands
+                .entity(entity)
+                .remove::<MeshResultTask>()
+                .insert((
+                    MaterialMeshBundle {
+                        mesh: meshes.add(opaque),
+                        material: textures.material.clone(),
+                        transform: Transform::from_translation(translation)
+                            .with_scale(Vec3::from_array([1.; 3])),
+                        // we scale the mesh so that it is approximately one meter per
+                        // voxel.
+                        ..Default::default()
+                    },
+                    TranslucentMesh(translucent),
+                    Wireframe,
+                ))
+                .add_child(child);
+        }
+    }
+}
+
+/// Saved materials get their runtime ids before any chunks are loaded.
+fn reserve_saved_materials(world: Res<World>, mut registry: ResMut<VoxelMaterialRegistry>) {
+    if let Some(save) = world.save() {
+        if let Err(error) = save.reserve_materials(&mut registry) {
+            error!("Can't load saved materials: {}", error);
+        }
+    }
+}
+
+/// Puts the player back where they were when the world was saved.
+fn restore_player_position(
+    world: Res<World>,
+    mut query: Query<&mut Transform, With<PlayerController>>,
+) {
+    let Some(position) = world.save().and_then(|save| save.player_position()) else {
+        return;
+    };
+    for mut transform in &mut query {
+        transform.translation = position;
+    }
+}
+
+fn autosave(
+    time: Res<Time>,
+    mut timer: ResMut<Autosave>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut world: ResMut<World>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        let player_position = query.iter().next().map(|transform| transform.translation);
+        world.save_all(&registry, player_position);
+    }
+}
+
+fn save_on_exit(
+    mut exits: EventReader<AppExit>,
+    registry: Res<VoxelMaterialRegistry>,
+    mut world: ResMut<World>,
+    query: Query<&Transform, With<PlayerController>>,
+) {
+    if exits.read().next().is_some() {
+        let player_position = query.iter().next().map(|transform| transform.translation);
+        world.save_all(&registry, player_position);
+        // the app may stop as soon as this returns
+        world.flush_saves();
+    }
+}
+
+/// Moves every vertex of the mesh by the offset.
+fn offset_positions(mesh: &mut Mesh, offset: Vec3) {
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions {
+            *position = (Vec3::from(*position) + offset).to_array();
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::world::World;
+    use crate::material::{VoxelMaterialRegistry, DIRT, GRASS, LEAVES, LOG, SAND, SNOW, STONE};
+    use crate::player_controller::PlayerSettings;
+    use crate::save::WorldSave;
+    use crate::storage::chunk::ChunkPosition;
+    use crate::storage::map::VoxelMapSettings;
+    use crate::storage::palette::PaletteChunk;
+    use crate::voxel::{Voxel, EMPTY};
+    use crate::world::{lod_scale, ChunkLoader, World, WorldPlugin};
+    use bevy::prelude::*;
+    use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
 
     #[test]
     fn world_generation_succeeds() {
-        World::generate([4, 4, 4]);
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        for x in -2..2 {
+            for y in -2..2 {
+                for z in -2..2 {
+                    assert!(world.load_chunk(ChunkPosition::new(x, y, z)));
+                }
+            }
+        }
+        assert!(world.chunks.contains_chunk(ChunkPosition::new(-2, -2, -2)));
+        assert!(!world.chunks.contains_chunk(ChunkPosition::new(2, 2, 2)));
+    }
+
+    #[test]
+    fn nearest_chunks_are_generated_first() {
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        let player = ChunkPosition::new(4, -1, 3);
+        world.load_chunk(player);
+        let queue = world.chunks_to_generate(&[player], 2);
+        assert_eq!(queue.len(), 5 * 5 * 5 - 1);
+        assert!(!queue.contains(&player));
+        assert_eq!(queue[0].distance(player), 1);
+        let distances: Vec<i32> = queue
+            .iter()
+            .map(|pos| (pos.as_ivec3() - player.as_ivec3()).length_squared())
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn chunks_are_generated_in_the_background() {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let settings = VoxelMapSettings {
+            bounds: ChunkPosition::new(4, 0, 4),
+        };
+        let mut world = World::new(settings, |_| EMPTY);
+        world.max_generation_tasks = 3;
+        let player = ChunkPosition::new(0, 0, 0);
+        let queue = world.chunks_to_generate(&[player], 1);
+        // chunks above and below lie outside the bounds, so are never queued
+        assert_eq!(queue.len(), 9);
+        world.spawn_generation_tasks(queue.clone());
+        assert_eq!(world.generating.len(), 3);
+        while !world.generating.is_empty() {
+            world.finish_generation_tasks();
+            std::thread::yield_now();
+        }
+        for pos in &queue[..3] {
+            assert!(world.chunks.contains_chunk(*pos));
+        }
+        let remaining = world.chunks_to_generate(&[player], 1);
+        assert_eq!(remaining.len(), 6);
+        assert!(remaining.iter().all(|pos| queue[3..].contains(pos)));
+    }
+
+    #[test]
+    fn voxels_are_edited_in_world_coordinates() {
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        world.load_chunk(ChunkPosition::new(-1, 0, 0));
+        let position = IVec3::new(-1, 31, 0);
+        assert_eq!(world.get_voxel(position), Some(EMPTY));
+        assert!(world.set_voxel(position, Voxel::new(STONE)));
+        assert_eq!(world.get_voxel(position), Some(Voxel::new(STONE)));
+        let chunk = world
+            .chunks
+            .get_chunk(ChunkPosition::new(-1, 0, 0))
+            .unwrap();
+        assert_eq!(chunk.get([31, 31, 0]), Voxel::new(STONE));
+        // voxels in chunks that aren't loaded can't be edited
+        assert_eq!(world.get_voxel(IVec3::new(0, 31, 0)), None);
+        assert!(!world.set_voxel(IVec3::new(0, 31, 0), Voxel::new(STONE)));
+        let edits = [
+            (IVec3::new(-5, 2, 3), Voxel::new(STONE)),
+            (IVec3::new(-5, -2, 3), Voxel::new(STONE)),
+        ];
+        assert_eq!(world.set_voxels(edits), 1);
+    }
+
+    #[test]
+    fn edits_re_mesh_neighbours_sharing_the_voxel() {
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let pos = ChunkPosition::new(x, y, z);
+                    world.load_chunk(pos);
+                    world.visible.insert(pos, Entity::PLACEHOLDER);
+                }
+            }
+        }
+        // a voxel inside the chunk is only in the chunk's own mesh
+        world.set_voxel(IVec3::new(5, 5, 5), Voxel::new(STONE));
+        let dirty: Vec<ChunkPosition> = world.dirty.drain().collect();
+        assert_eq!(dirty, [ChunkPosition::new(0, 0, 0)]);
+        // a voxel on the low x and high z sides is also in the padding of
+        // the chunks on those sides
+        world.set_voxel(IVec3::new(0, 5, 31), Voxel::new(STONE));
+        assert_eq!(world.dirty.len(), 3);
+        assert!(world.dirty.contains(&ChunkPosition::new(0, 0, 0)));
+        assert!(world.dirty.contains(&ChunkPosition::new(-1, 0, 0)));
+        assert!(world.dirty.contains(&ChunkPosition::new(0, 0, 1)));
+        // setting a voxel to what it already is changes nothing
+        world.dirty.clear();
+        world.set_voxel(IVec3::new(0, 5, 31), Voxel::new(STONE));
+        assert!(world.dirty.is_empty());
+    }
+
+    #[test]
+    fn edits_relight_and_re_mesh_chunks() {
+        let registry = VoxelMaterialRegistry::default();
+        let ground = |position: IVec3| {
+            if position.y < 0 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::new(VoxelMapSettings::default(), ground);
+        let above = ChunkPosition::new(0, 0, 0);
+        let below = ChunkPosition::new(0, -1, 0);
+        world.load_chunk(above);
+        world.load_chunk(below);
+        world.visible.insert(below, Entity::PLACEHOLDER);
+        assert_eq!(world.get_light(IVec3::new(5, 5, 5)), None);
+        world.update_light(&registry);
+        assert_eq!(world.get_light(IVec3::new(5, 5, 5)).unwrap().sky, 15);
+        assert_eq!(world.get_light(IVec3::new(5, -1, 5)).unwrap().sky, 0);
+        // digging into the ground lets the sky in
+        world.dirty.clear();
+        world.set_voxel(IVec3::new(5, -1, 5), EMPTY);
+        world.update_light(&registry);
+        assert_eq!(world.get_light(IVec3::new(5, -1, 5)).unwrap().sky, 15);
+        assert!(world.dirty.contains(&below));
+    }
+
+    #[test]
+    fn chunks_are_lit_a_few_at_a_time() {
+        let registry = VoxelMaterialRegistry::default();
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        for x in 0..5 {
+            world.load_chunk(ChunkPosition::new(x, 0, 0));
+        }
+        world.update_light_limited(&registry, 2);
+        assert!(world.get_light(IVec3::new(5, 5, 5)).is_some());
+        assert!(world.get_light(IVec3::new(37, 5, 5)).is_some());
+        assert_eq!(world.get_light(IVec3::new(69, 5, 5)), None);
+        world.update_light(&registry);
+        assert!(world.get_light(IVec3::new(133, 5, 5)).is_some());
+    }
+
+    #[test]
+    fn distant_chunks_are_meshed_at_lower_detail() {
+        let scales = [0, 2, 3, 4, 5, 8, 9, 100].map(|distance| lod_scale(distance, 2));
+        assert_eq!(scales, [1, 1, 2, 2, 4, 4, 8, 8]);
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        let near = ChunkPosition::new(2, 0, 0);
+        let far = ChunkPosition::new(3, 0, 0);
+        for pos in [near, far] {
+            world.visible.insert(pos, Entity::PLACEHOLDER);
+        }
+        world.update_lods(&[ChunkPosition::new(0, 0, 0)], 2);
+        assert_eq!((world.lods[&near], world.lods[&far]), (1, 2));
+        // the full resolution chunk only skirts the side facing the other
+        assert_eq!(
+            world.skirts(near),
+            [true, false, false, false, false, false]
+        );
+        assert_eq!(world.skirts(far), [true; 6]);
+        // moving away only re-meshes the chunk whose detail changed
+        world.dirty.clear();
+        world.update_lods(&[ChunkPosition::new(-1, 0, 0)], 2);
+        assert_eq!((world.lods[&near], world.lods[&far]), (2, 2));
+        assert_eq!(world.dirty.iter().collect::<Vec<_>>(), [&near]);
+        // by default, the furthest chunks players see are at lower detail
+        let settings = PlayerSettings::default();
+        assert!(lod_scale(settings.view_distance as i32, settings.lod_distance as i32) > 1);
+    }
+
+    #[test]
+    fn chunk_positions_extend_into_negative_space() {
+        assert_eq!(World::chunk_pos(Vec3::ZERO), ChunkPosition::new(0, 0, 0));
+        assert_eq!(
+            World::chunk_pos(Vec3::new(-0.5, -33., 31.9)),
+            ChunkPosition::new(-1, -2, 0)
+        );
+        let pos = ChunkPosition::new(-3, 1, -7);
+        assert_eq!(World::chunk_pos(World::world_position(pos)), pos);
+    }
+
+    #[test]
+    fn far_chunks_are_evicted() {
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY);
+        let near = ChunkPosition::new(-2, 0, 2);
+        let far = ChunkPosition::new(0, 4, 0);
+        let player = ChunkPosition::new(-1, 1, 0);
+        world.load_chunk(near);
+        world.load_chunk(far);
+        let registry = VoxelMaterialRegistry::default();
+        world.evict_chunks(&[player], 2, &registry);
+        assert!(world.chunks.contains_chunk(near));
+        assert!(!world.chunks.contains_chunk(far));
+        world.evict_chunks(&[], 2, &registry);
+        assert!(!world.chunks.contains_chunk(near));
+    }
+
+    #[test]
+    fn evicted_chunks_are_loaded_from_the_save() {
+        let dir = std::env::temp_dir().join(format!("voxelite-world-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let save = WorldSave::open(&dir, 1).unwrap();
+        save.reserve_materials(&mut registry).unwrap();
+        let mut world = World::new(VoxelMapSettings::default(), |_| EMPTY).with_save(save);
+        let pos = ChunkPosition::new(0, 0, 0);
+        world.load_chunk(pos);
+        world.load_chunk(ChunkPosition::new(1, 0, 0));
+        // generated chunks can be generated again, so aren't saved
+        assert!(world.unsaved.is_empty());
+        world.set_voxel(IVec3::new(3, 4, 5), Voxel::new(STONE));
+        world.evict_chunks(&[], 2, &registry);
+        assert!(world.unsaved.is_empty());
+        world.flush_saves();
+        let regions = std::fs::read_dir(dir.join("regions")).unwrap().count();
+        assert_eq!(regions, 1);
+        // the edit would be lost if the chunk were generated again
+        world.load_chunk(pos);
+        assert_eq!(
+            world.get_voxel(IVec3::new(3, 4, 5)),
+            Some(Voxel::new(STONE))
+        );
+        assert!(!world.unsaved.contains(&pos));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn palette_worlds_are_edited_lit_and_saved() {
+        let dir = std::env::temp_dir().join(format!("voxelite-palette-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut registry = VoxelMaterialRegistry::default();
+        let save = WorldSave::open(&dir, 1).unwrap();
+        save.reserve_materials(&mut registry).unwrap();
+        let ground = |position: IVec3| {
+            if position.y < 0 {
+                Voxel::new(STONE)
+            } else {
+                EMPTY
+            }
+        };
+        let mut world = World::<PaletteChunk>::with_storage(VoxelMapSettings::default(), ground)
+            .with_save(save);
+        let below = ChunkPosition::new(0, -1, 0);
+        world.load_chunk(ChunkPosition::new(0, 0, 0));
+        world.load_chunk(below);
+        // enough materials that the palette has to grow
+        let materials = [STONE, DIRT, GRASS, SAND, SNOW, LOG, LEAVES];
+        let edits = (0..20).map(|x| (IVec3::new(x, -1, 3), Voxel::new(materials[x as usize % 7])));
+        assert_eq!(world.set_voxels(edits), 20);
+        world.set_voxel(IVec3::new(5, -1, 5), EMPTY);
+        world.update_light(&registry);
+        assert_eq!(world.get_light(IVec3::new(5, -1, 5)).unwrap().sky, 15);
+
+        world.evict_chunks(&[], 2, &registry);
+        world.flush_saves();
+        world.load_chunk(below);
+        for x in 0..20 {
+            let voxel = Voxel::new(materials[x as usize % 7]);
+            assert_eq!(world.get_voxel(IVec3::new(x, -1, 3)), Some(voxel));
+        }
+        assert_eq!(world.get_voxel(IVec3::new(5, -1, 5)), Some(EMPTY));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_outside_bounds_are_not_created() {
+        let settings = VoxelMapSettings {
+            bounds: ChunkPosition::new(1, 1, 1),
+        };
+        let mut world = World::new(settings, |_| EMPTY);
+        assert!(world.load_chunk(ChunkPosition::new(-1, 1, 0)));
+        assert!(!world.load_chunk(ChunkPosition::new(0, -2, 0)));
+        assert!(world.chunks.contains_chunk(ChunkPosition::new(-1, 1, 0)));
+        assert!(!world.chunks.contains_chunk(ChunkPosition::new(0, -2, 0)));
+    }
+
+    #[test]
+    fn headless_worlds_load_chunks_around_chunk_loaders() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            WorldPlugin {
+                meshing: false,
+                ..default()
+            },
+        ))
+        .init_resource::<VoxelMaterialRegistry>();
+        let spawn = Vec3::new(100., 10., -40.);
+        app.world
+            .spawn((Transform::from_translation(spawn), ChunkLoader));
+        let spawn_chunk = World::chunk_pos(spawn);
+        for _ in 0..1000 {
+            app.update();
+            if app.world.resource::<World>().chunks.contains_chunk(spawn_chunk) {
+                break;
+            }
+            std::thread::yield_now();
+        }
+        let world = app.world.resource::<World>();
+        assert!(world.chunks.contains_chunk(spawn_chunk));
+        assert!(!world.chunks.contains_chunk(ChunkPosition::new(0, 0, 0)));
+        assert!(world.visible.is_empty());
     }
 }